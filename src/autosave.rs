@@ -0,0 +1,116 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// The Emacs style auto-save file for path: "dir/#name#".
+pub fn auto_save_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!("#{}#", name))
+}
+
+/// True when path has an auto-save file that was written after path itself was last
+/// modified, which means an earlier session ended before saving.
+pub fn has_newer_auto_save(path: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|meta| meta.modified());
+    match (modified(&auto_save_path(path)), modified(path)) {
+        (Ok(auto_saved), Ok(saved)) => auto_saved > saved,
+        (Ok(_), Err(_)) => true,
+        _ => false,
+    }
+}
+
+/// Decides when dirty buffers get written to their auto-save files: after keystrokes
+/// key presses, or once interval has passed since the last auto-save.
+#[derive(Debug, Clone)]
+pub struct AutoSave {
+    interval: Duration,
+    keystrokes: usize,
+    count: usize,
+    last: Instant,
+}
+
+impl Default for AutoSave {
+    fn default() -> Self {
+        AutoSave::new(Duration::from_secs(30), 300)
+    }
+}
+
+impl AutoSave {
+    pub fn new(interval: Duration, keystrokes: usize) -> Self {
+        AutoSave {
+            interval,
+            keystrokes,
+            count: 0,
+            last: Instant::now(),
+        }
+    }
+
+    /// Counts a key press. Returns true when that makes an auto-save due.
+    pub fn keystroke(&mut self) -> bool {
+        self.count += 1;
+        self.due()
+    }
+
+    pub fn due(&self) -> bool {
+        self.count >= self.keystrokes || self.time_left().is_zero()
+    }
+
+    /// How long the event loop can wait for a key before the timer runs out.
+    pub fn time_left(&self) -> Duration {
+        self.interval.saturating_sub(self.last.elapsed())
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.last = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn test_auto_save_path() {
+        assert_eq!(
+            auto_save_path(Path::new("src/main.rs")),
+            PathBuf::from("src/#main.rs#")
+        );
+        assert_eq!(auto_save_path(Path::new("notes")), PathBuf::from("#notes#"));
+    }
+
+    #[test]
+    fn test_auto_save_due() {
+        let mut timer = AutoSave::new(Duration::from_secs(60), 3);
+        assert!(!timer.keystroke());
+        assert!(!timer.keystroke());
+        assert!(timer.keystroke());
+        timer.reset();
+        assert!(!timer.due());
+
+        let expired = AutoSave::new(Duration::ZERO, 300);
+        assert!(expired.due());
+        assert_eq!(expired.time_left(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_newer_auto_save() {
+        let dir = scratch_dir("autosave");
+        let path = dir.join("draft.txt");
+        std::fs::write(&path, "old").unwrap();
+        assert!(!has_newer_auto_save(&path));
+
+        let file = std::fs::File::create(auto_save_path(&path)).unwrap();
+        let later = std::fs::metadata(&path).unwrap().modified().unwrap() + Duration::from_secs(5);
+        file.set_modified(later).unwrap();
+        assert!(has_newer_auto_save(&path));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
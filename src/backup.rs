@@ -0,0 +1,199 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+/// Whether backups are "file~" or numbered "file.~N~", the same choices as Emacs'
+/// version-control variable.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum VersionControl {
+    /// Numbered when numbered backups of the file already exist, otherwise "file~".
+    #[default]
+    Existing,
+    Numbered,
+    Never,
+}
+
+impl std::str::FromStr for VersionControl {
+    type Err = std::string::String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "existing" | "nil" => Ok(VersionControl::Existing),
+            "numbered" | "t" => Ok(VersionControl::Numbered),
+            "never" => Ok(VersionControl::Never),
+            _ => Err(format!("Unknown version control setting: {}", s)),
+        }
+    }
+}
+
+/// Where and how the copy of a file's previous contents is kept on the first save of
+/// a session. With a directory set, every backup goes there under the full path of
+/// the file with "/" written as "!", so files with the same name don't collide.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BackupConfig {
+    pub enabled: bool,
+    pub version_control: VersionControl,
+    pub directory: Option<PathBuf>,
+}
+
+impl BackupConfig {
+    /// Backups are on by default. MR_TEXT_BACKUP_DIR sets a central backup directory
+    /// and MR_TEXT_VERSION_CONTROL is one of existing, numbered or never.
+    pub fn from_env() -> Self {
+        let version_control = std::env::var("MR_TEXT_VERSION_CONTROL")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default();
+        BackupConfig {
+            enabled: std::env::var_os("MR_TEXT_NO_BACKUP").is_none(),
+            version_control,
+            directory: std::env::var_os("MR_TEXT_BACKUP_DIR")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from),
+        }
+    }
+
+    /// The directory backups of path go to and the file name they start with.
+    fn location(&self, path: &Path) -> (PathBuf, std::string::String) {
+        match &self.directory {
+            Some(dir) => {
+                let absolute = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+                (dir.clone(), absolute.to_string_lossy().replace('/', "!"))
+            }
+            None => {
+                let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                (dir, name)
+            }
+        }
+    }
+
+    /// The path the next backup of path is written to.
+    pub fn backup_path(&self, path: &Path) -> PathBuf {
+        let (dir, name) = self.location(path);
+        let highest = highest_version(&dir, &name);
+        let numbered = match self.version_control {
+            VersionControl::Numbered => true,
+            VersionControl::Existing => highest > 0,
+            VersionControl::Never => false,
+        };
+        if numbered {
+            dir.join(format!("{}.~{}~", name, highest + 1))
+        } else {
+            dir.join(format!("{}~", name))
+        }
+    }
+
+    /// Copies path to its backup. Returns the backup written, or None when backups are
+    /// off or there is no file to back up yet.
+    pub fn make_backup(&self, path: &Path) -> std::io::Result<Option<PathBuf>> {
+        if !self.enabled || !path.is_file() {
+            return Ok(None);
+        }
+        if let Some(dir) = &self.directory {
+            std::fs::create_dir_all(dir)?;
+        }
+        let backup = self.backup_path(path);
+        std::fs::copy(path, &backup)?;
+        Ok(Some(backup))
+    }
+}
+
+/// The largest N of the "name.~N~" files in dir, or 0 when there are none.
+fn highest_version(dir: &Path, name: &str) -> usize {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let prefix = format!("{}.~", name);
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter_map(|file| {
+            file.strip_prefix(&prefix)?
+                .strip_suffix('~')?
+                .parse::<usize>()
+                .ok()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn test_simple_backup() {
+        let dir = scratch_dir("backup-simple");
+        let path = dir.join("notes.txt");
+        let config = BackupConfig {
+            enabled: true,
+            ..BackupConfig::default()
+        };
+        assert_eq!(config.make_backup(&path).unwrap(), None);
+
+        std::fs::write(&path, "v1").unwrap();
+        let backup = config.make_backup(&path).unwrap().unwrap();
+        assert_eq!(backup, dir.join("notes.txt~"));
+        assert_eq!(std::fs::read_to_string(backup).unwrap(), "v1");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_numbered_backups() {
+        let dir = scratch_dir("backup-numbered");
+        let path = dir.join("main.rs");
+        std::fs::write(&path, "fn main() {}").unwrap();
+        let mut config = BackupConfig {
+            enabled: true,
+            version_control: VersionControl::Numbered,
+            directory: None,
+        };
+        assert_eq!(
+            config.make_backup(&path).unwrap().unwrap(),
+            dir.join("main.rs.~1~")
+        );
+        assert_eq!(
+            config.make_backup(&path).unwrap().unwrap(),
+            dir.join("main.rs.~2~")
+        );
+
+        // Existing switches to numbered backups once there are some.
+        config.version_control = VersionControl::Existing;
+        assert_eq!(config.backup_path(&path), dir.join("main.rs.~3~"));
+        config.version_control = VersionControl::Never;
+        assert_eq!(config.backup_path(&path), dir.join("main.rs~"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_backup_directory() {
+        let dir = scratch_dir("backup-central");
+        let path = dir.join("src").join("lib.rs");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "pub mod a;").unwrap();
+        let store = dir.join("backups");
+        let config = BackupConfig {
+            enabled: true,
+            version_control: VersionControl::Existing,
+            directory: Some(store.clone()),
+        };
+
+        let backup = config.make_backup(&path).unwrap().unwrap();
+        let expected = std::fs::canonicalize(&path)
+            .unwrap()
+            .to_string_lossy()
+            .replace('/', "!");
+        assert_eq!(backup, store.join(format!("{}~", expected)));
+        assert_eq!(std::fs::read_to_string(backup).unwrap(), "pub mod a;");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
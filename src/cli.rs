@@ -0,0 +1,325 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use std::path::Path;
+
+pub const USAGE: &str = "\
+Usage: mr_text [OPTIONS] [[+LINE[:COL]] FILE]...
+
+Open each FILE in its own buffer. FILE may also be written as FILE:LINE[:COL],
+the way compilers print locations, and \"-\" reads standard input into a buffer.
+
+Options:
+  +LINE[:COL]   Start the next FILE at LINE and COL (both 1-based).
+  --readonly    Open every buffer read-only.
+  --version     Print the version and exit.
+  --help        Print this message and exit.
+  --            Treat every following argument as a FILE.
+
+Environment:
+  MR_TEXT_BACKUP_DIR       Keep backups of saved files in this directory.
+  MR_TEXT_VERSION_CONTROL  existing, numbered or never: when to use FILE.~N~.
+  MR_TEXT_NO_BACKUP        Don't back up files on their first save.
+  MR_TEXT_THEME            Read colours from this theme file.
+  MR_TEXT_LINE_NUMBERS     absolute, relative (the default), hybrid or off.
+  MR_TEXT_WHITESPACE_GLYPHS
+                           Glyphs for M-x whitespace-mode, as tab=»,space=·,nbsp=¤,eol=¬.
+  MR_TEXT_WHITESPACE_CLEANUP
+                           Delete trailing whitespace and add a final newline on save.
+  MR_TEXT_MODE_LINE        Mode line format: %b buffer, %* read-only and modified
+                           flags, %m mode, %l line, %c column, %p percent, %z encoding,
+                           %E line endings, %g git branch, %% a literal %.
+  COLORTERM, TERM          truecolor or a 256color TERM allow more colours.
+";
+
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Edit,
+    Help,
+    Version,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Source {
+    Path(std::string::String),
+    Stdin,
+}
+
+/// A file named on the command line together with where to put point.
+#[derive(Debug, PartialEq)]
+pub struct FileArg {
+    pub source: Source,
+    pub line: Option<usize>,
+    pub col: Option<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Cli {
+    pub action: Action,
+    pub readonly: bool,
+    pub files: Vec<FileArg>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    UnknownOption(std::string::String),
+    BadPosition(std::string::String),
+    DanglingPosition(std::string::String),
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::UnknownOption(arg) => write!(f, "unknown option '{}'", arg),
+            CliError::BadPosition(arg) => write!(f, "invalid position '{}'", arg),
+            CliError::DanglingPosition(arg) => write!(f, "'{}' is not followed by a file", arg),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// Parses the arguments after the program name.
+pub fn parse_args<I>(args: I) -> Result<Cli, CliError>
+where
+    I: IntoIterator<Item = std::string::String>,
+{
+    parse_args_with(args, |path| Path::new(path).exists())
+}
+
+/// Same as parse_args, with the check for whether a literal path exists passed in so
+/// FILE:LINE:COL splitting can be tested without touching the file system.
+pub fn parse_args_with<I, F>(args: I, exists: F) -> Result<Cli, CliError>
+where
+    I: IntoIterator<Item = std::string::String>,
+    F: Fn(&str) -> bool,
+{
+    let mut cli = Cli {
+        action: Action::Edit,
+        readonly: false,
+        files: Vec::new(),
+    };
+    let mut position: Option<(std::string::String, usize, Option<usize>)> = None;
+    let mut only_files = false;
+
+    for arg in args {
+        if !only_files {
+            match arg.as_str() {
+                "--" => {
+                    only_files = true;
+                    continue;
+                }
+                "--help" | "-h" => {
+                    cli.action = Action::Help;
+                    continue;
+                }
+                "--version" | "-V" => {
+                    cli.action = Action::Version;
+                    continue;
+                }
+                "--readonly" => {
+                    cli.readonly = true;
+                    continue;
+                }
+                "-" => {
+                    let (line, col) = take_position(&mut position);
+                    cli.files.push(FileArg {
+                        source: Source::Stdin,
+                        line,
+                        col,
+                    });
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(spec) = arg.strip_prefix('+') {
+                let (line, col) =
+                    parse_line_col(spec).ok_or_else(|| CliError::BadPosition(arg.clone()))?;
+                position = Some((arg, line, col));
+                continue;
+            }
+            if arg.starts_with('-') {
+                return Err(CliError::UnknownOption(arg));
+            }
+        }
+
+        let (path, line, col) = match take_position(&mut position) {
+            (Some(line), col) => (arg, Some(line), col),
+            (None, _) => split_location(arg, &exists),
+        };
+        cli.files.push(FileArg {
+            source: Source::Path(path),
+            line,
+            col,
+        });
+    }
+
+    match position {
+        Some((arg, ..)) if cli.action == Action::Edit => Err(CliError::DanglingPosition(arg)),
+        _ => Ok(cli),
+    }
+}
+
+fn take_position(
+    position: &mut Option<(std::string::String, usize, Option<usize>)>,
+) -> (Option<usize>, Option<usize>) {
+    match position.take() {
+        Some((_, line, col)) => (Some(line), col),
+        None => (None, None),
+    }
+}
+
+/// Parses "LINE" or "LINE:COL". Both numbers must be at least 1.
+fn parse_line_col(spec: &str) -> Option<(usize, Option<usize>)> {
+    let (line, col) = match spec.split_once(':') {
+        Some((line, col)) => (line, Some(col)),
+        None => (spec, None),
+    };
+    let line = line.parse::<usize>().ok().filter(|line| *line > 0)?;
+    let col = match col {
+        Some(col) => Some(col.parse::<usize>().ok().filter(|col| *col > 0)?),
+        None => None,
+    };
+    Some((line, col))
+}
+
+/// Splits "path:LINE[:COL][:]" as printed by compilers. A path that exists as
+/// written is never split, so files with colons in their names still open.
+fn split_location<F>(
+    arg: std::string::String,
+    exists: &F,
+) -> (std::string::String, Option<usize>, Option<usize>)
+where
+    F: Fn(&str) -> bool,
+{
+    if exists(&arg) {
+        return (arg, None, None);
+    }
+    let trimmed = arg.strip_suffix(':').unwrap_or(&arg);
+    let mut numbers = Vec::with_capacity(2);
+    let mut rest = trimmed;
+    while numbers.len() < 2 {
+        match rest.rsplit_once(':') {
+            Some((head, tail)) if !tail.is_empty() && tail.bytes().all(|b| b.is_ascii_digit()) => {
+                numbers.push(tail);
+                rest = head;
+            }
+            _ => break,
+        }
+    }
+    if rest.is_empty() || numbers.is_empty() {
+        return (arg, None, None);
+    }
+    let spec = numbers.iter().rev().copied().collect::<Vec<_>>().join(":");
+    match parse_line_col(&spec) {
+        Some((line, col)) => (rest.to_string(), Some(line), col),
+        None => (arg, None, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, CliError> {
+        parse_args_with(args.iter().map(|arg| arg.to_string()), |path| {
+            path == "odd:1"
+        })
+    }
+
+    fn file(path: &str, line: Option<usize>, col: Option<usize>) -> FileArg {
+        FileArg {
+            source: Source::Path(path.to_string()),
+            line,
+            col,
+        }
+    }
+
+    #[test]
+    fn test_plain_files() {
+        let cli = parse(&["a.rs", "b.rs"]).unwrap();
+        assert_eq!(cli.action, Action::Edit);
+        assert!(!cli.readonly);
+        assert_eq!(
+            cli.files,
+            vec![file("a.rs", None, None), file("b.rs", None, None)]
+        );
+        assert!(parse(&[]).unwrap().files.is_empty());
+    }
+
+    #[test]
+    fn test_plus_position() {
+        let cli = parse(&["+12", "a.rs", "+3:7", "b.rs", "c.rs"]).unwrap();
+        assert_eq!(
+            cli.files,
+            vec![
+                file("a.rs", Some(12), None),
+                file("b.rs", Some(3), Some(7)),
+                file("c.rs", None, None),
+            ]
+        );
+        assert_eq!(
+            parse(&["+0", "a.rs"]),
+            Err(CliError::BadPosition("+0".to_string()))
+        );
+        assert_eq!(
+            parse(&["+x", "a.rs"]),
+            Err(CliError::BadPosition("+x".to_string()))
+        );
+        assert_eq!(
+            parse(&["a.rs", "+4"]),
+            Err(CliError::DanglingPosition("+4".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_compiler_locations() {
+        let cli = parse(&["src/main.rs:10:5", "lib.rs:42", "x.rs:8:2:", "odd:1", "a:b"]).unwrap();
+        assert_eq!(
+            cli.files,
+            vec![
+                file("src/main.rs", Some(10), Some(5)),
+                file("lib.rs", Some(42), None),
+                file("x.rs", Some(8), Some(2)),
+                file("odd:1", None, None),
+                file("a:b", None, None),
+            ]
+        );
+        // An explicit +LINE wins over anything that looks like a location.
+        assert_eq!(
+            parse(&["+2", "y.rs:9"]).unwrap().files,
+            vec![file("y.rs:9", Some(2), None)]
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        let cli = parse(&["--readonly", "-", "+5", "-"]).unwrap();
+        assert!(cli.readonly);
+        assert_eq!(
+            cli.files,
+            vec![
+                FileArg {
+                    source: Source::Stdin,
+                    line: None,
+                    col: None
+                },
+                FileArg {
+                    source: Source::Stdin,
+                    line: Some(5),
+                    col: None
+                },
+            ]
+        );
+        assert_eq!(parse(&["--version"]).unwrap().action, Action::Version);
+        assert_eq!(parse(&["a.rs", "--help"]).unwrap().action, Action::Help);
+        assert_eq!(
+            parse(&["--bogus"]),
+            Err(CliError::UnknownOption("--bogus".to_string()))
+        );
+        assert_eq!(
+            parse(&["--", "--help"]).unwrap().files,
+            vec![file("--help", None, None)]
+        );
+    }
+}
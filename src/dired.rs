@@ -0,0 +1,329 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use crate::ffi;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Lines above the first entry of a listing: the directory name and a blank line.
+pub const HEADER_LINES: usize = 2;
+
+/// One file of a directory listing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub name: std::string::String,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    pub len: u64,
+    pub mode: u32,
+    pub modified: Option<SystemTime>,
+    /// Flagged for deletion with "d".
+    pub marked: bool,
+}
+
+impl Entry {
+    fn read(dir: &Path, name: &str) -> std::io::Result<Entry> {
+        let path = dir.join(name);
+        let link = std::fs::symlink_metadata(&path)?;
+        // Symlinks are shown as what they point to, unless that is gone.
+        let meta = std::fs::metadata(&path).unwrap_or_else(|_| link.clone());
+        Ok(Entry {
+            name: name.to_string(),
+            is_dir: meta.is_dir(),
+            is_symlink: link.file_type().is_symlink(),
+            len: meta.len(),
+            mode: meta.permissions().mode(),
+            modified: meta.modified().ok(),
+            marked: false,
+        })
+    }
+
+    /// "D  drwxr-xr-x       4096 2026-10-18 09:41 src/" with the D only when marked.
+    pub fn format(&self) -> std::string::String {
+        let time = self.modified.map(format_time).unwrap_or_default();
+        format!(
+            "{}  {} {:>10} {:16} {}{}",
+            if self.marked { 'D' } else { ' ' },
+            mode_string(self.mode, self.is_dir, self.is_symlink),
+            self.len,
+            time,
+            self.name,
+            if self.is_dir { "/" } else { "" }
+        )
+    }
+}
+
+/// A directory as shown in a dired buffer. Every operation goes straight to the file
+/// system and is followed by refresh, so the listing always shows what is on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dired {
+    dir: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl Dired {
+    pub fn read(dir: &Path) -> std::io::Result<Dired> {
+        let mut dired = Dired {
+            dir: dir.to_path_buf(),
+            entries: Vec::new(),
+        };
+        dired.refresh()?;
+        Ok(dired)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Reads the directory again. Deletion marks stay on files that are still there.
+    pub fn refresh(&mut self) -> std::io::Result<()> {
+        let mut names = vec!["..".to_string()];
+        for entry in std::fs::read_dir(&self.dir)? {
+            names.push(entry?.file_name().to_string_lossy().to_string());
+        }
+        names[1..].sort();
+        let mut entries = Vec::with_capacity(names.len());
+        for name in names {
+            // A file removed between read_dir and here is simply left out.
+            if let Ok(mut entry) = Entry::read(&self.dir, &name) {
+                entry.marked = self
+                    .entries
+                    .iter()
+                    .any(|old| old.name == name && old.marked);
+                entries.push(entry);
+            }
+        }
+        self.entries = entries;
+        Ok(())
+    }
+
+    /// The text of the buffer: a header followed by one line per entry.
+    pub fn listing(&self) -> std::string::String {
+        let mut text = format!("  {}:\n\n", self.dir.display());
+        for entry in &self.entries {
+            text.push_str(&entry.format());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// The entry shown on line of the listing.
+    pub fn entry_at_line(&self, line: usize) -> Option<&Entry> {
+        self.entries.get(line.checked_sub(HEADER_LINES)?)
+    }
+
+    pub fn path_of(&self, entry: &Entry) -> PathBuf {
+        self.dir.join(&entry.name)
+    }
+
+    /// Sets the deletion mark of the entry on line. ".." can't be marked.
+    pub fn set_mark(&mut self, line: usize, marked: bool) -> bool {
+        match line
+            .checked_sub(HEADER_LINES)
+            .and_then(|idx| self.entries.get_mut(idx))
+        {
+            Some(entry) if entry.name != ".." => {
+                entry.marked = marked;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn marked(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().filter(|entry| entry.marked)
+    }
+
+    /// Deletes every marked entry. Directories are only removed when empty. Stops at
+    /// the first failure; what was deleted before it stays deleted.
+    pub fn delete_marked(&mut self) -> std::io::Result<usize> {
+        let paths: Vec<(PathBuf, bool)> = self
+            .marked()
+            .map(|entry| (self.path_of(entry), entry.is_dir && !entry.is_symlink))
+            .collect();
+        let mut deleted = 0;
+        let result = paths.iter().try_for_each(|(path, is_dir)| {
+            if *is_dir {
+                std::fs::remove_dir(path)?;
+            } else {
+                std::fs::remove_file(path)?;
+            }
+            deleted += 1;
+            Ok(())
+        });
+        self.refresh()?;
+        result.map(|()| deleted)
+    }
+
+    /// Where to, relative to the listed directory. An existing directory as target
+    /// means into that directory under the same name.
+    fn target(&self, entry: &Entry, to: &str) -> PathBuf {
+        let target = self.dir.join(to);
+        if target.is_dir() {
+            target.join(&entry.name)
+        } else {
+            target
+        }
+    }
+
+    /// Renames the entry on line to to. Returns the new path.
+    pub fn rename(&mut self, line: usize, to: &str) -> std::io::Result<PathBuf> {
+        let entry = self.entry_for_op(line)?;
+        let target = self.target(&entry, to);
+        std::fs::rename(self.path_of(&entry), &target)?;
+        self.refresh()?;
+        Ok(target)
+    }
+
+    /// Copies the file on line to to. Directories are not copied.
+    pub fn copy(&mut self, line: usize, to: &str) -> std::io::Result<PathBuf> {
+        let entry = self.entry_for_op(line)?;
+        if entry.is_dir {
+            return Err(std::io::Error::other(
+                "Copying directories is not supported",
+            ));
+        }
+        let target = self.target(&entry, to);
+        std::fs::copy(self.path_of(&entry), &target)?;
+        self.refresh()?;
+        Ok(target)
+    }
+
+    pub fn create_dir(&mut self, name: &str) -> std::io::Result<PathBuf> {
+        let path = self.dir.join(name);
+        std::fs::create_dir_all(&path)?;
+        self.refresh()?;
+        Ok(path)
+    }
+
+    fn entry_for_op(&self, line: usize) -> std::io::Result<Entry> {
+        match self.entry_at_line(line) {
+            Some(entry) if entry.name != ".." => Ok(entry.clone()),
+            _ => Err(std::io::Error::other("No file on this line")),
+        }
+    }
+}
+
+/// "drwxr-xr-x" from mode bits, with "l" for symlinks.
+pub fn mode_string(mode: u32, is_dir: bool, is_symlink: bool) -> std::string::String {
+    let kind = if is_symlink {
+        'l'
+    } else if is_dir {
+        'd'
+    } else {
+        '-'
+    };
+    let mut text = std::string::String::from(kind);
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        text.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        text.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        text.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    text
+}
+
+/// "YYYY-MM-DD HH:MM" in local time, the same clock the *Messages* log uses. Blank
+/// when the C library can't convert the time.
+pub fn format_time(time: SystemTime) -> std::string::String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    };
+    match ffi::local_time(secs) {
+        Some(local) => format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            local.year, local.month, local.day, local.hour, local.min
+        ),
+        None => " ".repeat(16),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    fn names(dired: &Dired) -> Vec<&str> {
+        dired
+            .entries()
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_listing() {
+        let dir = scratch_dir("dired-list");
+        std::fs::write(dir.join("b.txt"), "12345").unwrap();
+        std::fs::create_dir(dir.join("a")).unwrap();
+        let dired = Dired::read(&dir).unwrap();
+        assert_eq!(names(&dired), ["..", "a", "b.txt"]);
+
+        let listing = dired.listing();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], format!("  {}:", dir.display()));
+        assert!(lines[3].starts_with("   drwx") && lines[3].ends_with(" a/"));
+        assert!(lines[4].contains("          5 ") && lines[4].ends_with(" b.txt"));
+        assert_eq!(dired.entry_at_line(4).unwrap().name, "b.txt");
+        assert_eq!(dired.entry_at_line(1), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_file_operations() {
+        let dir = scratch_dir("dired-ops");
+        std::fs::write(dir.join("keep.txt"), "keep").unwrap();
+        std::fs::write(dir.join("old.txt"), "old").unwrap();
+        let mut dired = Dired::read(&dir).unwrap();
+
+        let sub = dired.create_dir("sub").unwrap();
+        assert!(sub.is_dir());
+        assert_eq!(names(&dired), ["..", "keep.txt", "old.txt", "sub"]);
+
+        dired.rename(4, "new.txt").unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("new.txt")).unwrap(), "old");
+        assert_eq!(names(&dired), ["..", "keep.txt", "new.txt", "sub"]);
+
+        // Copying onto a directory copies into it.
+        dired.copy(3, "sub").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(sub.join("keep.txt")).unwrap(),
+            "keep"
+        );
+        assert!(dired.copy(5, "elsewhere").is_err());
+        assert!(dired.rename(2, "x").is_err());
+
+        assert!(!dired.set_mark(2, true));
+        assert!(dired.set_mark(3, true) && dired.set_mark(4, true));
+        dired.refresh().unwrap();
+        assert!(dired.listing().lines().nth(3).unwrap().starts_with('D'));
+        assert_eq!(dired.delete_marked().unwrap(), 2);
+        assert_eq!(names(&dired), ["..", "sub"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(mode_string(0o755, true, false), "drwxr-xr-x");
+        assert_eq!(mode_string(0o100640, false, false), "-rw-r-----");
+        assert_eq!(mode_string(0o777, false, true), "lrwxrwxrwx");
+        // 2024-02-29 12:34:56 UTC, shown on the local clock.
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(1_709_210_096);
+        let local = ffi::local_time(1_709_210_096).unwrap();
+        assert_eq!(
+            format_time(time),
+            format!(
+                "{}-{:02}-{:02} {:02}:{:02}",
+                local.year, local.month, local.day, local.hour, local.min
+            )
+        );
+        assert!(format_time(time).starts_with("2024-0"));
+        assert_eq!(local.sec, 56);
+    }
+}
@@ -1,47 +1,92 @@
 #![allow(unused_imports, unused_variables)]
 #![allow(dead_code)]
 
+use crate::{
+    autosave,
+    backup::BackupConfig,
+    dired::{self, Dired},
+    encoding::{self, Encoding},
+    ffi, grapheme,
+    gutter::{self, SignColumn, SignKind, Signs},
+    hexl::Hexl,
+    highlight::{Highlighter, Language, Token},
+    largefile::{self, LargeFile},
+    modeline,
+    syntax::{Class, Context, Scanner, SyntaxTable},
+    visual::{self, LineWrap},
+    whitespace,
+};
 use ropey::Rope;
-use std::{io::BufWriter, ops::RangeBounds};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    ops::{Bound, RangeBounds},
+    os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// How far back from a bracket scanning starts to learn whether it sits in a string
+/// or comment. Strings open longer than this before a bracket confuse matching.
+const MATCH_DISTANCE: usize = 100_000;
+
+/// A bracket next to point and the one matching it, as char offsets. other is None
+/// when the bracket is unbalanced or closed by the wrong kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParenMatch {
+    pub at: usize,
+    pub other: Option<usize>,
+}
 
 pub trait Doc {
-    fn parse_file_name(name: &str) -> (String, String) {
-        if let Some(pos) = name.chars().position(|letter| letter == '.') {
-            (name[..pos].to_string(), name[pos..].to_string())
-        } else {
-            (name.to_string(), ".txt".to_string())
+    /// The extension modes are picked by: the part after the last "." of the file
+    /// name, so "archive.tar.gz" is "gz" and ".gitignore" has none.
+    fn file_extension(path: &Path) -> Option<std::string::String> {
+        path.extension()
+            .map(|ext| ext.to_string_lossy().to_string())
+    }
+
+    /// The name a buffer visiting path starts out with, before uniquify_buffer_names
+    /// tells apart buffers with the same file name.
+    fn name_for_path(path: &Path) -> std::string::String {
+        match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => path.to_string_lossy().to_string(),
         }
     }
 }
 
 pub struct NewDocument {
     rope: Rope,
-    file_name: String,
-    file_ext: String,
+    file_name: PathBuf,
     char_count: usize,
     line_count: usize,
 }
 
 impl Doc for NewDocument {}
 
+impl Default for NewDocument {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NewDocument {
     pub fn new() -> Self {
         NewDocument {
             rope: Rope::new(),
-            file_name: "new.txt".to_string(),
-            file_ext: ".txt".to_string(),
+            file_name: PathBuf::from("new.txt"),
             char_count: 0,
             line_count: 0,
         }
     }
 
     pub fn save_file(self, name: Option<&str>) -> std::io::Result<Document> {
-        let fl_nm = if let Some(name) = name {
-            Self::parse_file_name(name)
-        } else {
-            (self.file_name, self.file_ext)
+        let file_name = match name {
+            Some(name) => PathBuf::from(name),
+            None => self.file_name,
         };
-        let file_name = format!("{}{}", fl_nm.0, fl_nm.1);
         let file = match std::fs::File::create_new(&file_name) {
             Ok(file) => file,
             Err(_) => return Err(std::io::Error::last_os_error()),
@@ -51,13 +96,13 @@ impl NewDocument {
             Ok(()) => {}
             Err(_) => return Err(std::io::Error::last_os_error()),
         };
-        Ok(Document {
-            rope: self.rope,
-            file_name,
-            file_ext: fl_nm.1,
-            char_count: self.char_count,
-            line_count: self.line_count,
-        })
+        Ok(Document::with_rope(
+            Some(file_name),
+            None,
+            self.rope,
+            Encoding::Utf8,
+            false,
+        ))
     }
 
     pub fn update_doc_info(&mut self) {
@@ -78,84 +123,2620 @@ impl NewDocument {
 }
 
 pub struct Document {
-    file_name: std::string::String,
-    file_ext: std::string::String,
+    path: Option<PathBuf>,
+    buffer_name: std::string::String,
     char_count: usize,
     line_count: usize,
     rope: Rope,
+    point: usize,
+    read_only: bool,
+    eol: Eol,
+    encoding: Encoding,
+    bom: bool,
+    final_newline: FinalNewline,
+    modified: bool,
+    auto_saved: bool,
+    backup: BackupConfig,
+    backed_up: bool,
+    save_failed: bool,
+    disk: Option<FileStamp>,
+    auto_revert: bool,
+    large: Option<LargeView>,
+    hexl: Option<Hexl>,
+    dired: Option<Dired>,
+    line_wrap: LineWrap,
+    tab_width: usize,
+    indent_tabs: bool,
+    syntax: SyntaxTable,
+    /// Behind a RefCell so drawing, which only reads the buffer, can lex lines.
+    highlighter: RefCell<Highlighter>,
+    signs: Signs,
+    /// What diff signs compare the buffer with, while they are on.
+    diff_base: Option<std::string::String>,
+    /// Set by edits while diff signs are on, until update_diff_signs runs.
+    diff_stale: bool,
+    /// Whitespace mode: show tabs, trailing whitespace and line ends.
+    whitespace: bool,
+    /// Delete trailing whitespace and ensure a final newline on every save.
+    whitespace_cleanup: bool,
+    /// The git branch the mode line shows, read on visit, save and revert and
+    /// refreshed by refresh_git_branch rather than on every redraw.
+    git_branch: Option<std::string::String>,
 }
 
 impl Doc for Document {}
 
 impl Document {
-    pub fn open_doc(name: &str) -> std::io::Result<Self> {
-        let fl_nm = if let Some(pos) = name.chars().position(|letter| letter == '.') {
-            (name[..pos].to_string(), name[pos..].to_string())
-        } else {
-            (name.to_string(), ".txt".to_string())
+    /// Loads an existing file, keeping path exactly as given. The file is only opened
+    /// for reading, and the buffer is read-only when the file can't be written.
+    pub fn open_doc<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let mut file = std::fs::File::open(path)?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let mut doc = Self::from_bytes(Some(path.to_path_buf()), None, &bytes);
+        doc.disk = Some(FileStamp::from(&file.metadata()?));
+        doc.read_only = !ffi::is_writable(path);
+        Ok(doc)
+    }
+
+    /// A read-only buffer listing the directory dir, named after it with a trailing
+    /// "/". Point starts on the first entry after "..".
+    pub fn open_dired(dir: &Path) -> std::io::Result<Self> {
+        // "src/.." lists the parent under its own name.
+        let dir = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        let dir = dir.as_path();
+        let dired = Dired::read(dir)?;
+        let name = format!("{}/", Self::name_for_path(dir).trim_end_matches('/'));
+        let mut doc = Self::with_rope(
+            None,
+            Some(name),
+            Rope::from_str(&dired.listing()),
+            Encoding::Utf8,
+            false,
+        );
+        doc.read_only = true;
+        doc.dired = Some(dired);
+        doc.goto_line_col(dired::HEADER_LINES + 2, 1);
+        Ok(doc)
+    }
+
+    pub fn dired(&self) -> Option<&Dired> {
+        self.dired.as_ref()
+    }
+
+    /// Runs op on the directory of a dired buffer and shows the listing again, with
+    /// point on the same line. None when this isn't a dired buffer.
+    pub fn with_dired<T>(
+        &mut self,
+        op: impl FnOnce(&mut Dired) -> std::io::Result<T>,
+    ) -> Option<std::io::Result<T>> {
+        let dired = self.dired.as_mut()?;
+        let result = op(dired);
+        let listing = dired.listing();
+        let line = self.point_line();
+        self.replace_rope(Rope::from_str(&listing));
+        self.update_doc_info();
+        self.goto_line_col(line + 1, 1);
+        Some(result)
+    }
+
+    /// Shows path through a memory map instead of reading it into the rope. The buffer
+    /// is read-only; only the lines that are looked at are ever read from disk.
+    pub fn open_large(path: &Path) -> std::io::Result<Self> {
+        let file = LargeFile::open(path)?;
+        let mut doc = Self::with_rope(
+            Some(path.to_path_buf()),
+            None,
+            Rope::new(),
+            Encoding::Utf8,
+            false,
+        );
+        doc.disk = FileStamp::of(path);
+        doc.read_only = true;
+        doc.large = Some(LargeView {
+            file,
+            line: 0,
+            col: 0,
+        });
+        Ok(doc)
+    }
+
+    /// Keeps a large-file view readable after its file was truncated on disk; see
+    /// LargeFile::remap_if_shrunk. Point is clamped to what is left of the file.
+    /// A truncation after this check can still crash the next read.
+    pub fn remap_if_shrunk(&mut self) -> std::io::Result<()> {
+        if let Some(view) = &mut self.large {
+            if view.file.remap_if_shrunk()? {
+                let (line, col) = (view.line, view.col);
+                view.goto(line, col);
+            }
+        }
+        Ok(())
+    }
+
+    /// True while the buffer is a read-only large-file view.
+    pub fn is_large(&self) -> bool {
+        self.large.is_some()
+    }
+
+    /// Leaves the large-file view by reading the whole file into the rope, keeping
+    /// point on the same line and column.
+    pub fn edit_whole_file(&mut self) -> std::io::Result<()> {
+        let Some(view) = &self.large else {
+            return Ok(());
         };
-        let file_name = format!("{}{}", fl_nm.0, fl_nm.1);
-        let file = match std::fs::OpenOptions::new()
-            .read(true)
-            .append(true)
-            .create(true)
-            .open(&file_name) {
-                Ok(file) => file,
-                Err(_) => return Err(std::io::Error::last_os_error()),                
-            };
-
-        let reader = std::io::BufReader::new(file);
-        let rope = match Rope::from_reader(reader) {
-            Ok(rope) => rope,
-            Err(_) => return Err(std::io::Error::last_os_error()),
+        let (line, col) = (view.line, view.col);
+        let fresh = Self::open_doc(self.visited_path()?)?;
+        self.replace_rope(fresh.rope);
+        self.eol = fresh.eol;
+        self.encoding = fresh.encoding;
+        self.bom = fresh.bom;
+        self.disk = fresh.disk;
+        self.read_only = fresh.read_only;
+        self.large = None;
+        self.update_doc_info();
+        self.goto_line_col(line + 1, col + 1);
+        Ok(())
+    }
+
+    pub fn is_hexl(&self) -> bool {
+        self.hexl.is_some()
+    }
+
+    /// True when the text is likely a binary file: it has NUL bytes or wasn't valid
+    /// in any of the encodings tried before the Windows-1252 fallback.
+    pub fn looks_binary(&self) -> bool {
+        matches!(self.encoding, Encoding::Utf8Raw | Encoding::Windows1252)
+            || self.rope.chars().any(|ch| ch == '\0')
+    }
+
+    /// Switches to the hex view of the bytes the buffer would be saved as. Until
+    /// hexl_exit, edits and saves work on those bytes.
+    pub fn hexl_mode(&mut self) -> std::io::Result<()> {
+        if self.is_large() {
+            return Err(std::io::Error::other(
+                "Large file view can't be shown in hex; M-x edit-whole-file first",
+            ));
+        }
+        if self.hexl.is_none() {
+            let bytes = self.encoding.encode(&self.rope, self.bom)?;
+            self.hexl = Some(Hexl::new(bytes));
+        }
+        Ok(())
+    }
+
+    /// Leaves the hex view, decoding the possibly edited bytes back into text.
+    pub fn hexl_exit(&mut self) {
+        let Some(hexl) = self.hexl.take() else {
+            return;
+        };
+        let decoded = encoding::decode(hexl.bytes());
+        self.replace_rope(Rope::from_str(&decoded.text));
+        self.encoding = decoded.encoding;
+        self.bom = decoded.bom;
+        self.eol = Eol::detect(&self.rope);
+        self.point = self.point.min(self.rope.len_chars());
+        self.update_doc_info();
+    }
+
+    /// Types the hex digit ch over the nibble at the cursor of the hex view.
+    pub fn hexl_input(&mut self, ch: char) -> bool {
+        let edited = self.hexl.as_mut().is_some_and(|hexl| hexl.input(ch));
+        if edited {
+            self.changed();
+        }
+        edited
+    }
+
+    /// The bytes save_file and auto_save write: the hex view's bytes as they are, or
+    /// the text in the buffer's encoding.
+    fn contents(&self) -> std::io::Result<Vec<u8>> {
+        match &self.hexl {
+            Some(hexl) => Ok(hexl.bytes().to_vec()),
+            None => self.encoding.encode(&self.rope, self.bom),
+        }
+    }
+
+    /// Reads everything from reader into a buffer called name, used for "-" on the
+    /// command line.
+    pub fn from_reader<R: std::io::Read>(name: &str, mut reader: R) -> std::io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(Self::from_bytes(None, Some(name.to_string()), &bytes))
+    }
+
+    /// Visit a file the way find-file does. An existing file is loaded, a missing one
+    /// becomes an empty buffer that is only written to disk by save_file. Files of
+    /// LARGE_FILE_THRESHOLD bytes or more get a large-file view.
+    pub fn find_file(path: &Path) -> std::io::Result<Self> {
+        if let Ok(meta) = std::fs::metadata(path) {
+            if meta.is_dir() {
+                return Self::open_dired(path);
+            }
+            if meta.is_file() && meta.len() >= largefile::LARGE_FILE_THRESHOLD {
+                return Self::open_large(path);
+            }
+            return Self::open_doc(path);
+        }
+        Ok(Self::with_rope(
+            Some(path.to_path_buf()),
+            None,
+            Rope::new(),
+            Encoding::Utf8,
+            false,
+        ))
+    }
+
+    fn from_bytes(
+        path: Option<PathBuf>,
+        buffer_name: Option<std::string::String>,
+        bytes: &[u8],
+    ) -> Self {
+        let decoded = encoding::decode(bytes);
+        let rope = Rope::from_str(&decoded.text);
+        Self::with_rope(path, buffer_name, rope, decoded.encoding, decoded.bom)
+    }
+
+    /// Every constructor ends here. Buffers without a path, like *stdin*, are never
+    /// written to disk; the buffer name defaults to the file name of path.
+    fn with_rope(
+        path: Option<PathBuf>,
+        buffer_name: Option<std::string::String>,
+        rope: Rope,
+        encoding: Encoding,
+        bom: bool,
+    ) -> Self {
+        let buffer_name = match (buffer_name, &path) {
+            (Some(name), _) => name,
+            (None, Some(path)) => Self::name_for_path(path),
+            (None, None) => "*scratch*".to_string(),
         };
-        Ok(Document {
-            file_name,
-            file_ext: fl_nm.1,
+        let ext = path.as_deref().and_then(Self::file_extension);
+        let syntax = SyntaxTable::for_extension(ext.as_deref());
+        let language = Language::for_extension(ext.as_deref());
+        Document {
+            path,
+            buffer_name,
             char_count: rope.len_chars(),
             line_count: rope.len_lines(),
+            eol: Eol::detect(&rope),
             rope,
+            point: 0,
+            read_only: false,
+            encoding,
+            bom,
+            final_newline: FinalNewline::Keep,
+            modified: false,
+            auto_saved: true,
+            backup: BackupConfig::default(),
+            backed_up: false,
+            save_failed: false,
+            disk: None,
+            auto_revert: false,
+            large: None,
+            hexl: None,
+            dired: None,
+            line_wrap: LineWrap::default(),
+            tab_width: visual::DEFAULT_TAB_WIDTH,
+            indent_tabs: true,
+            syntax,
+            highlighter: RefCell::new(Highlighter::new(language)),
+            signs: Signs::default(),
+            diff_base: None,
+            diff_stale: false,
+            whitespace: false,
+            whitespace_cleanup: false,
+            git_branch: None,
+        }
+    }
+
+    /// True when this buffer is visiting path. Both sides are compared as absolute
+    /// paths so "./a.rs" and "a.rs" refer to the same buffer.
+    pub fn visits(&self, path: &Path) -> bool {
+        self.path
+            .as_deref()
+            .or(self.dired.as_ref().map(Dired::dir))
+            .is_some_and(|own| absolute_path(own) == absolute_path(path))
+    }
+
+    /// How the file is backed up on the first save. Buffers start with backups off.
+    pub fn set_backup_config(&mut self, backup: BackupConfig) {
+        self.backup = backup;
+    }
+
+    /// Writes the buffer to a temporary file next to the target and renames it over
+    /// the original, so a failed write never leaves a truncated file behind; see
+    /// write_file for symlinks and hard links. The final newline setting is applied
+    /// to the buffer first. The first save of the buffer copies the old file to its
+    /// backup before the rename.
+    pub fn save_file(&mut self) -> std::io::Result<()> {
+        let saved = self.write_visited_file();
+        self.save_failed = saved.is_err();
+        saved
+    }
+
+    /// True when the last save of the buffer failed; a later successful save of the
+    /// same buffer clears it.
+    pub fn save_failed(&self) -> bool {
+        self.save_failed
+    }
+
+    fn write_visited_file(&mut self) -> std::io::Result<()> {
+        if self.is_large() {
+            return Err(std::io::Error::other(
+                "Large file view can't be saved; M-x edit-whole-file first",
+            ));
+        }
+        if !self.is_hexl() {
+            self.apply_final_newline();
+            if self.whitespace_cleanup {
+                self.delete_trailing_whitespace();
+                self.ensure_final_newline();
+            }
+        }
+        // Encoding first means a char the encoding can't represent fails the save
+        // before anything on disk is touched.
+        let bytes = self.contents()?;
+        let path = self.visited_path()?.to_path_buf();
+        let path = path.as_path();
+        if !self.backed_up {
+            self.backup.make_backup(path)?;
+        }
+        write_file(path, &bytes)?;
+        self.disk = FileStamp::of(path);
+        self.backed_up = true;
+        self.modified = false;
+        self.auto_saved = true;
+        if let Some(auto_save) = self.auto_save_path() {
+            let _ = std::fs::remove_file(auto_save);
+        }
+        if self.diff_base.is_some() {
+            self.set_diff_signs(true);
+        }
+        self.refresh_git_branch();
+        Ok(())
+    }
+
+    /// The path of the visited file exactly as it was given, None for buffers like
+    /// *stdin* that don't visit one.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn visited_path(&self) -> std::io::Result<&Path> {
+        self.path().ok_or_else(|| {
+            std::io::Error::other(format!("{} is not visiting a file", self.buffer_name))
         })
     }
 
-    //TODO: Change File to Open builder.
-    pub fn save_file(&self) -> std::io::Result<()> {
-        let file = match std::fs::File::create_new(&self.file_name) {
-            Ok(file) => file,
-            Err(_) => return Err(std::io::Error::last_os_error()),
+    /// The name shown in the mode line and used in messages.
+    pub fn buffer_name(&self) -> &str {
+        &self.buffer_name
+    }
+
+    pub fn set_buffer_name(&mut self, buffer_name: std::string::String) {
+        self.buffer_name = buffer_name;
+    }
+
+    /// The real extension of the visited file, used to pick the major mode.
+    pub fn extension(&self) -> Option<std::string::String> {
+        self.path().and_then(Self::file_extension)
+    }
+
+    /// True when the file on disk is no longer the one the buffer was read from or
+    /// last saved to: it was written, replaced, created or deleted by someone else.
+    pub fn changed_on_disk(&self) -> bool {
+        self.path()
+            .is_some_and(|path| FileStamp::of(path) != self.disk)
+    }
+
+    /// Replaces the text of the buffer with that of fresh, a buffer just read from
+    /// disk, the way revert and recover_auto_save load a file. The hex and large-file
+    /// views are dropped and the line ending is taken from the new text; the buffer's
+    /// other settings are kept.
+    fn load(&mut self, fresh: Document) {
+        self.hexl = None;
+        self.large = fresh.large;
+        self.replace_rope(fresh.rope);
+        self.eol = fresh.eol;
+        self.encoding = fresh.encoding;
+        self.bom = fresh.bom;
+        self.point = self.point.min(self.rope.len_chars());
+        self.auto_saved = true;
+        self.update_doc_info();
+    }
+
+    /// Throws away the buffer text and reads the file again. Point stays on the same
+    /// line and column where possible and the buffer's settings are kept.
+    pub fn revert(&mut self) -> std::io::Result<()> {
+        // Finding point's line and column reads the old view.
+        let _ = self.remap_if_shrunk();
+        let (line, col) = (self.point_line(), self.point_col());
+        let fresh = Self::find_file(self.visited_path()?)?;
+        let hexl = self.hexl.is_some();
+        self.disk = fresh.disk;
+        self.load(fresh);
+        self.modified = false;
+        if hexl {
+            self.hexl_mode()?;
+        }
+        self.goto_line_col(line + 1, col + 1);
+        if let Some(auto_save) = self.auto_save_path() {
+            let _ = std::fs::remove_file(auto_save);
+        }
+        if self.diff_base.is_some() {
+            self.set_diff_signs(true);
+        }
+        self.refresh_git_branch();
+        Ok(())
+    }
+
+    pub fn auto_revert(&self) -> bool {
+        self.auto_revert
+    }
+
+    /// In auto-revert mode an unmodified buffer follows its file on disk.
+    pub fn set_auto_revert(&mut self, auto_revert: bool) {
+        self.auto_revert = auto_revert;
+    }
+
+    pub fn line_wrap(&self) -> LineWrap {
+        self.line_wrap
+    }
+
+    /// Only changes how the buffer is drawn; the text stays as it is.
+    pub fn set_line_wrap(&mut self, line_wrap: LineWrap) {
+        self.line_wrap = line_wrap;
+    }
+
+    /// Columns between tab stops, both for showing tabs and for indenting.
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width.max(1);
+    }
+
+    /// Whether the Tab key inserts a tab character rather than spaces.
+    pub fn indent_tabs(&self) -> bool {
+        self.indent_tabs
+    }
+
+    pub fn set_indent_tabs(&mut self, indent_tabs: bool) {
+        self.indent_tabs = indent_tabs;
+    }
+
+    /// Indents to the next tab stop with a tab, or with spaces when indent_tabs is off.
+    pub fn insert_tab(&mut self) {
+        if self.indent_tabs {
+            return self.insert("\t");
+        }
+        let line = self.line_text(self.point_line()).unwrap_or_default();
+        let col = visual::display_col(&line, self.point_col(), self.tab_width);
+        let spaces = visual::next_tab_stop(col, self.tab_width) - col;
+        self.insert(&" ".repeat(spaces));
+    }
+
+    /// Brings an auto-revert buffer up to date with its file. When the file only grew,
+    /// the way a log written by "tail -f" style appends does, only the new complete
+    /// lines are read and appended; anything else reverts the whole buffer. Returns
+    /// true when the buffer changed. Buffers with unsaved changes are left alone.
+    pub fn auto_revert_now(&mut self) -> std::io::Result<bool> {
+        if !self.auto_revert || self.modified || !self.changed_on_disk() {
+            return Ok(false);
+        }
+        if self.is_large() {
+            return self.revert().map(|()| true);
+        }
+        let path = self.visited_path()?;
+        match (&self.disk, FileStamp::of(path)) {
+            (Some(old), Some(new))
+                if new.ino == old.ino && new.len > old.len && !self.encoding.is_utf16() =>
+            {
+                self.append_from_disk(old.len)
+            }
+            _ => self.revert().map(|()| true),
+        }
+    }
+
+    /// Appends the complete lines written past offset in the file. A trailing line
+    /// that is still being written is picked up by a later call.
+    fn append_from_disk(&mut self, offset: u64) -> std::io::Result<bool> {
+        let mut file = std::fs::File::open(self.visited_path()?)?;
+        let meta = file.metadata()?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let Some(end) = bytes.iter().rposition(|byte| *byte == b'\n') else {
+            return Ok(false);
         };
-        let buf_writer = BufWriter::new(file);
-        match self.rope.write_to(buf_writer) {
-            Ok(()) => {}
-            Err(_) => return Err(std::io::Error::last_os_error()),
+        bytes.truncate(end + 1);
+        let text = match self.encoding.decode(&bytes) {
+            Some(text) => text,
+            None => encoding::decode(&bytes).text,
         };
-        Ok(())
+        let follow = self.point == self.rope.len_chars();
+        let (line, before) = (self.rope.len_lines() - 1, self.rope.len_lines());
+        self.signs
+            .edited(self.rope.len_chars(), 0, text.chars().count());
+        self.rope.insert(self.rope.len_chars(), &text);
+        self.lines_edited(line, before);
+        if follow {
+            self.point = self.rope.len_chars();
+        }
+        self.update_doc_info();
+        self.disk = Some(FileStamp {
+            len: offset + bytes.len() as u64,
+            ..FileStamp::from(&meta)
+        });
+        Ok(true)
     }
 
-    pub fn update_doc_info(&mut self) {
-        self.char_count = self.rope.len_chars();
-        self.line_count = self.rope.len_lines()
+    pub fn eol(&self) -> Eol {
+        self.eol
     }
 
-    pub fn insert(&mut self, text: &str) {
-        self.rope.insert(self.rope.len_chars(), text)
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
     }
 
-    pub fn remove<R>(&mut self, char_range: R)
-    where
-        R: RangeBounds<usize>,
-    {
-        self.rope.remove(char_range)
+    /// Sets the encoding used by the next save. The text in the buffer is unchanged.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
 
-    #[test]
-    fn test_load_file() {
-        let doc = Document::open_doc("./text.txt").unwrap();
-        assert_eq!(20, doc.line_count);
+    pub fn set_bom(&mut self, bom: bool) {
+        self.bom = bom
+    }
+
+    pub fn final_newline(&self) -> FinalNewline {
+        self.final_newline
+    }
+
+    pub fn set_final_newline(&mut self, final_newline: FinalNewline) {
+        self.final_newline = final_newline
+    }
+
+    /// Rewrites every line break in the buffer to eol. Future newlines use it too.
+    pub fn convert_eol(&mut self, eol: Eol) {
+        let before: std::string::String = self.rope.slice(..self.point).chars().collect();
+        let after: std::string::String = self.rope.slice(self.point..).chars().collect();
+        let before = eol.convert(&before);
+        self.point = before.chars().count();
+        let mut rope = Rope::from_str(&before);
+        rope.append(Rope::from_str(&eol.convert(&after)));
+        self.replace_rope(rope);
+        self.eol = eol;
+        self.changed();
+    }
+
+    /// Puts rope in place of the buffer text as a whole, as reverting or converting
+    /// line endings does. Diagnostics and diff signs are dropped, since they were
+    /// about the old text, and diff signs are redone on idle. Bookmarks stay on their
+    /// line numbers, clamped to the new text.
+    fn replace_rope(&mut self, rope: Rope) {
+        let old = std::mem::replace(&mut self.rope, rope);
+        self.highlighter.get_mut().reset();
+        self.signs.clear(SignColumn::Diagnostic);
+        self.signs.clear(SignColumn::Diff);
+        self.signs.replaced(&old, &self.rope);
+        self.diff_stale = self.diff_base.is_some();
+    }
+
+    /// Inserts the buffer's own line break at point.
+    pub fn newline(&mut self) {
+        self.insert(self.eol.as_str())
+    }
+
+    fn ends_with_line_break(&self) -> bool {
+        let len = self.rope.len_chars();
+        len > 0 && matches!(self.rope.char(len - 1), '\n' | '\r')
+    }
+
+    /// Adds a line break at the end of a buffer that has text but doesn't end in one.
+    fn ensure_final_newline(&mut self) {
+        if self.rope.len_chars() > 0 && !self.ends_with_line_break() {
+            let (line, before) = (self.rope.len_lines() - 1, self.rope.len_lines());
+            self.signs
+                .edited(self.rope.len_chars(), 0, self.eol.as_str().len());
+            self.rope.insert(self.rope.len_chars(), self.eol.as_str());
+            self.lines_edited(line, before);
+            self.changed();
+        }
+    }
+
+    /// Removes the whitespace at the end of every line. Point stays on the text it
+    /// was on, or at the new end of its line. Returns how many lines changed.
+    pub fn delete_trailing_whitespace(&mut self) -> usize {
+        if !self.rope_motion() || self.dired.is_some() {
+            return 0;
+        }
+        let mut point = self.point;
+        let mut count = 0;
+        for line in (0..self.rope.len_lines()).rev() {
+            let start = self.rope.line_to_char(line);
+            let end = start + self.line_len(line);
+            let text = self.rope.slice(start..end).to_string();
+            let keep = start + whitespace::trailing_start(&text);
+            if keep == end {
+                continue;
+            }
+            if point > keep {
+                point -= point.min(end) - keep;
+            }
+            self.remove(keep..end);
+            count += 1;
+        }
+        self.point = point;
+        count
+    }
+
+    /// True while whitespace mode shows this buffer's whitespace. Views other than
+    /// the text itself don't show it.
+    pub fn whitespace_mode(&self) -> bool {
+        self.whitespace && self.rope_motion() && self.dired.is_none()
+    }
+
+    pub fn set_whitespace_mode(&mut self, on: bool) {
+        self.whitespace = on;
+    }
+
+    pub fn whitespace_cleanup(&self) -> bool {
+        self.whitespace_cleanup
+    }
+
+    /// Turns the save hook that deletes trailing whitespace and ensures a final
+    /// newline on or off.
+    pub fn set_whitespace_cleanup(&mut self, on: bool) {
+        self.whitespace_cleanup = on;
+    }
+
+    fn apply_final_newline(&mut self) {
+        match self.final_newline {
+            FinalNewline::Keep => {}
+            FinalNewline::Ensure => self.ensure_final_newline(),
+            FinalNewline::Strip => {
+                if self.ends_with_line_break() {
+                    let last_line = self.rope.len_lines() - 1;
+                    let end = self.rope.line_to_char(last_line);
+                    let start =
+                        self.rope.line_to_char(last_line - 1) + self.line_len(last_line - 1);
+                    let before = self.rope.len_lines();
+                    self.signs.edited(start, end - start, 0);
+                    self.rope.remove(start..end);
+                    self.lines_edited(last_line - 1, before);
+                    self.point = self.point.min(self.rope.len_chars());
+                    self.changed();
+                }
+            }
+        }
+    }
+
+    pub fn rope(&self) -> &Rope {
+        &self.rope
+    }
+
+    /// Point is a char index into the rope.
+    pub fn point(&self) -> usize {
+        self.point
+    }
+
+    pub fn point_line(&self) -> usize {
+        match (&self.hexl, &self.large) {
+            (Some(hexl), _) => hexl.cursor_row(),
+            (_, Some(view)) => view.line,
+            _ => self.rope.char_to_line(self.point),
+        }
+    }
+
+    pub fn point_col(&self) -> usize {
+        match (&self.hexl, &self.large) {
+            (Some(hexl), _) => hexl.cursor_col(),
+            (_, Some(view)) => view.col,
+            _ => self.point - self.rope.line_to_char(self.point_line()),
+        }
+    }
+
+    /// The text of line without its line break, None past the last line. This is how
+    /// the screen reads both ropes and large-file views.
+    pub fn line_text(&self, line: usize) -> Option<Cow<'_, str>> {
+        if let Some(hexl) = &self.hexl {
+            return hexl.row(line).map(Cow::Owned);
+        }
+        match &self.large {
+            Some(view) => view
+                .file
+                .line(line)
+                .map(std::string::String::from_utf8_lossy),
+            None if line < self.rope.len_lines() => {
+                let slice = self.rope.line(line);
+                Some(Cow::Owned(slice.slice(..self.line_len(line)).to_string()))
+            }
+            None => None,
+        }
+    }
+
+    /// How many lines line_text can show, as far as that is known without reading the
+    /// whole file: a large-file view knows only the lines it indexed so far.
+    pub fn known_lines(&self) -> usize {
+        match (&self.hexl, &self.large) {
+            (Some(hexl), _) => hexl.rows(),
+            (_, Some(view)) => view.file.line_count(),
+            _ => self.rope.len_lines(),
+        }
+    }
+
+    /// Moves point to the end of the next match of needle after point. Returns false,
+    /// leaving point alone, when there is none.
+    pub fn search_forward(&mut self, needle: &str) -> bool {
+        if let Some(hexl) = &mut self.hexl {
+            let from = hexl.cursor() + 1;
+            let found = hexl.bytes().get(from..).and_then(|rest| {
+                rest.windows(needle.len().max(1))
+                    .position(|w| w == needle.as_bytes())
+            });
+            if let Some(pos) = found {
+                hexl.goto(from + pos);
+            }
+            return found.is_some();
+        }
+        if let Some(view) = &mut self.large {
+            return view.search_forward(needle);
+        }
+        let rest = self.rope.slice(self.point..).to_string();
+        match rest.find(needle) {
+            Some(pos) => {
+                let end = rest[..pos + needle.len()].chars().count();
+                self.point += end;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// True when the file exists and this process can't write to it.
+    pub fn is_write_protected(&self) -> bool {
+        self.path()
+            .is_some_and(|path| path.exists() && !ffi::is_writable(path))
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only
+    }
+
+    /// Moves point to a 1-based line and column, clamped to the buffer.
+    pub fn goto_line_col(&mut self, line: usize, col: usize) {
+        if let Some(hexl) = &mut self.hexl {
+            hexl.goto(line.saturating_sub(1) * crate::hexl::ROW_BYTES);
+            return;
+        }
+        if let Some(view) = &mut self.large {
+            view.goto(line.saturating_sub(1), col.saturating_sub(1));
+            return;
+        }
+        let line = line.saturating_sub(1).min(self.rope.len_lines() - 1);
+        let col = col.saturating_sub(1).min(self.line_len(line));
+        self.point = self.rope.line_to_char(line) + col;
+        if !grapheme::is_boundary(&self.rope, self.point) {
+            self.point = grapheme::prev_boundary(&self.rope, self.point);
+        }
+    }
+
+    /// Moves over one grapheme cluster, so a combining accent, an emoji sequence or a
+    /// CRLF pair is a single step for motion and deletion.
+    pub fn forward_char(&mut self) {
+        if let Some(hexl) = &mut self.hexl {
+            hexl.forward_byte();
+            return;
+        }
+        if let Some(view) = &mut self.large {
+            if view.col < view.line_len(view.line) {
+                view.col += 1;
+            } else if view.file.line_start(view.line + 1).is_some() {
+                view.goto(view.line + 1, 0);
+            }
+            return;
+        }
+        self.point = grapheme::next_boundary(&self.rope, self.point);
+    }
+
+    pub fn backward_char(&mut self) {
+        if let Some(hexl) = &mut self.hexl {
+            hexl.backward_byte();
+            return;
+        }
+        if let Some(view) = &mut self.large {
+            if view.col > 0 {
+                view.col -= 1;
+            } else if view.line > 0 {
+                view.goto(view.line - 1, usize::MAX);
+            }
+            return;
+        }
+        self.point = grapheme::prev_boundary(&self.rope, self.point);
+    }
+
+    /// Moves point count lines down (or up when negative), keeping the screen column
+    /// where the target line is long enough.
+    pub fn move_lines(&mut self, count: isize) {
+        if let Some(hexl) = &mut self.hexl {
+            hexl.move_rows(count);
+            return;
+        }
+        if let Some(view) = &mut self.large {
+            view.goto(view.line.saturating_add_signed(count), view.col);
+            return;
+        }
+        let from = self.point_line();
+        let col = visual::display_col(
+            &self.line_text(from).unwrap_or_default(),
+            self.point_col(),
+            self.tab_width,
+        );
+        let last_line = self.rope.len_lines() - 1;
+        let line = from.saturating_add_signed(count).min(last_line);
+        let text = self.line_text(line).unwrap_or_default();
+        self.point = self.rope.line_to_char(line) + visual::char_at_col(&text, col, self.tab_width);
+    }
+
+    /// Moves to the start of the line point is on.
+    pub fn beginning_of_line(&mut self) {
+        if let Some(hexl) = &mut self.hexl {
+            hexl.goto(hexl.cursor_row() * crate::hexl::ROW_BYTES);
+            return;
+        }
+        if let Some(view) = &mut self.large {
+            view.col = 0;
+            return;
+        }
+        self.point = self.rope.line_to_char(self.point_line());
+    }
+
+    /// Moves to the end of the line point is on, before its line break.
+    pub fn end_of_line(&mut self) {
+        if let Some(hexl) = &mut self.hexl {
+            hexl.goto((hexl.cursor_row() + 1) * crate::hexl::ROW_BYTES - 1);
+            return;
+        }
+        if let Some(view) = &mut self.large {
+            view.col = view.line_len(view.line);
+            return;
+        }
+        let line = self.point_line();
+        self.point = self.rope.line_to_char(line) + self.line_len(line);
+    }
+
+    /// Moves to the first character of the line that isn't whitespace.
+    pub fn back_to_indentation(&mut self) {
+        if self.hexl.is_some() || self.large.is_some() {
+            return self.beginning_of_line();
+        }
+        let line = self.point_line();
+        let indent = self
+            .rope
+            .line(line)
+            .chars()
+            .take(self.line_len(line))
+            .take_while(|ch| ch.is_whitespace())
+            .count();
+        self.point = self.rope.line_to_char(line) + indent;
+    }
+
+    pub fn beginning_of_buffer(&mut self) {
+        if let Some(hexl) = &mut self.hexl {
+            hexl.goto(0);
+            return;
+        }
+        if let Some(view) = &mut self.large {
+            view.goto(0, 0);
+            return;
+        }
+        self.point = 0;
+    }
+
+    /// Moves to the end of the buffer. In a large file this reads the whole index.
+    pub fn end_of_buffer(&mut self) {
+        if let Some(hexl) = &mut self.hexl {
+            hexl.goto(usize::MAX);
+            return;
+        }
+        if let Some(view) = &mut self.large {
+            let last = view.file.count_lines() - 1;
+            view.goto(last, usize::MAX);
+            return;
+        }
+        self.point = self.rope.len_chars();
+    }
+
+    /// Moves past the end of the next word. What is part of a word is up to the
+    /// buffer's syntax table.
+    pub fn forward_word(&mut self) {
+        if !self.rope_motion() {
+            return;
+        }
+        let len = self.rope.len_chars();
+        let mut idx = self.point;
+        while idx < len && !self.syntax.is_word(self.rope.char(idx)) {
+            idx += 1;
+        }
+        while idx < len && self.syntax.is_word(self.rope.char(idx)) {
+            idx += 1;
+        }
+        self.point = idx;
+    }
+
+    /// Moves to the start of the word before point.
+    pub fn backward_word(&mut self) {
+        if !self.rope_motion() {
+            return;
+        }
+        let mut idx = self.point;
+        while idx > 0 && !self.syntax.is_word(self.rope.char(idx - 1)) {
+            idx -= 1;
+        }
+        while idx > 0 && self.syntax.is_word(self.rope.char(idx - 1)) {
+            idx -= 1;
+        }
+        self.point = idx;
+    }
+
+    /// Moves to the end of the sentence point is in, or of the next one when point is
+    /// between sentences. A sentence ends with ".", "?" or "!", maybe followed by
+    /// closing quotes or brackets, then whitespace; the end of a paragraph ends one
+    /// too.
+    pub fn forward_sentence(&mut self) {
+        if !self.rope_motion() {
+            return;
+        }
+        let len = self.rope.len_chars();
+        let mut idx = self.point;
+        while idx < len && self.rope.char(idx).is_whitespace() {
+            idx += 1;
+        }
+        while idx < len {
+            if let Some(end) = self.sentence_end_at(idx) {
+                self.point = end;
+                return;
+            }
+            let ch = self.rope.char(idx);
+            if matches!(ch, '\n' | '\r') && self.is_blank_line(self.rope.char_to_line(idx) + 1) {
+                self.point = idx;
+                return;
+            }
+            idx += 1;
+        }
+        self.point = len;
+    }
+
+    /// Moves to the start of the sentence point is in, or of the one before when point
+    /// is at a sentence start.
+    pub fn backward_sentence(&mut self) {
+        if !self.rope_motion() {
+            return;
+        }
+        let mut start = self.point;
+        while start > 0 && self.rope.char(start - 1).is_whitespace() {
+            start -= 1;
+        }
+        let mut idx = start;
+        while idx > 0 {
+            let line = self.rope.char_to_line(idx);
+            if self.rope.line_to_char(line) == idx && line > 0 && self.is_blank_line(line - 1) {
+                break;
+            }
+            if matches!(self.sentence_end_at(idx - 1), Some(end) if end < start) {
+                break;
+            }
+            idx -= 1;
+        }
+        while idx < start && self.rope.char(idx).is_whitespace() {
+            idx += 1;
+        }
+        self.point = idx;
+    }
+
+    /// The offset just past the sentence ending with the punctuation at idx, if it is
+    /// one.
+    fn sentence_end_at(&self, idx: usize) -> Option<usize> {
+        if !matches!(self.rope.char(idx), '.' | '?' | '!') {
+            return None;
+        }
+        let len = self.rope.len_chars();
+        let mut end = idx + 1;
+        while end < len
+            && matches!(
+                self.rope.char(end),
+                '"' | '\'' | ')' | ']' | '\u{201d}' | '\u{2019}'
+            )
+        {
+            end += 1;
+        }
+        (end == len || self.rope.char(end).is_whitespace()).then_some(end)
+    }
+
+    /// Moves to the blank line after the paragraph point is in, or to the end of the
+    /// buffer. Paragraphs are separated by lines holding only whitespace.
+    pub fn forward_paragraph(&mut self) {
+        if !self.rope_motion() {
+            return;
+        }
+        let last = self.rope.len_lines() - 1;
+        let mut line = self.point_line();
+        while line <= last && self.is_blank_line(line) {
+            line += 1;
+        }
+        while line <= last && !self.is_blank_line(line) {
+            line += 1;
+        }
+        self.point = match line > last {
+            true => self.rope.len_chars(),
+            false => self.rope.line_to_char(line),
+        };
+    }
+
+    /// Moves to the blank line before the paragraph point is in, or to the start of the
+    /// buffer.
+    pub fn backward_paragraph(&mut self) {
+        if !self.rope_motion() {
+            return;
+        }
+        let mut line = self.point_line();
+        while line > 0 && self.is_blank_line(line) {
+            line -= 1;
+        }
+        while line > 0 && !self.is_blank_line(line) {
+            line -= 1;
+        }
+        self.point = self.rope.line_to_char(line);
+    }
+
+    /// Where scanning for idx starts: the start of a line far enough back to be outside
+    /// any string.
+    fn scan_start(&self, idx: usize) -> usize {
+        let line = self.rope.char_to_line(idx.saturating_sub(MATCH_DISTANCE));
+        self.rope.line_to_char(line)
+    }
+
+    fn scanner(&self, from: usize) -> Scanner<'_, ropey::iter::Chars<'_>> {
+        Scanner::new(&self.syntax, self.rope.chars_at(from), from)
+    }
+
+    /// The offset of the bracket matching the one at idx. Brackets only match others
+    /// in the same context, so a "(" in a string is closed by a ")" in that string.
+    pub fn matching_paren(&self, idx: usize) -> Option<usize> {
+        self.find_match(idx).flatten()
+    }
+
+    /// None when idx is no bracket or is in a comment, Some(None) when it has no match.
+    fn find_match(&self, idx: usize) -> Option<Option<usize>> {
+        if !self.rope_motion() || idx >= self.rope.len_chars() {
+            return None;
+        }
+        let class = self.syntax.class(self.rope.char(idx));
+        let start = self.scan_start(idx);
+        let mut scanner = self.scanner(start);
+        match class {
+            Class::Open(close) => {
+                let (_, _, context) = scanner.nth(idx - start)?;
+                if context == Context::Comment {
+                    return None;
+                }
+                let mut closers = vec![close];
+                for (pos, ch, ctx) in scanner {
+                    if ctx != context {
+                        if matches!(context, Context::String(_)) {
+                            break;
+                        }
+                        continue;
+                    }
+                    match self.syntax.class(ch) {
+                        Class::Open(close) => closers.push(close),
+                        Class::Close(_) if closers.pop() != Some(ch) => return Some(None),
+                        Class::Close(_) if closers.is_empty() => return Some(Some(pos)),
+                        _ => {}
+                    }
+                }
+                Some(None)
+            }
+            Class::Close(open) => {
+                let brackets: Vec<(usize, char, Context)> = scanner
+                    .take(idx - start + 1)
+                    .filter(|(_, ch, _)| {
+                        matches!(self.syntax.class(*ch), Class::Open(_) | Class::Close(_))
+                    })
+                    .collect();
+                let (_, _, context) = *brackets.last()?;
+                if context == Context::Comment {
+                    return None;
+                }
+                let mut openers = vec![open];
+                for &(pos, ch, ctx) in brackets.iter().rev().skip(1) {
+                    if ctx != context {
+                        continue;
+                    }
+                    match self.syntax.class(ch) {
+                        Class::Close(open) => openers.push(open),
+                        _ if openers.pop() != Some(ch) => return Some(None),
+                        _ if openers.is_empty() => return Some(Some(pos)),
+                        _ => {}
+                    }
+                }
+                Some(None)
+            }
+            _ => None,
+        }
+    }
+
+    /// The bracket to highlight: the one after point if it opens, else the one before
+    /// point if it closes.
+    pub fn paren_match(&self) -> Option<ParenMatch> {
+        let after = matches!(
+            self.rope
+                .get_char(self.point)
+                .map(|ch| self.syntax.class(ch)),
+            Some(Class::Open(_))
+        );
+        let at = match after {
+            true => self.point,
+            false => self.point.checked_sub(1)?,
+        };
+        if !after && !matches!(self.syntax.class(self.rope.char(at)), Class::Close(_)) {
+            return None;
+        }
+        let other = self.find_match(at)?;
+        Some(ParenMatch { at, other })
+    }
+
+    /// The string point is inside, by the offset of its opening quote. Point on the
+    /// opening quote is not inside.
+    fn string_at_point(&self) -> Option<usize> {
+        let start = self.scan_start(self.point);
+        match self.scanner(start).nth(self.point - start) {
+            Some((_, _, Context::String(open))) if open < self.point => Some(open),
+            _ => None,
+        }
+    }
+
+    /// Moves over the next balanced expression: a word or symbol, a string or a
+    /// bracketed group. Inside a string it moves within the string.
+    pub fn forward_sexp(&mut self) -> std::io::Result<()> {
+        if !self.rope_motion() {
+            return Ok(());
+        }
+        let inside = self.string_at_point().map(Context::String);
+        let start = self.scan_start(self.point);
+        let mut events = self.scanner(start).skip(self.point - start).peekable();
+        while let Some((pos, ch, ctx)) = events.next() {
+            if ctx == Context::Comment {
+                continue;
+            }
+            match inside {
+                // The closing quote of the string point is in.
+                Some(string) if events.peek().map(|(_, _, next)| *next) != Some(string) => {
+                    return Err(std::io::Error::other(
+                        "Containing expression ends prematurely",
+                    ))
+                }
+                None if matches!(ctx, Context::String(_)) => {
+                    let mut end = pos + 1;
+                    while let Some((pos, _, _)) = events.next_if(|(_, _, next)| *next == ctx) {
+                        end = pos + 1;
+                    }
+                    self.point = end;
+                    return Ok(());
+                }
+                _ => {}
+            }
+            match self.syntax.class(ch) {
+                Class::Open(_) => {
+                    let end = self
+                        .matching_paren(pos)
+                        .ok_or_else(|| std::io::Error::other("Unbalanced parentheses"))?;
+                    self.point = end + 1;
+                    return Ok(());
+                }
+                Class::Close(_) => {
+                    return Err(std::io::Error::other(
+                        "Containing expression ends prematurely",
+                    ))
+                }
+                Class::Word | Class::Symbol => {
+                    let mut end = pos + 1;
+                    while let Some((pos, _, _)) = events.next_if(|(_, ch, next)| {
+                        *next == ctx
+                            && matches!(self.syntax.class(*ch), Class::Word | Class::Symbol)
+                    }) {
+                        end = pos + 1;
+                    }
+                    self.point = end;
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        self.point = self.rope.len_chars();
+        Ok(())
+    }
+
+    /// Moves back over the balanced expression before point.
+    pub fn backward_sexp(&mut self) -> std::io::Result<()> {
+        if !self.rope_motion() {
+            return Ok(());
+        }
+        let inside = self.string_at_point();
+        let start = self.scan_start(self.point);
+        let before: Vec<(usize, char, Context)> =
+            self.scanner(start).take(self.point - start).collect();
+        let mut events = before.into_iter().rev().peekable();
+        while let Some((pos, ch, ctx)) = events.next() {
+            if ctx == Context::Comment {
+                continue;
+            }
+            match (inside, ctx) {
+                (Some(open), _) if pos == open => {
+                    return Err(std::io::Error::other(
+                        "Containing expression ends prematurely",
+                    ))
+                }
+                (None, Context::String(open)) => {
+                    self.point = open;
+                    return Ok(());
+                }
+                _ => {}
+            }
+            match self.syntax.class(ch) {
+                Class::Close(_) => {
+                    self.point = self
+                        .matching_paren(pos)
+                        .ok_or_else(|| std::io::Error::other("Unbalanced parentheses"))?;
+                    return Ok(());
+                }
+                Class::Open(_) => {
+                    return Err(std::io::Error::other(
+                        "Containing expression ends prematurely",
+                    ))
+                }
+                Class::Word | Class::Symbol => {
+                    let mut first = pos;
+                    while let Some((pos, _, _)) = events.next_if(|(_, ch, prev)| {
+                        *prev == ctx
+                            && matches!(self.syntax.class(*ch), Class::Word | Class::Symbol)
+                    }) {
+                        first = pos;
+                    }
+                    self.point = first;
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        self.point = start;
+        Ok(())
+    }
+
+    /// True when line holds only whitespace. Lines past the end count as blank.
+    fn is_blank_line(&self, line: usize) -> bool {
+        line >= self.rope.len_lines() || self.rope.line(line).chars().all(char::is_whitespace)
+    }
+
+    /// Word, sentence and paragraph motion only move through text held in the rope.
+    fn rope_motion(&self) -> bool {
+        self.hexl.is_none() && self.large.is_none()
+    }
+
+    pub fn delete_backward_char(&mut self) {
+        let end = self.point;
+        self.backward_char();
+        if self.point < end {
+            let (line, before) = (self.rope.char_to_line(self.point), self.rope.len_lines());
+            self.signs.edited(self.point, end - self.point, 0);
+            self.rope.remove(self.point..end);
+            self.lines_edited(line, before);
+            self.changed();
+        }
+    }
+
+    /// Length of line in chars, not counting its line break.
+    fn line_len(&self, line: usize) -> usize {
+        let slice = self.rope.line(line);
+        let len = slice.len_chars();
+        match (
+            len.checked_sub(2).map(|i| slice.char(i)),
+            len.checked_sub(1).map(|i| slice.char(i)),
+        ) {
+            (Some('\r'), Some('\n')) => len - 2,
+            (_, Some('\n' | '\r')) => len - 1,
+            _ => len,
+        }
+    }
+
+    pub fn update_doc_info(&mut self) {
+        self.char_count = self.rope.len_chars();
+        self.line_count = self.rope.len_lines()
+    }
+
+    /// Inserts text at point and moves point past it.
+    pub fn insert(&mut self, text: &str) {
+        let (line, before) = (self.rope.char_to_line(self.point), self.rope.len_lines());
+        self.signs.edited(self.point, 0, text.chars().count());
+        self.rope.insert(self.point, text);
+        self.lines_edited(line, before);
+        self.point += text.chars().count();
+        self.changed();
+    }
+
+    pub fn remove<R>(&mut self, char_range: R)
+    where
+        R: RangeBounds<usize>,
+    {
+        let start = match char_range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match char_range.end_bound() {
+            Bound::Included(end) => end + 1,
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => self.rope.len_chars(),
+        };
+        let (line, before) = (self.rope.char_to_line(start), self.rope.len_lines());
+        self.signs.edited(start, end.saturating_sub(start), 0);
+        self.rope.remove(char_range);
+        self.lines_edited(line, before);
+        self.point = self.point.min(self.rope.len_chars());
+        self.changed();
+    }
+
+    /// Tells the highlighter about an edit that started on line, when the buffer had
+    /// before lines.
+    fn lines_edited(&mut self, line: usize, before: usize) {
+        let after = self.rope.len_lines();
+        self.highlighter.get_mut().edited(line, before, after);
+    }
+
+    /// The highlighting of line. Views other than the text itself, like hexl and
+    /// dired, are not highlighted.
+    pub fn highlights(&self, line: usize) -> Vec<Token> {
+        if !self.rope_motion() || self.dired.is_some() {
+            return Vec::new();
+        }
+        self.highlighter.borrow_mut().tokens(&self.rope, line)
+    }
+
+    pub fn language(&self) -> Language {
+        self.highlighter.borrow().language()
+    }
+
+    /// The major mode, as the mode line shows it: the view the buffer is in, or the
+    /// language it is highlighted as.
+    pub fn mode_name(&self) -> std::string::String {
+        if self.dired.is_some() {
+            "Dired".to_string()
+        } else if self.hexl.is_some() {
+            "Hexl".to_string()
+        } else if self.large.is_some() {
+            "Large".to_string()
+        } else {
+            self.language().to_string()
+        }
+    }
+
+    fn changed(&mut self) {
+        self.modified = true;
+        self.auto_saved = false;
+        self.diff_stale = self.diff_base.is_some();
+    }
+
+    /// The gutter signs of the buffer.
+    pub fn signs(&self) -> &Signs {
+        &self.signs
+    }
+
+    /// Replaces the signs in column, given by line. Diagnostics and other subsystems
+    /// that mark lines publish their signs through this.
+    pub fn set_signs<I>(&mut self, column: SignColumn, signs: I)
+    where
+        I: IntoIterator<Item = (usize, SignKind)>,
+    {
+        self.signs.set_column(&self.rope, column, signs);
+    }
+
+    /// Bookmarks point's line, or removes its bookmark. Returns true when the line is
+    /// bookmarked afterwards.
+    pub fn toggle_bookmark(&mut self) -> bool {
+        let line = self.point_line();
+        self.signs.toggle(&self.rope, line, SignKind::Bookmark)
+    }
+
+    /// Moves point to the start of the next bookmarked line, going round to the first
+    /// after the last. Returns false when there are no bookmarks.
+    pub fn next_bookmark(&mut self) -> bool {
+        let lines = self.signs.lines_with(&self.rope, SignKind::Bookmark);
+        let point_line = self.point_line();
+        match lines
+            .iter()
+            .find(|line| **line > point_line)
+            .or(lines.first())
+        {
+            Some(line) => {
+                self.point = self.rope.line_to_char(*line);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn diff_signs(&self) -> bool {
+        self.diff_base.is_some()
+    }
+
+    /// The directory the buffer's file is in, or that a dired buffer lists.
+    pub fn dir(&self) -> Option<PathBuf> {
+        match &self.dired {
+            Some(dired) => Some(dired.dir().to_path_buf()),
+            None => self.path().and_then(|path| path.parent()).map(|dir| {
+                match dir.as_os_str().is_empty() {
+                    true => PathBuf::from("."),
+                    false => dir.to_path_buf(),
+                }
+            }),
+        }
+    }
+
+    pub fn git_branch(&self) -> Option<&str> {
+        self.git_branch.as_deref()
+    }
+
+    /// Reads the git branch of the buffer's directory again. Returns true when it
+    /// changed.
+    pub fn refresh_git_branch(&mut self) -> bool {
+        let branch = self.dir().and_then(|dir| modeline::git_branch(&dir));
+        let changed = branch != self.git_branch;
+        self.git_branch = branch;
+        changed
+    }
+
+    /// Turns diff signs on or off. They compare the buffer with the file as committed
+    /// in git, or as it is on disk when git doesn't know it; a new file is all added.
+    pub fn set_diff_signs(&mut self, on: bool) {
+        self.diff_base = match (on, self.path()) {
+            (false, _) | (_, None) => None,
+            (true, Some(path)) => Some(
+                gutter::git_head_text(path)
+                    .or_else(|| {
+                        let bytes = std::fs::read(path).ok()?;
+                        Some(match self.encoding.decode(&bytes) {
+                            Some(text) => text,
+                            None => encoding::decode(&bytes).text,
+                        })
+                    })
+                    .unwrap_or_default(),
+            ),
+        };
+        self.diff_stale = self.diff_base.is_some();
+        self.signs.clear(SignColumn::Diff);
+        self.update_diff_signs();
+    }
+
+    /// Compares the buffer with the diff base again after edits.
+    pub fn update_diff_signs(&mut self) {
+        if !self.diff_stale {
+            return;
+        }
+        self.diff_stale = false;
+        if let Some(base) = &self.diff_base {
+            // Only the text of lines counts, as for git with autocrlf: the base gets the
+            // buffer's line breaks and both are split at them.
+            let base = self.eol.convert(base);
+            let signs = gutter::diff_signs(&base, &self.rope.to_string(), self.eol.as_str());
+            self.signs.set_column(&self.rope, SignColumn::Diff, signs);
+        }
+    }
+
+    /// True when edits since the last update_diff_signs left the diff signs behind.
+    pub fn diff_stale(&self) -> bool {
+        self.diff_stale
+    }
+
+    /// True when the buffer has changes that are not saved.
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    /// False for buffers like *stdin* that were not read from a file.
+    pub fn has_file(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn auto_save_path(&self) -> Option<PathBuf> {
+        self.path().map(autosave::auto_save_path)
+    }
+
+    /// True when the buffer changed since it was last saved or auto-saved.
+    pub fn needs_auto_save(&self) -> bool {
+        self.modified && !self.auto_saved && self.has_file()
+    }
+
+    /// Writes the buffer to its #file# auto-save file in the buffer's encoding. The
+    /// visited file is not touched.
+    pub fn auto_save(&mut self) -> std::io::Result<()> {
+        let bytes = self.contents()?;
+        let path = self.visited_path()?;
+        // No more readable than the visited file and never by others, but always
+        // writable by the owner so the next auto-save can replace it.
+        let mode = std::fs::metadata(path).map_or(0o600, |meta| meta.mode() & 0o600 | 0o200);
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(mode)
+            .open(autosave::auto_save_path(path))?;
+        // mode only applies when the file is created; an older #file# may be looser.
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+        file.write_all(&bytes)?;
+        self.auto_saved = true;
+        Ok(())
+    }
+
+    /// Replaces the buffer text with the contents of its auto-save file. The buffer
+    /// counts as modified until it is saved.
+    pub fn recover_auto_save(&mut self) -> std::io::Result<()> {
+        let bytes = std::fs::read(autosave::auto_save_path(self.visited_path()?))?;
+        let (text, encoding) = match self.encoding.decode(&bytes) {
+            Some(text) => (text, self.encoding),
+            None => {
+                let decoded = encoding::decode(&bytes);
+                (decoded.text, decoded.encoding)
+            }
+        };
+        let rope = Rope::from_str(text.strip_prefix('\u{FEFF}').unwrap_or(&text));
+        let fresh = Self::with_rope(self.path.clone(), None, rope, encoding, self.bom);
+        self.load(fresh);
+        self.modified = true;
+        Ok(())
+    }
+}
+
+/// Line break style of a buffer. The rope keeps the line breaks that were on disk, so
+/// saving writes them back unchanged.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Eol {
+    #[default]
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl Eol {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Eol::Lf => "\n",
+            Eol::CrLf => "\r\n",
+            Eol::Cr => "\r",
+        }
+    }
+
+    /// The most common line break in rope. Files without any line break are LF.
+    pub fn detect(rope: &Rope) -> Eol {
+        let mut counts = [0usize; 3];
+        let mut chars = rope.chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\n' => counts[0] += 1,
+                '\r' if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                    counts[1] += 1;
+                }
+                '\r' => counts[2] += 1,
+                _ => {}
+            }
+        }
+        match counts {
+            [lf, crlf, cr] if crlf > lf && crlf >= cr => Eol::CrLf,
+            [lf, crlf, cr] if cr > lf && cr > crlf => Eol::Cr,
+            _ => Eol::Lf,
+        }
+    }
+
+    /// Replaces every LF, CRLF and CR in text with self.
+    pub fn convert(&self, text: &str) -> std::string::String {
+        let mut out = std::string::String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\r' => {
+                    if chars.peek() == Some(&'\n') {
+                        chars.next();
+                    }
+                    out.push_str(self.as_str());
+                }
+                '\n' => out.push_str(self.as_str()),
+                ch => out.push(ch),
+            }
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for Eol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Eol::Lf => write!(f, "LF"),
+            Eol::CrLf => write!(f, "CRLF"),
+            Eol::Cr => write!(f, "CR"),
+        }
+    }
+}
+
+impl std::str::FromStr for Eol {
+    type Err = std::string::String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lf" | "unix" => Ok(Eol::Lf),
+            "crlf" | "dos" => Ok(Eol::CrLf),
+            "cr" | "mac" => Ok(Eol::Cr),
+            _ => Err(format!("Unknown line ending: {}", s)),
+        }
+    }
+}
+
+/// What save_file does with the line break at the end of the buffer.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum FinalNewline {
+    #[default]
+    Keep,
+    Ensure,
+    Strip,
+}
+
+impl std::str::FromStr for FinalNewline {
+    type Err = std::string::String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "keep" => Ok(FinalNewline::Keep),
+            "ensure" => Ok(FinalNewline::Ensure),
+            "strip" => Ok(FinalNewline::Strip),
+            _ => Err(format!("Unknown final newline setting: {}", s)),
+        }
+    }
+}
+
+/// Point of a large-file view, kept as a line and column because there is no rope
+/// to index into.
+#[derive(Debug)]
+struct LargeView {
+    file: LargeFile,
+    line: usize,
+    col: usize,
+}
+
+impl LargeView {
+    fn line_len(&self, line: usize) -> usize {
+        self.file.line(line).map_or(0, |bytes| {
+            std::string::String::from_utf8_lossy(bytes).chars().count()
+        })
+    }
+
+    /// Moves to line and col, both 0-based, clamped to the file.
+    fn goto(&mut self, line: usize, col: usize) {
+        self.line = match self.file.line_start(line) {
+            Some(_) => line,
+            None => self.file.count_lines() - 1,
+        };
+        self.col = col.min(self.line_len(self.line));
+    }
+
+    fn search_forward(&mut self, needle: &str) -> bool {
+        let Some(start) = self.file.line_start(self.line) else {
+            return false;
+        };
+        let line = self.file.line(self.line).unwrap_or_default();
+        let from = start
+            + std::string::String::from_utf8_lossy(line)
+                .chars()
+                .take(self.col)
+                .map(char::len_utf8)
+                .sum::<usize>()
+                .min(line.len());
+        let Some(found) = self.file.find(needle.as_bytes(), from) else {
+            return false;
+        };
+        let end = found + needle.len();
+        self.line = self.file.line_of_offset(end);
+        let line_start = self.file.line_start(self.line).unwrap_or(end);
+        let before = &self.file.bytes()[line_start..end];
+        self.col = std::string::String::from_utf8_lossy(before).chars().count();
+        true
+    }
+}
+
+/// What a buffer remembers about its file to notice changes made behind its back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    ino: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<FileStamp> {
+        std::fs::metadata(path)
+            .ok()
+            .map(|meta| FileStamp::from(&meta))
+    }
+}
+
+impl From<&std::fs::Metadata> for FileStamp {
+    fn from(meta: &std::fs::Metadata) -> Self {
+        FileStamp {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+            ino: meta.ino(),
+        }
+    }
+}
+
+/// Renames buffers visiting files with the same name after the closest directories
+/// that tell them apart, "main.rs<src>" and "main.rs<tests>". A buffer whose file
+/// name is unique is just called by it again.
+pub fn uniquify_buffer_names(docs: &mut [Document]) {
+    let dirs: Vec<Vec<std::string::String>> = docs
+        .iter()
+        .map(|doc| match doc.path() {
+            Some(path) => absolute_path(path)
+                .parent()
+                .map(|dir| {
+                    dir.components()
+                        .rev()
+                        .map(|part| part.as_os_str().to_string_lossy().to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            None => Vec::new(),
+        })
+        .collect();
+    let base: Vec<Option<std::string::String>> = docs
+        .iter()
+        .map(|doc| doc.path().map(Document::name_for_path))
+        .collect();
+
+    for idx in 0..docs.len() {
+        let Some(name) = &base[idx] else {
+            continue;
+        };
+        let same: Vec<usize> = (0..docs.len())
+            .filter(|other| base[*other].as_ref() == Some(name))
+            .collect();
+        if same.len() == 1 {
+            docs[idx].buffer_name = name.clone();
+            continue;
+        }
+        // Take one more directory until no other buffer with this name ends in the
+        // same directories.
+        let suffix = |other: usize, depth: usize| -> Vec<&std::string::String> {
+            dirs[other].iter().take(depth).collect()
+        };
+        let mut depth = 1;
+        while depth < dirs[idx].len()
+            && same
+                .iter()
+                .any(|other| *other != idx && suffix(*other, depth) == suffix(idx, depth))
+        {
+            depth += 1;
+        }
+        let mut parts = suffix(idx, depth);
+        parts.reverse();
+        let parts: Vec<&str> = parts.iter().map(|part| part.as_str()).collect();
+        docs[idx].buffer_name = format!("{}<{}>", name, parts.join("/"));
+    }
+}
+
+fn absolute_path(path: &Path) -> PathBuf {
+    if let Ok(path) = std::fs::canonicalize(path) {
+        return path;
+    }
+    match std::env::current_dir() {
+        Ok(dir) if path.is_relative() => dir.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Writes bytes to the file at path the way a save does. A symlink is followed so its
+/// target gets the new contents. A file with more than one hard link is overwritten
+/// in place so every link sees them. Otherwise the bytes go to a temporary file that
+/// takes the mode and owner of the old file and is then renamed over it.
+fn write_file(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let target = resolve_symlinks(path);
+    let meta = std::fs::metadata(&target).ok();
+    if meta.as_ref().is_some_and(|meta| meta.nlink() > 1) {
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&target)?;
+        file.write_all(bytes)?;
+        return file.sync_all();
+    }
+    let tmp_path = save_tmp_path(&target);
+    let write = || -> std::io::Result<()> {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            // Private until the old file's mode is copied over; a new file gets the
+            // usual mode less the umask.
+            .mode(if meta.is_some() { 0o600 } else { 0o666 })
+            .open(&tmp_path)?;
+        if let Some(meta) = &meta {
+            // Only root can give a file away, so a failed chown keeps the saver's own.
+            let _ = std::os::unix::fs::fchown(&file, Some(meta.uid()), Some(meta.gid()));
+            file.set_permissions(meta.permissions())?;
+        }
+        let mut buf_writer = BufWriter::new(file);
+        buf_writer.write_all(bytes)?;
+        buf_writer.flush()?;
+        buf_writer.get_ref().sync_all()?;
+        std::fs::rename(&tmp_path, &target)
+    };
+    write().inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp_path);
+    })
+}
+
+/// The file path ends up at after following symlinks, also when the last link points
+/// to a file that doesn't exist yet.
+fn resolve_symlinks(path: &Path) -> PathBuf {
+    if let Ok(target) = std::fs::canonicalize(path) {
+        return target;
+    }
+    match std::fs::read_link(path) {
+        Ok(link) => path.parent().unwrap_or(Path::new("")).join(link),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+fn save_tmp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.mr_text-save", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+    use crate::theme::Face;
+
+    #[test]
+    fn test_load_file() {
+        let doc = Document::open_doc("./text.txt").unwrap();
+        assert_eq!(20, doc.line_count);
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let dir = scratch_dir("ro");
+        let path = dir.join("ro.txt");
+        assert!(Document::open_doc(&path).is_err());
+        assert!(!path.exists());
+
+        std::fs::write(&path, "locked\n").unwrap();
+        let mut perms = std::fs::metadata(&path).unwrap().permissions();
+        perms.set_readonly(true);
+        std::fs::set_permissions(&path, perms).unwrap();
+        let doc = Document::find_file(&path).unwrap();
+        assert_eq!(doc.rope().to_string(), "locked\n");
+        // access(2) lets root write anything.
+        let root = unsafe { libc::geteuid() } == 0;
+        assert_eq!(doc.is_read_only(), !root);
+        assert_eq!(doc.is_write_protected(), !root);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_buffer_names() {
+        let doc = Document::find_file(Path::new("./archive.tar.gz")).unwrap();
+        assert_eq!(doc.path(), Some(Path::new("./archive.tar.gz")));
+        assert_eq!(doc.buffer_name(), "archive.tar.gz");
+        assert_eq!(doc.extension().as_deref(), Some("gz"));
+        let dotfile = Document::find_file(Path::new(".gitignore")).unwrap();
+        assert_eq!(dotfile.buffer_name(), ".gitignore");
+        assert_eq!(dotfile.extension(), None);
+        let plain = Document::find_file(Path::new("Makefile")).unwrap();
+        assert_eq!(plain.path(), Some(Path::new("Makefile")));
+        let stdin = Document::from_reader("*stdin*", "".as_bytes()).unwrap();
+        assert_eq!((stdin.buffer_name(), stdin.path()), ("*stdin*", None));
+
+        let mut docs: Vec<Document> = ["/p/src/main.rs", "/p/tests/main.rs", "/q/src/main.rs"]
+            .iter()
+            .map(|path| Document::find_file(Path::new(path)).unwrap())
+            .collect();
+        docs.push(doc);
+        docs.push(stdin);
+        uniquify_buffer_names(&mut docs);
+        let names: Vec<&str> = docs.iter().map(|doc| doc.buffer_name()).collect();
+        assert_eq!(
+            names,
+            [
+                "main.rs<p/src>",
+                "main.rs<tests>",
+                "main.rs<q/src>",
+                "archive.tar.gz",
+                "*stdin*"
+            ]
+        );
+        docs.remove(1);
+        docs.remove(1);
+        uniquify_buffer_names(&mut docs);
+        assert_eq!(docs[0].buffer_name(), "main.rs");
+    }
+
+    #[test]
+    fn test_large_file_view() {
+        let dir = scratch_dir("view");
+        let path = dir.join("view.log");
+        let text: std::string::String = (1..=1000).map(|n| format!("entry {}\n", n)).collect();
+        std::fs::write(&path, &text).unwrap();
+
+        let mut doc = Document::open_large(&path).unwrap();
+        assert!(doc.is_large() && doc.is_read_only());
+        assert!(doc.rope().len_chars() == 0);
+        assert_eq!(doc.line_text(9).as_deref(), Some("entry 10"));
+        doc.goto_line_col(500, 3);
+        assert_eq!((doc.point_line(), doc.point_col()), (499, 2));
+        assert!(doc.known_lines() >= 500);
+        doc.move_lines(10_000);
+        assert_eq!(doc.point_line(), 1000);
+        assert_eq!(doc.known_lines(), 1001);
+        doc.goto_line_col(1, 1);
+        assert!(doc.search_forward("entry 77\n"));
+        assert_eq!((doc.point_line(), doc.point_col()), (77, 0));
+        assert!(!doc.search_forward("missing"));
+        assert!(doc.save_file().is_err());
+
+        doc.goto_line_col(3, 2);
+        doc.edit_whole_file().unwrap();
+        assert!(!doc.is_large() && !doc.is_read_only());
+        assert_eq!(doc.rope().to_string(), text);
+        assert_eq!((doc.point_line(), doc.point_col()), (2, 1));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_hexl_round_trip() {
+        let dir = scratch_dir("hexl");
+        let path = dir.join("hexl.bin");
+        let bytes = b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0\xff\xfe\r\n";
+        std::fs::write(&path, bytes).unwrap();
+        let mut doc = Document::find_file(&path).unwrap();
+        assert!(doc.looks_binary());
+
+        doc.hexl_mode().unwrap();
+        assert_eq!(
+            doc.line_text(0).as_deref(),
+            Some("00000000: 7f45 4c46 0201 0100 0000 0000 0000 0000  .ELF............")
+        );
+        doc.goto_line_col(2, 1);
+        assert!(doc.hexl_input('0') && doc.hexl_input('1'));
+        assert!(!doc.hexl_input('x'));
+        assert!(doc.is_modified());
+        doc.save_file().unwrap();
+        let mut expected = bytes.to_vec();
+        expected[16] = 0x01;
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+
+        doc.hexl_exit();
+        assert!(!doc.is_hexl());
+        doc.save_file().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_dired_buffer() {
+        let dir = scratch_dir("dired-doc");
+        std::fs::write(dir.join("a.txt"), "a").unwrap();
+
+        let mut doc = Document::find_file(&dir).unwrap();
+        assert!(doc.dired().is_some() && doc.is_read_only() && !doc.has_file());
+        assert!(doc.visits(&dir));
+        assert_eq!(
+            doc.buffer_name(),
+            format!("mr_text-dired-doc-{}/", std::process::id())
+        );
+        let entry = doc
+            .dired()
+            .unwrap()
+            .entry_at_line(doc.point_line())
+            .unwrap();
+        assert_eq!(entry.name, "a.txt");
+
+        std::fs::write(dir.join("b.txt"), "b").unwrap();
+        doc.with_dired(Dired::refresh).unwrap().unwrap();
+        assert!(doc.rope().to_string().ends_with(" b.txt\n"));
+        assert_eq!(doc.point_line(), dired::HEADER_LINES + 1);
+        assert!(!doc.is_modified());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_find_new_file() {
+        let dir = scratch_dir("new");
+        let path = dir.join("new.txt");
+        let mut doc = Document::find_file(&path).unwrap();
+        assert!(!path.exists());
+        assert!(doc.visits(&path));
+
+        doc.insert("hello\n");
+        doc.save_file().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello\n");
+        doc.insert("again\n");
+        doc.save_file().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello\nagain\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_eol_round_trip() {
+        let dir = scratch_dir("eol");
+        let path = dir.join("eol.txt");
+        let bytes = b"\xEF\xBB\xBFone\r\ntwo\r\nthree";
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut doc = Document::find_file(&path).unwrap();
+        assert_eq!(doc.eol(), Eol::CrLf);
+        assert!(doc.has_bom());
+        assert_eq!(doc.rope().char(0), 'o');
+        doc.save_file().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+
+        doc.goto_line_col(1, 4);
+        doc.newline();
+        assert_eq!(doc.rope().line(1).to_string(), "\r\n");
+        doc.delete_backward_char();
+        doc.forward_char();
+        assert_eq!((doc.point_line(), doc.point_col()), (1, 0));
+
+        doc.set_final_newline(FinalNewline::Ensure);
+        doc.save_file().unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"\xEF\xBB\xBFone\r\ntwo\r\nthree\r\n"
+        );
+
+        doc.convert_eol(Eol::Lf);
+        doc.set_bom(false);
+        doc.set_final_newline(FinalNewline::Strip);
+        doc.save_file().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"one\ntwo\nthree");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_encoding_round_trip() {
+        let dir = scratch_dir("enc");
+        let path = dir.join("enc.log");
+        let bytes = b"ok \xE2\x9C\x93\n\x00\xFFjunk\xC3\n";
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut doc = Document::find_file(&path).unwrap();
+        assert_eq!(doc.encoding(), Encoding::Utf8Raw);
+        doc.goto_line_col(1, 1);
+        doc.insert("edited ");
+        doc.save_file().unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"edited ok \xE2\x9C\x93\n\x00\xFFjunk\xC3\n"
+        );
+
+        doc.set_encoding(Encoding::Latin1);
+        assert!(doc.save_file().is_err());
+        assert!(doc.save_failed());
+        assert_eq!(std::fs::read(&path).unwrap()[..6], *b"edited");
+        doc.set_encoding(Encoding::Utf8Raw);
+        doc.save_file().unwrap();
+        assert!(!doc.save_failed());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_save_keeps_mode_and_links() {
+        let dir = scratch_dir("links");
+        let script = dir.join("run.sh");
+        std::fs::write(&script, "echo one\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o700)).unwrap();
+        let mut doc = Document::find_file(&script).unwrap();
+        doc.insert("# ");
+        doc.save_file().unwrap();
+        let meta = std::fs::metadata(&script).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o700);
+        assert_eq!(std::fs::read_to_string(&script).unwrap(), "# echo one\n");
+
+        // Saving through a symlink edits its target and leaves the link in place.
+        let link = dir.join("link.sh");
+        std::os::unix::fs::symlink("run.sh", &link).unwrap();
+        let mut doc = Document::find_file(&link).unwrap();
+        doc.insert("#");
+        doc.save_file().unwrap();
+        assert!(std::fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(std::fs::read_to_string(&script).unwrap(), "## echo one\n");
+
+        // A hard link sees the new contents too.
+        let hard = dir.join("hard.sh");
+        std::fs::hard_link(&script, &hard).unwrap();
+        let mut doc = Document::find_file(&hard).unwrap();
+        doc.insert("!");
+        doc.save_file().unwrap();
+        assert_eq!(std::fs::read_to_string(&script).unwrap(), "!## echo one\n");
+        assert_eq!(std::fs::metadata(&script).unwrap().nlink(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_auto_save_and_recover() {
+        let dir = scratch_dir("recover");
+        let path = dir.join("recover.txt");
+        std::fs::write(&path, "saved\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut doc = Document::find_file(&path).unwrap();
+        assert!(!doc.needs_auto_save());
+
+        doc.insert("unsaved ");
+        assert!(doc.is_modified() && doc.needs_auto_save());
+        doc.auto_save().unwrap();
+        assert!(!doc.needs_auto_save());
+        let auto_save = doc.auto_save_path().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&auto_save).unwrap(),
+            "unsaved saved\n"
+        );
+        assert_eq!(std::fs::metadata(&auto_save).unwrap().mode() & 0o777, 0o600);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "saved\n");
+
+        // The recovered text brings its own line ending and counts.
+        std::fs::write(&auto_save, "unsaved\r\nsaved\r\n").unwrap();
+        let mut reopened = Document::find_file(&path).unwrap();
+        reopened.recover_auto_save().unwrap();
+        assert_eq!(reopened.rope().to_string(), "unsaved\r\nsaved\r\n");
+        assert_eq!(reopened.eol(), Eol::CrLf);
+        assert_eq!(reopened.line_count, 3);
+        assert!(reopened.is_modified());
+        reopened.save_file().unwrap();
+        assert!(!reopened.is_modified());
+        assert!(!reopened.auto_save_path().unwrap().exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_backup_on_first_save() {
+        let dir = scratch_dir("backup");
+        let path = dir.join("backup.txt");
+        let backup = dir.join("backup.txt~");
+        std::fs::write(&path, "original\n").unwrap();
+        let mut doc = Document::find_file(&path).unwrap();
+        doc.set_backup_config(BackupConfig {
+            enabled: true,
+            ..BackupConfig::default()
+        });
+
+        doc.insert("first ");
+        doc.save_file().unwrap();
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "original\n");
+        doc.insert("second ");
+        doc.save_file().unwrap();
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "original\n");
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "first second original\n"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_changed_on_disk_and_revert() {
+        let dir = scratch_dir("revert");
+        let path = dir.join("revert.txt");
+        std::fs::write(&path, "one\ntwo\n").unwrap();
+        let mut doc = Document::find_file(&path).unwrap();
+        assert!(!doc.changed_on_disk());
+        doc.goto_line_col(2, 2);
+
+        std::fs::write(&path, "one\nTWO!\nthree\n").unwrap();
+        assert!(doc.changed_on_disk());
+        doc.revert().unwrap();
+        assert!(!doc.changed_on_disk() && !doc.is_modified());
+        assert_eq!(doc.rope().to_string(), "one\nTWO!\nthree\n");
+        assert_eq!((doc.point_line(), doc.point_col()), (1, 1));
+
+        // Signs about the old text go; bookmarks keep their lines within the new.
+        doc.set_signs(SignColumn::Diagnostic, [(0, SignKind::Error)]);
+        doc.goto_line_col(4, 1);
+        assert!(doc.toggle_bookmark());
+        std::fs::write(&path, "one\nTWO!\n").unwrap();
+        doc.revert().unwrap();
+        assert_eq!(doc.signs().columns(), [SignColumn::Bookmark]);
+        assert_eq!(doc.signs().lines_with(doc.rope(), SignKind::Bookmark), [2]);
+        doc.convert_eol(Eol::Cr);
+        assert_eq!(doc.signs().lines_with(doc.rope(), SignKind::Bookmark), [2]);
+        doc.goto_line_col(2, 1);
+
+        doc.insert("x");
+        doc.save_file().unwrap();
+        assert!(!doc.changed_on_disk());
+        std::fs::remove_file(&path).unwrap();
+        assert!(doc.changed_on_disk());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_auto_revert_tail() {
+        let dir = scratch_dir("tail");
+        let path = dir.join("tail.log");
+        std::fs::write(&path, "start\n").unwrap();
+        let mut doc = Document::find_file(&path).unwrap();
+        doc.set_auto_revert(true);
+        doc.goto_line_col(2, 1);
+        assert!(!doc.auto_revert_now().unwrap());
+
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        log.write_all(b"second\nthi").unwrap();
+        assert!(doc.auto_revert_now().unwrap());
+        assert_eq!(doc.rope().to_string(), "start\nsecond\n");
+        assert_eq!(doc.point(), doc.rope().len_chars());
+
+        log.write_all(b"rd\n").unwrap();
+        assert!(doc.auto_revert_now().unwrap());
+        assert_eq!(doc.rope().to_string(), "start\nsecond\nthird\n");
+        assert!(!doc.is_modified());
+
+        // A truncated (rotated) log is read again from the start.
+        std::fs::write(&path, "new\n").unwrap();
+        assert!(doc.auto_revert_now().unwrap());
+        assert_eq!(doc.rope().to_string(), "new\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_detect_eol() {
+        assert_eq!(Eol::detect(&Rope::from_str("a\nb\r\nc\n")), Eol::Lf);
+        assert_eq!(Eol::detect(&Rope::from_str("a\r\nb\r\nc\n")), Eol::CrLf);
+        assert_eq!(Eol::detect(&Rope::from_str("a\rb\rc")), Eol::Cr);
+        assert_eq!(Eol::detect(&Rope::from_str("no breaks")), Eol::Lf);
+        assert_eq!(Eol::Cr.convert("a\r\nb\nc\r"), "a\rb\rc\r");
+    }
+
+    #[test]
+    fn test_goto_line_col() {
+        let mut doc = Document::from_reader("*stdin*", "one\ntwo\r\nthree".as_bytes()).unwrap();
+        doc.goto_line_col(2, 2);
+        assert_eq!((doc.point_line(), doc.point_col()), (1, 1));
+        doc.goto_line_col(2, 80);
+        assert_eq!((doc.point_line(), doc.point_col()), (1, 3));
+        doc.goto_line_col(99, 1);
+        assert_eq!((doc.point_line(), doc.point_col()), (2, 0));
+    }
+
+    #[test]
+    fn test_grapheme_motion() {
+        let text = "e\u{301}t\u{e9}\r\n日本x\nab\u{1f468}\u{200d}\u{1f469}c\n";
+        let mut doc = Document::from_reader("*stdin*", text.as_bytes()).unwrap();
+        doc.forward_char();
+        assert_eq!(doc.point_col(), 2);
+        doc.forward_char();
+        doc.forward_char();
+        assert_eq!(doc.point_col(), 4);
+        // CRLF is one step.
+        doc.forward_char();
+        assert_eq!((doc.point_line(), doc.point_col()), (1, 0));
+        doc.backward_char();
+        assert_eq!((doc.point_line(), doc.point_col()), (0, 4));
+
+        // Line motion keeps the screen column: past the wide "日" is column 2.
+        doc.goto_line_col(2, 2);
+        doc.move_lines(1);
+        assert_eq!((doc.point_line(), doc.point_col()), (2, 2));
+        doc.move_lines(-1);
+        assert_eq!(doc.point_col(), 1);
+        // The end of the first line is column 3, the second half of the emoji two lines
+        // down, so point goes to the start of the emoji.
+        doc.goto_line_col(1, 5);
+        doc.move_lines(2);
+        assert_eq!((doc.point_line(), doc.point_col()), (2, 2));
+
+        // Deleting backward removes the whole emoji sequence.
+        doc.goto_line_col(3, 6);
+        doc.delete_backward_char();
+        assert_eq!(doc.line_text(2).unwrap(), "abc");
+        // A column inside a cluster is moved to its start.
+        doc.goto_line_col(1, 2);
+        assert_eq!(doc.point_col(), 0);
+    }
+
+    fn sample() -> Document {
+        Document::from_reader("text.txt", include_str!("text.txt").as_bytes()).unwrap()
+    }
+
+    fn point_text(doc: &Document, len: usize) -> std::string::String {
+        doc.rope.chars_at(doc.point).take(len).collect()
+    }
+
+    #[test]
+    fn test_word_motion() {
+        let mut doc = sample();
+        doc.forward_word();
+        doc.forward_word();
+        assert_eq!(doc.point, "In a".len());
+        doc.backward_word();
+        assert_eq!(point_text(&doc, 8), "a previo");
+        // In text "we’ll" is one word.
+        doc.goto_line_col(2, 23);
+        doc.forward_word();
+        assert_eq!(point_text(&doc, 10), " go one st");
+        doc.backward_word();
+        assert_eq!(point_text(&doc, 5), "we’ll");
+
+        // In code "_" splits words.
+        let mut code = Document::with_rope(
+            Some(PathBuf::from("a.rs")),
+            None,
+            Rope::from_str("let snake_case = 1;"),
+            Encoding::Utf8,
+            false,
+        );
+        code.forward_word();
+        code.forward_word();
+        assert_eq!(code.point, "let snake".len());
+        code.end_of_line();
+        code.backward_word();
+        code.backward_word();
+        assert_eq!(code.point, "let snake_".len());
+    }
+
+    fn rust_doc(text: &str) -> Document {
+        Document::with_rope(
+            Some(PathBuf::from("a.rs")),
+            None,
+            Rope::from_str(text),
+            Encoding::Utf8,
+            false,
+        )
+    }
+
+    #[test]
+    fn test_matching_paren() {
+        let doc = rust_doc("f(a[0], \"(]\") // )\n{ g(x) ]");
+        assert_eq!(doc.matching_paren(1), Some(12));
+        assert_eq!(doc.matching_paren(12), Some(1));
+        assert_eq!(doc.matching_paren(3), Some(5));
+        // Brackets in strings only match inside the string; comments have none.
+        assert_eq!(doc.find_match(9), Some(None));
+        assert_eq!(doc.find_match(10), Some(None));
+        assert_eq!(doc.find_match(17), None);
+        assert_eq!(doc.find_match(19), Some(None));
+        assert_eq!(doc.matching_paren(22), Some(24));
+        assert_eq!(doc.find_match(26), Some(None));
+
+        let mut doc = rust_doc("(a) [b");
+        assert_eq!(
+            doc.paren_match(),
+            Some(ParenMatch {
+                at: 0,
+                other: Some(2)
+            })
+        );
+        doc.point = 3;
+        assert_eq!(
+            doc.paren_match(),
+            Some(ParenMatch {
+                at: 2,
+                other: Some(0)
+            })
+        );
+        doc.point = 4;
+        assert_eq!(doc.paren_match(), Some(ParenMatch { at: 4, other: None }));
+        doc.point = 6;
+        assert_eq!(doc.paren_match(), None);
+    }
+
+    #[test]
+    fn test_sexp_motion() {
+        let mut doc = rust_doc("foo_bar (a \"b)\" [c]) \"x (y) z\" // w\nend");
+        doc.forward_sexp().unwrap();
+        assert_eq!(doc.point, 7);
+        doc.forward_sexp().unwrap();
+        assert_eq!(point_text(&doc, 2), " \"");
+        doc.forward_sexp().unwrap();
+        assert_eq!(point_text(&doc, 3), " //");
+        doc.forward_sexp().unwrap();
+        assert_eq!(doc.point, doc.rope.len_chars());
+        doc.backward_sexp().unwrap();
+        assert_eq!(point_text(&doc, 3), "end");
+        doc.backward_sexp().unwrap();
+        assert_eq!(point_text(&doc, 2), "\"x");
+        doc.backward_sexp().unwrap();
+        assert_eq!(point_text(&doc, 2), "(a");
+        doc.backward_sexp().unwrap();
+        assert_eq!(doc.point, 0);
+
+        // Inside a string, brackets in the string are expressions of their own.
+        doc.point = "foo_bar (a \"b)\" [c]) \"x".len();
+        doc.forward_sexp().unwrap();
+        assert_eq!(point_text(&doc, 2), " z");
+        doc.forward_sexp().unwrap();
+        assert!(doc.forward_sexp().is_err());
+        doc.backward_sexp().unwrap();
+        doc.backward_sexp().unwrap();
+        doc.backward_sexp().unwrap();
+        assert_eq!(point_text(&doc, 2), "x ");
+        assert!(doc.backward_sexp().is_err());
+
+        let mut doc = rust_doc("(a (b) c");
+        assert!(doc.forward_sexp().is_err());
+        assert_eq!(doc.point, 0);
+        doc.point = 5;
+        assert!(doc.forward_sexp().is_err());
+    }
+
+    #[test]
+    fn test_highlights() {
+        let mut doc = rust_doc("let a = 1;\nlet b = 2;\n");
+        assert_eq!(doc.language(), Language::Rust);
+        let faces = |doc: &Document, line| -> Vec<Face> {
+            doc.highlights(line)
+                .iter()
+                .map(|token| token.face)
+                .collect()
+        };
+        assert_eq!(faces(&doc, 1), [Face::Keyword, Face::Number]);
+        doc.insert("/* ");
+        assert_eq!(faces(&doc, 1), [Face::Comment]);
+        doc.goto_line_col(2, 1);
+        doc.insert("*/\n");
+        assert_eq!(faces(&doc, 1), [Face::Comment]);
+        assert_eq!(faces(&doc, 2), [Face::Keyword, Face::Number]);
+        doc.remove(0..3);
+        assert_eq!(faces(&doc, 0), [Face::Keyword, Face::Number]);
+        assert!(faces(&doc, 1).is_empty());
+        assert!(sample().highlights(0).is_empty());
+    }
+
+    #[test]
+    fn test_sentence_motion() {
+        let mut doc = sample();
+        doc.forward_sentence();
+        assert!(doc
+            .rope
+            .slice(..doc.point)
+            .to_string()
+            .ends_with("using epoll."));
+        doc.forward_sentence();
+        assert!(doc
+            .rope
+            .slice(..doc.point)
+            .to_string()
+            .ends_with("asynchronous I/O processing."));
+        doc.backward_sentence();
+        assert_eq!(point_text(&doc, 15), "This time aroun");
+        doc.backward_sentence();
+        assert_eq!(doc.point, 0);
+        // The closing quote belongs to the sentence.
+        doc.goto_line_col(4, 50);
+        doc.forward_sentence();
+        assert!(doc
+            .rope
+            .slice(..doc.point)
+            .to_string()
+            .ends_with("I do not understand.\""));
+        // The sentence after the last one of a paragraph starts past the blank line.
+        doc.goto_line_col(10, 30);
+        doc.forward_sentence();
+        assert!(doc
+            .rope
+            .slice(..doc.point)
+            .to_string()
+            .ends_with("modify those."));
+        doc.forward_sentence();
+        doc.backward_sentence();
+        assert_eq!(point_text(&doc, 8), "Alright,");
+
+        // The end of a paragraph ends a sentence without punctuation.
+        let mut doc = Document::from_reader("*stdin*", "a heading\n\nText.".as_bytes()).unwrap();
+        doc.forward_sentence();
+        assert_eq!(doc.point, 9);
+    }
+
+    #[test]
+    fn test_paragraph_and_line_motion() {
+        let mut doc = sample();
+        doc.goto_line_col(3, 10);
+        doc.forward_paragraph();
+        assert_eq!((doc.point_line(), doc.point_col()), (10, 0));
+        doc.forward_paragraph();
+        assert_eq!(doc.point_line(), 19);
+        doc.backward_paragraph();
+        assert_eq!((doc.point_line(), doc.point_col()), (10, 0));
+        doc.backward_paragraph();
+        assert_eq!(doc.point, 0);
+
+        doc.goto_line_col(3, 10);
+        doc.end_of_line();
+        assert_eq!(point_text(&doc, 1), "\n");
+        doc.beginning_of_line();
+        assert_eq!(doc.point_col(), 0);
+        doc.end_of_buffer();
+        assert_eq!(doc.point, doc.rope.len_chars());
+        doc.beginning_of_buffer();
+        assert_eq!(doc.point, 0);
+
+        let mut code =
+            Document::from_reader("*stdin*", "fn f() {\n    \tbody();\r\n}".as_bytes()).unwrap();
+        code.goto_line_col(2, 12);
+        code.back_to_indentation();
+        assert_eq!(code.point_col(), 5);
+        code.end_of_line();
+        assert_eq!(code.point_col(), 12);
+    }
+
+    #[test]
+    fn test_tabs() {
+        let mut doc = Document::from_reader("*stdin*", "\tx\nabcdefghij\n".as_bytes()).unwrap();
+        doc.set_tab_width(4);
+        // Column 4 is after the tab; the line below keeps it.
+        doc.forward_char();
+        doc.move_lines(1);
+        assert_eq!(doc.point_col(), 4);
+        // Column 3 of the second line is inside the tab of the first, so point moves to
+        // the tab's start.
+        doc.goto_line_col(2, 4);
+        doc.move_lines(-1);
+        assert_eq!(doc.point_col(), 0);
+
+        doc.goto_line_col(2, 2);
+        doc.insert_tab();
+        assert_eq!(doc.line_text(1).unwrap(), "a\tbcdefghij");
+        doc.set_indent_tabs(false);
+        doc.insert_tab();
+        assert_eq!(doc.line_text(1).unwrap(), "a\t    bcdefghij");
+        assert_eq!(doc.point_col(), 6);
+    }
+
+    #[test]
+    fn test_delete_trailing_whitespace() {
+        let mut doc =
+            Document::from_reader("*stdin*", "a  \n\t\nb\u{a0}c \t\r\nend ".as_bytes()).unwrap();
+        doc.goto_line_col(3, 5);
+        assert_eq!(doc.delete_trailing_whitespace(), 4);
+        assert_eq!(doc.rope().to_string(), "a\n\nb\u{a0}c\r\nend");
+        // Point was in the removed whitespace, so it ends up at the end of its line.
+        assert_eq!((doc.point_line(), doc.point_col()), (2, 3));
+        assert_eq!(doc.delete_trailing_whitespace(), 0);
+
+        let dir = scratch_dir("cleanup");
+        let path = dir.join("cleanup.txt");
+        std::fs::write(&path, "x \ny\t").unwrap();
+        let mut doc = Document::find_file(&path).unwrap();
+        doc.save_file().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "x \ny\t");
+        doc.set_whitespace_cleanup(true);
+        doc.save_file().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "x\ny\n");
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
@@ -0,0 +1,370 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use ropey::Rope;
+
+/// The on-disk encoding of a buffer. Text is always held as UTF-8 in the rope and
+/// converted back with encode when the buffer is saved.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// UTF-8 with bytes that are not valid UTF-8. Each such byte is kept in the rope
+    /// as a char from RAW_BYTE_BASE.. so it is written back unchanged.
+    Utf8Raw,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Windows1252,
+}
+
+/// Raw byte b is stored as RAW_BYTE_BASE + b. These are plane 16 private use chars,
+/// which real text practically never contains. Files that do contain them are read as
+/// Windows-1252 instead so the mapping stays unambiguous.
+pub const RAW_BYTE_BASE: u32 = 0x10_FF00;
+
+/// The result of decoding a file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Decoded {
+    pub text: std::string::String,
+    pub encoding: Encoding,
+    pub bom: bool,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 6] = [
+        Encoding::Utf8,
+        Encoding::Utf8Raw,
+        Encoding::Utf16Le,
+        Encoding::Utf16Be,
+        Encoding::Latin1,
+        Encoding::Windows1252,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf8Raw => "utf-8-raw",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin-1",
+            Encoding::Windows1252 => "windows-1252",
+        }
+    }
+
+    /// UTF-16 is the only supported encoding where a b'\n' byte is not always a line
+    /// break.
+    pub fn is_utf16(&self) -> bool {
+        matches!(*self, Encoding::Utf16Le | Encoding::Utf16Be)
+    }
+
+    fn bom_bytes(&self) -> &'static [u8] {
+        match *self {
+            Encoding::Utf8 | Encoding::Utf8Raw => &[0xEF, 0xBB, 0xBF],
+            Encoding::Utf16Le => &[0xFF, 0xFE],
+            Encoding::Utf16Be => &[0xFE, 0xFF],
+            Encoding::Latin1 | Encoding::Windows1252 => &[],
+        }
+    }
+
+    /// Decodes bytes with this encoding. Returns None when they are not valid in it.
+    pub fn decode(&self, bytes: &[u8]) -> Option<std::string::String> {
+        match *self {
+            Encoding::Utf8 => std::str::from_utf8(bytes).ok().map(str::to_string),
+            Encoding::Utf8Raw => Some(decode_utf8_raw(bytes)),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                if !bytes.len().is_multiple_of(2) {
+                    return None;
+                }
+                let units = bytes.chunks_exact(2).map(|pair| match *self {
+                    Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]]),
+                });
+                char::decode_utf16(units).collect::<Result<_, _>>().ok()
+            }
+            Encoding::Latin1 => Some(bytes.iter().map(|b| *b as char).collect()),
+            Encoding::Windows1252 => Some(bytes.iter().map(|b| cp1252_char(*b)).collect()),
+        }
+    }
+
+    /// Encodes rope with this encoding, with a byte order mark first when bom is set.
+    /// Fails on chars the encoding cannot represent.
+    pub fn encode(&self, rope: &Rope, bom: bool) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(rope.len_bytes() + 3);
+        if bom {
+            out.extend_from_slice(self.bom_bytes());
+        }
+        match *self {
+            Encoding::Utf8 => rope
+                .chunks()
+                .for_each(|chunk| out.extend_from_slice(chunk.as_bytes())),
+            Encoding::Utf8Raw => {
+                for ch in rope.chars() {
+                    match raw_byte(ch) {
+                        Some(byte) => out.push(byte),
+                        None => out.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes()),
+                    }
+                }
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                for unit in rope.chars().flat_map(|ch| {
+                    let mut buf = [0; 2];
+                    let len = ch.encode_utf16(&mut buf).len();
+                    buf.into_iter().take(len)
+                }) {
+                    match *self {
+                        Encoding::Utf16Le => out.extend_from_slice(&unit.to_le_bytes()),
+                        _ => out.extend_from_slice(&unit.to_be_bytes()),
+                    }
+                }
+            }
+            Encoding::Latin1 | Encoding::Windows1252 => {
+                for ch in rope.chars() {
+                    let byte = match *self {
+                        Encoding::Latin1 => u8::try_from(ch as u32).ok(),
+                        _ => cp1252_byte(ch),
+                    };
+                    match byte {
+                        Some(byte) => out.push(byte),
+                        None => {
+                            return Err(std::io::Error::new(
+                                std::io::ErrorKind::InvalidData,
+                                format!("'{}' cannot be encoded as {}", ch, self),
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = std::string::String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        match name.as_str() {
+            "utf8" => Ok(Encoding::Utf8),
+            "latin1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            "cp1252" => Ok(Encoding::Windows1252),
+            _ => Encoding::ALL
+                .into_iter()
+                .find(|encoding| encoding.name() == name)
+                .ok_or_else(|| format!("Unknown encoding: {}", s)),
+        }
+    }
+}
+
+/// Picks an encoding for bytes read from disk and decodes them.
+///
+/// A byte order mark decides first. Without one, text with a NUL in most odd or most
+/// even positions is tried as UTF-16, then valid UTF-8 is UTF-8. Anything else that
+/// still contains valid multi-byte UTF-8 keeps its invalid bytes as raw bytes, and
+/// plain 8-bit text is read as Windows-1252. Every choice decodes to text that
+/// encode turns back into the same bytes.
+pub fn decode(bytes: &[u8]) -> Decoded {
+    for encoding in [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be] {
+        if let Some(rest) = bytes.strip_prefix(encoding.bom_bytes()) {
+            if let Some(text) = encoding.decode(rest) {
+                return Decoded {
+                    text,
+                    encoding,
+                    bom: true,
+                };
+            }
+        }
+    }
+    if let Some(encoding) = guess_utf16(bytes) {
+        if let Some(text) = encoding.decode(bytes) {
+            return Decoded {
+                text,
+                encoding,
+                bom: false,
+            };
+        }
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Decoded {
+            text: text.to_string(),
+            encoding: Encoding::Utf8,
+            bom: false,
+        };
+    }
+    let raw = decode_utf8_raw(bytes);
+    let has_multibyte = raw
+        .chars()
+        .any(|ch| ch.len_utf8() > 1 && raw_byte(ch).is_none());
+    let ambiguous = std::string::String::from_utf8_lossy(bytes)
+        .chars()
+        .any(|ch| raw_byte(ch).is_some());
+    let encoding = if has_multibyte && !ambiguous {
+        Encoding::Utf8Raw
+    } else {
+        Encoding::Windows1252
+    };
+    Decoded {
+        text: encoding.decode(bytes).unwrap_or_default(),
+        encoding,
+        bom: false,
+    }
+}
+
+/// The byte a raw-byte char stands for.
+pub fn raw_byte(ch: char) -> Option<u8> {
+    (ch as u32)
+        .checked_sub(RAW_BYTE_BASE)
+        .and_then(|byte| u8::try_from(byte).ok())
+}
+
+fn decode_utf8_raw(mut bytes: &[u8]) -> std::string::String {
+    let mut text = std::string::String::with_capacity(bytes.len());
+    while !bytes.is_empty() {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                text.push_str(valid);
+                break;
+            }
+            Err(err) => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                // Safety: from_utf8 validated everything before valid_up_to.
+                text.push_str(unsafe { std::str::from_utf8_unchecked(valid) });
+                let bad = err.error_len().unwrap_or(rest.len());
+                for byte in &rest[..bad] {
+                    text.push(char::from_u32(RAW_BYTE_BASE + *byte as u32).unwrap());
+                }
+                bytes = &rest[bad..];
+            }
+        }
+    }
+    text
+}
+
+fn guess_utf16(bytes: &[u8]) -> Option<Encoding> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let pairs = bytes.len() / 2;
+    let even_nul = bytes.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd_nul = bytes.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+    if odd_nul * 2 > pairs && even_nul * 8 < pairs {
+        Some(Encoding::Utf16Le)
+    } else if even_nul * 2 > pairs && odd_nul * 8 < pairs {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+/// Windows-1252 bytes 0x80..=0x9F. The five unassigned bytes map to the matching C1
+/// control chars, the same as Latin-1, so every byte has a char and back.
+const CP1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+fn cp1252_char(byte: u8) -> char {
+    match byte {
+        0x80..=0x9F => CP1252_HIGH[(byte - 0x80) as usize],
+        byte => byte as char,
+    }
+}
+
+fn cp1252_byte(ch: char) -> Option<u8> {
+    if let Some(pos) = CP1252_HIGH.iter().position(|high| *high == ch) {
+        return Some(0x80 + pos as u8);
+    }
+    match ch as u32 {
+        0x80..=0x9F => None,
+        code => u8::try_from(code).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8]) -> Decoded {
+        let decoded = decode(bytes);
+        let rope = Rope::from_str(&decoded.text);
+        assert_eq!(
+            decoded.encoding.encode(&rope, decoded.bom).unwrap(),
+            bytes,
+            "{:?}",
+            decoded.encoding
+        );
+        decoded
+    }
+
+    #[test]
+    fn test_detect_bom() {
+        let utf8 = round_trip(b"\xEF\xBB\xBFhi");
+        assert_eq!(
+            (utf8.encoding, utf8.bom, utf8.text.as_str()),
+            (Encoding::Utf8, true, "hi")
+        );
+        let le = round_trip(b"\xFF\xFEh\x00\xe9\x00");
+        assert_eq!(
+            (le.encoding, le.bom, le.text.as_str()),
+            (Encoding::Utf16Le, true, "hé")
+        );
+        let be = round_trip(b"\xFE\xFF\x00h\xD8\x3D\xDE\x00");
+        assert_eq!((be.encoding, be.text.as_str()), (Encoding::Utf16Be, "h😀"));
+    }
+
+    #[test]
+    fn test_detect_without_bom() {
+        assert_eq!(round_trip("plain é".as_bytes()).encoding, Encoding::Utf8);
+        let le = round_trip(b"a\x00b\x00c\x00\n\x00");
+        assert_eq!(
+            (le.encoding, le.text.as_str()),
+            (Encoding::Utf16Le, "abc\n")
+        );
+        let latin = round_trip(b"caf\xe9 \x80 \x81");
+        assert_eq!(latin.encoding, Encoding::Windows1252);
+        assert_eq!(latin.text, "café € \u{81}");
+    }
+
+    #[test]
+    fn test_raw_bytes_survive() {
+        let bytes = b"log \xE2\x9C\x93 ok\n\xFF\xFEbad \xC3 tail\n";
+        let decoded = round_trip(bytes);
+        assert_eq!(decoded.encoding, Encoding::Utf8Raw);
+        assert!(decoded.text.starts_with("log ✓ ok\n"));
+        assert_eq!(
+            decoded
+                .text
+                .chars()
+                .filter_map(raw_byte)
+                .collect::<Vec<_>>(),
+            [0xFF, 0xFE, 0xC3]
+        );
+
+        let every_byte: Vec<u8> = (0..=255).collect();
+        round_trip(&every_byte);
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        let rope = Rope::from_str("snow ☃");
+        assert!(Encoding::Latin1.encode(&rope, false).is_err());
+        assert!(Encoding::Windows1252.encode(&rope, false).is_err());
+        assert_eq!(
+            Encoding::Latin1
+                .encode(&Rope::from_str("é"), false)
+                .unwrap(),
+            b"\xe9"
+        );
+        assert_eq!("UTF-16LE".parse::<Encoding>(), Ok(Encoding::Utf16Le));
+        assert!("ebcdic".parse::<Encoding>().is_err());
+    }
+}
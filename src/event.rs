@@ -1,10 +1,13 @@
 #![allow(unused_imports, unused_variables)]
 #![allow(dead_code)]
+#![allow(clippy::io_other_error)]
 
+use crate::ffi;
 use smallvec::SmallVec;
 use std::{
     default,
     io::{Error, ErrorKind, Read, Write},
+    time::Duration,
 };
 
 #[derive(Debug, Default, PartialEq)]
@@ -13,6 +16,39 @@ pub struct ParseKey<R: Read> {
     overflow: Option<u8>,
 }
 
+impl<R: Read> ParseKey<R> {
+    /// True when a byte of the next key was already read from the reader.
+    pub fn has_pending(&self) -> bool {
+        self.overflow.is_some()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+}
+
+/// How long after an ESC byte the rest of an escape sequence may take to arrive.
+/// An ESC with nothing after it within this time is the Escape key on its own.
+pub const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Key input that can tell whether more bytes follow within a timeout, which is how
+/// Escape on its own is told apart from the start of an Alt chord or a sequence.
+pub trait Pending {
+    fn pending(&self, timeout: Duration) -> std::io::Result<bool>;
+}
+
+impl Pending for ffi::Tty {
+    fn pending(&self, timeout: Duration) -> std::io::Result<bool> {
+        ffi::poll_read(self, timeout)
+    }
+}
+
+impl<T: AsRef<[u8]>> Pending for std::io::Cursor<T> {
+    fn pending(&self, _timeout: Duration) -> std::io::Result<bool> {
+        Ok((self.position() as usize) < self.get_ref().as_ref().len())
+    }
+}
+
 pub trait ReadKey {
     type Output;
     fn read_key(self) -> Self::Output;
@@ -29,27 +65,36 @@ impl<R: Read> ReadKey for R {
     }
 }
 
-impl<R: Read> Iterator for ParseKey<R> {
+// Key input is read unbuffered on purpose so escape sequences arrive as soon as they are typed.
+#[allow(clippy::unbuffered_bytes)]
+impl<R: Read + Pending> Iterator for ParseKey<R> {
     type Item = std::io::Result<Key>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let error = Error::new(ErrorKind::Other, "Could not read buffer.");
         let reader = &mut self.reader;
 
-        if self.overflow.is_some() {
-            return Some(KeyEvent::parse_key(
-                self.overflow.take().unwrap(),
-                &mut reader.bytes(),
-            ));
+        if let Some(byte) = self.overflow.take() {
+            if byte == b'\x1B' && !reader.pending(ESCAPE_TIMEOUT).unwrap_or(true) {
+                return Some(Ok(Key::Escape));
+            }
+            return Some(KeyEvent::parse_key(byte, &mut reader.bytes()));
         }
         let mut buf = [0u8; 2];
         match reader.read(&mut buf) {
-            Ok(0) => return None,
+            Ok(0) => None,
             Ok(1) if buf[0].is_ascii_digit() => Some(Ok(Key::Letter(buf[0] as char))),
+            // Waiting for the byte after a lone ESC would turn Escape into an Alt chord
+            // with whatever key comes next.
+            Ok(1) if buf[0] == b'\x1B' && !reader.pending(ESCAPE_TIMEOUT).unwrap_or(true) => {
+                Some(Ok(Key::Escape))
+            }
+            // A single key press must not pick up the zeroed second byte of buf.
+            Ok(1) => Some(KeyEvent::parse_key(buf[0], &mut reader.bytes())),
             Ok(_) => {
                 let input = &mut Some(buf[1]).into_iter();
                 let ret_key = {
-                    let mut iter = input.map(|byte| Ok(byte)).chain(reader.bytes());
+                    let mut iter = input.map(Ok).chain(reader.bytes());
                     Some(KeyEvent::parse_key(buf[0], &mut iter))
                 };
                 self.overflow = input.next();
@@ -80,26 +125,37 @@ impl KeyEvent {
             b'\x1B' => match iter.next() {
                 Some(Ok(b'[')) => match iter.next() {
                     Some(Ok(val)) if val.is_ascii_digit() => Ok(Self::parse_cursor_pos(val, iter)?),
+                    // Sent on focus changes once focus reporting is turned on.
+                    Some(Ok(b'I')) => Ok(Key::FocusIn),
+                    Some(Ok(b'O')) => Ok(Key::FocusOut),
+                    Some(Ok(b'A')) => Ok(Key::Up),
+                    Some(Ok(b'B')) => Ok(Key::Down),
+                    Some(Ok(b'C')) => Ok(Key::Right),
+                    Some(Ok(b'D')) => Ok(Key::Left),
                     Some(Ok(val)) => Ok(Self::parse_control_seq(iter)?),
-                    _ => return Err(error),
+                    _ => Err(error),
                 },
                 Some(Ok(b'0')) => match iter.next() {
                     // Function key F1-F4.
                     Some(Ok(val @ b'P'..=b'S')) => Ok(Key::F(1 + val - b'P')),
                     _ => Err(error),
                 },
+                // Meta with a control key, like C-M-f.
+                Some(Ok(itm @ b'\x01'..=b'\x1A')) => {
+                    Ok(Key::CtrlAltKey((itm - 0x1 + b'a') as char))
+                }
                 Some(Ok(letter)) => {
                     let ch = Self::parse_char(letter, iter)?;
                     Ok(Key::AltKey(ch))
                 }
-                Some(Err(_)) => return Err(error),
+                Some(Err(_)) => Err(error),
                 None => Ok(Key::Escape),
             },
             b'\x08' => Ok(Key::Backspace),
             b'\x09' => Ok(Key::Tab('\t')),
             b'\x0A' => Ok(Key::Enter('\n')),
             b'\x7F' => Ok(Key::Delete),
-            itm @ b'\x01'..=b'\x1A' => Ok(Key::CtrlKey((itm as u8 - 0x1 + b'a') as char)),
+            itm @ b'\x01'..=b'\x1A' => Ok(Key::CtrlKey((itm - 0x1 + b'a') as char)),
             // TODO: Parse char should only parse utf8. Change key::Letter back to key::Ascii/key::utf8
             itm => match KeyEvent::parse_char(itm, iter) {
                 Ok(key) => Ok(Key::Letter(key)),
@@ -196,6 +252,7 @@ pub enum Key {
     Letter(char),
     CtrlKey(char),
     AltKey(char),
+    CtrlAltKey(char),
     Enter(char),
     Tab(char),
     TabBack,
@@ -213,6 +270,8 @@ pub enum Key {
     PageDown,
     Insert,
     Esc,
+    FocusIn,
+    FocusOut,
 }
 impl std::fmt::Display for Key {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -221,6 +280,7 @@ impl std::fmt::Display for Key {
             Key::Letter(ch) => write!(f, "{}", ch),
             Key::CtrlKey(ch) => write!(f, "CtrlKey: {}", ch),
             Key::AltKey(ch) => write!(f, "AltKey: {}", ch),
+            Key::CtrlAltKey(ch) => write!(f, "CtrlAltKey: {}", ch),
             Key::Enter(_) => write!(f, "Enter"),
             Key::Tab(_) => write!(f, "Tab"),
             Key::F(num) => write!(f, "F{}", num),
@@ -238,6 +298,8 @@ impl std::fmt::Display for Key {
             Key::PageDown => write!(f, "PageDown"),
             Key::Insert => write!(f, "Insert"),
             Key::Esc => write!(f, "Esc"),
+            Key::FocusIn => write!(f, "FocusIn"),
+            Key::FocusOut => write!(f, "FocusOut"),
         }
     }
 }
@@ -248,13 +310,27 @@ mod tests {
 
     #[test]
     fn test_parse_key_iterator() {
-        let input = "\x61\x1b\x62\x02\x1b[23;23R\x61";
+        let input = "\x61\x1b\x62\x02\x1b[23;23R\x61\x1b\x06";
         let mut reader = std::io::Cursor::new(input).read_key();
         assert_eq!(reader.next().unwrap().unwrap(), Key::Letter('a'));
         assert_eq!(reader.next().unwrap().unwrap(), Key::AltKey('b'));
         assert_eq!(reader.next().unwrap().unwrap(), Key::CtrlKey('b'));
         assert_eq!(reader.next().unwrap().unwrap(), Key::CursorPos((23, 23)));
         assert_eq!(reader.next().unwrap().unwrap(), Key::Letter('a'));
+        assert_eq!(reader.next().unwrap().unwrap(), Key::CtrlAltKey('f'));
+    }
+
+    #[test]
+    fn test_lone_escape() {
+        // An ESC with nothing after it is Escape, also when it was read together
+        // with the key before it.
+        let mut reader = std::io::Cursor::new("\x1b").read_key();
+        assert_eq!(reader.next().unwrap().unwrap(), Key::Escape);
+        assert!(reader.next().is_none());
+        let mut reader = std::io::Cursor::new("a\x1b").read_key();
+        assert_eq!(reader.next().unwrap().unwrap(), Key::Letter('a'));
+        assert_eq!(reader.next().unwrap().unwrap(), Key::Escape);
+        assert!(reader.next().is_none());
     }
 
     #[test]
@@ -302,6 +378,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_csi_keys() {
+        let input = "\x1b[I\x1b[A\x1b[Dx\x1b[O";
+        let mut reader = std::io::Cursor::new(input).read_key();
+        assert_eq!(reader.next().unwrap().unwrap(), Key::FocusIn);
+        assert_eq!(reader.next().unwrap().unwrap(), Key::Up);
+        assert_eq!(reader.next().unwrap().unwrap(), Key::Left);
+        assert_eq!(reader.next().unwrap().unwrap(), Key::Letter('x'));
+        assert_eq!(reader.next().unwrap().unwrap(), Key::FocusOut);
+    }
+
     #[test]
     fn test_alt_keys() {
         let simulated_keys = vec![
@@ -1,7 +1,6 @@
 #![allow(unused_imports, unused_variables)]
 #![allow(dead_code)]
 
-
 extern crate libc;
 
 use std::io::Error;
@@ -12,7 +11,7 @@ use libc::{tcgetattr, tcsetattr, termios, TCSAFLUSH};
 
 /// #Safety
 /// I copied this code from the mio crate.
-/// There isn't much of a safety concern here, on its own. 
+/// There isn't much of a safety concern here, on its own.
 ///
 #[allow(unused_macros)]
 macro_rules! libc_call {
@@ -84,14 +83,193 @@ pub fn io_ctl(stream: &mut impl fd::AsRawFd) -> std::io::Result<libc::winsize> {
         Ok(unsafe { result.assume_init() })
     }
 }
+/// Waits up to timeout for stream to become readable. Returns false on timeout, which
+/// is how the event loop finds time for timers between key presses.
+pub fn poll_read(stream: &impl fd::AsRawFd, timeout: std::time::Duration) -> std::io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd: stream.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    let ready = libc_call!(poll(&mut pollfd, 1, timeout))?;
+    Ok(ready > 0)
+}
+
+/// True when this process may write to path, as decided by access(2). That answers
+/// for the real user and takes ACLs and read-only mounts into account, which looking
+/// at the permission bits does not.
+pub fn is_writable(path: &std::path::Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    match std::ffi::CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => libc_call!(access(path.as_ptr(), libc::W_OK)).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// A moment as the local clock shows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LocalTime {
+    pub year: i32,
+    /// 1 to 12.
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub min: u32,
+    pub sec: u32,
+}
+
+/// The local time secs after the epoch, from localtime_r(3). None when the time is
+/// out of range for the C library.
+pub fn local_time(secs: i64) -> Option<LocalTime> {
+    let mut result: MaybeUninit<libc::tm> = MaybeUninit::uninit();
+    let time = secs as libc::time_t;
+    let tm = unsafe { libc::localtime_r(&time, result.as_mut_ptr()) };
+    if tm.is_null() {
+        return None;
+    }
+    let tm = unsafe { result.assume_init() };
+    Some(LocalTime {
+        year: tm.tm_year + 1900,
+        month: tm.tm_mon as u32 + 1,
+        day: tm.tm_mday as u32,
+        hour: tm.tm_hour as u32,
+        min: tm.tm_min as u32,
+        sec: tm.tm_sec as u32,
+    })
+}
+
+/// True when stream refers to a terminal.
+pub fn is_tty(stream: &impl fd::AsRawFd) -> bool {
+    unsafe { libc::isatty(stream.as_raw_fd()) == 1 }
+}
+
+/// The controlling terminal. This is stdin in the usual case. When stdin is a pipe,
+/// as in "cargo build 2>&1 | mr_text -", keys and the window size come from /dev/tty
+/// so stdin is free to be read as a buffer.
+#[derive(Debug)]
+pub enum Tty {
+    Stdin(std::io::Stdin),
+    Dev(std::fs::File),
+}
+
+impl Tty {
+    pub fn open() -> std::io::Result<Tty> {
+        let stdin = std::io::stdin();
+        if is_tty(&stdin) {
+            return Ok(Tty::Stdin(stdin));
+        }
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")?;
+        Ok(Tty::Dev(file))
+    }
+
+    pub fn try_clone(&self) -> std::io::Result<Tty> {
+        match self {
+            Tty::Stdin(_) => Ok(Tty::Stdin(std::io::stdin())),
+            Tty::Dev(file) => Ok(Tty::Dev(file.try_clone()?)),
+        }
+    }
+}
+
+impl fd::AsRawFd for Tty {
+    fn as_raw_fd(&self) -> fd::RawFd {
+        match self {
+            Tty::Stdin(stdin) => stdin.as_raw_fd(),
+            Tty::Dev(file) => file.as_raw_fd(),
+        }
+    }
+}
+
+impl std::io::Read for Tty {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Tty::Stdin(stdin) => stdin.read(buf),
+            Tty::Dev(file) => file.read(buf),
+        }
+    }
+}
+
+/// A read-only, private memory map of a whole file. Pages are only read from disk
+/// when they are touched, so mapping a file of several gigabytes is cheap.
+///
+/// The mapping shows the file as it is on disk. A file truncated by another process
+/// while it is mapped makes reads past the new end raise SIGBUS, so users of a map
+/// must compare the file's length with len before reading and map it again when the
+/// file shrank. That check cannot rule out a truncation between it and the read.
+#[derive(Debug)]
+pub struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    pub fn map(file: &std::fs::File) -> std::io::Result<Mmap> {
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| Error::other("File is too large to map"))?;
+        if len == 0 {
+            // mmap refuses empty mappings.
+            return Ok(Mmap::empty());
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                fd::AsRawFd::as_raw_fd(file),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        Ok(Mmap { ptr, len })
+    }
+
+    /// A map of nothing, for when a file can't be mapped again.
+    pub fn empty() -> Mmap {
+        Mmap {
+            ptr: std::ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // The mapping is len bytes long and lives until drop.
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct RevertOnDrop<'a> {
-    istream: &'a mut std::io::Stdin,
+pub struct RevertOnDrop<'a, T: fd::AsRawFd> {
+    istream: &'a mut T,
     original_term: libc::termios,
 }
 
-impl<'a> RevertOnDrop<'a> {
-    pub fn new(istream: &'a mut std::io::Stdin, original_term: libc::termios) -> RevertOnDrop {
+impl<'a, T: fd::AsRawFd> RevertOnDrop<'a, T> {
+    pub fn new(istream: &'a mut T, original_term: libc::termios) -> RevertOnDrop<'a, T> {
         RevertOnDrop {
             istream,
             original_term,
@@ -99,7 +277,7 @@ impl<'a> RevertOnDrop<'a> {
     }
 }
 
-impl Drop for RevertOnDrop<'_> {
+impl<T: fd::AsRawFd> Drop for RevertOnDrop<'_, T> {
     fn drop(&mut self) {
         tc_setattr(self.istream, self.original_term).unwrap();
     }
@@ -108,4 +286,37 @@ impl Drop for RevertOnDrop<'_> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn test_is_writable() {
+        let dir = scratch_dir("access");
+        let path = dir.join("access");
+        std::fs::write(&path, "").unwrap();
+        assert!(is_writable(&path));
+        assert!(!is_writable(&path.with_extension("missing")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_mmap() {
+        let file = std::fs::File::open("./text.txt").unwrap();
+        let map = Mmap::map(&file).unwrap();
+        assert_eq!(map.as_slice(), std::fs::read("./text.txt").unwrap());
+
+        let dir = scratch_dir("mmap");
+        let path = dir.join("empty");
+        std::fs::write(&path, "").unwrap();
+        let empty = Mmap::map(&std::fs::File::open(&path).unwrap()).unwrap();
+        assert!(empty.as_slice().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_is_tty() {
+        let file = std::fs::File::open("./text.txt").unwrap();
+        assert!(!is_tty(&file));
+        assert!(poll_read(&file, std::time::Duration::ZERO).unwrap());
+        assert!(io_ctl(&mut std::fs::File::open("./text.txt").unwrap()).is_err());
+    }
 }
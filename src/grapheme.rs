@@ -0,0 +1,96 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use ropey::{str_utils::byte_to_char_idx, Rope};
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+
+/// The char offset of the grapheme cluster boundary after char_idx, or the end of the
+/// rope. Walks the rope chunk by chunk so a cluster split across chunks is still one
+/// step.
+pub fn next_boundary(rope: &Rope, char_idx: usize) -> usize {
+    let byte_idx = rope.char_to_byte(char_idx);
+    let (mut chunk, mut chunk_byte_idx, mut chunk_char_idx, _) = rope.chunk_at_byte(byte_idx);
+    let mut cursor = GraphemeCursor::new(byte_idx, rope.len_bytes(), true);
+    loop {
+        match cursor.next_boundary(chunk, chunk_byte_idx) {
+            Ok(None) => return rope.len_chars(),
+            Ok(Some(boundary)) => {
+                return chunk_char_idx + byte_to_char_idx(chunk, boundary - chunk_byte_idx)
+            }
+            Err(GraphemeIncomplete::NextChunk) => {
+                chunk_byte_idx += chunk.len();
+                let (next, _, next_char_idx, _) = rope.chunk_at_byte(chunk_byte_idx);
+                chunk = next;
+                chunk_char_idx = next_char_idx;
+            }
+            Err(GraphemeIncomplete::PreContext(idx)) => {
+                let context = rope.chunk_at_byte(idx - 1).0;
+                cursor.provide_context(context, idx - context.len());
+            }
+            Err(_) => unreachable!("GraphemeCursor only asks for chunks and context"),
+        }
+    }
+}
+
+/// The char offset of the grapheme cluster boundary before char_idx, or 0.
+pub fn prev_boundary(rope: &Rope, char_idx: usize) -> usize {
+    let byte_idx = rope.char_to_byte(char_idx);
+    let (mut chunk, mut chunk_byte_idx, mut chunk_char_idx, _) = rope.chunk_at_byte(byte_idx);
+    let mut cursor = GraphemeCursor::new(byte_idx, rope.len_bytes(), true);
+    loop {
+        match cursor.prev_boundary(chunk, chunk_byte_idx) {
+            Ok(None) => return 0,
+            Ok(Some(boundary)) => {
+                return chunk_char_idx + byte_to_char_idx(chunk, boundary - chunk_byte_idx)
+            }
+            Err(GraphemeIncomplete::PrevChunk) => {
+                let (prev, prev_byte_idx, prev_char_idx, _) =
+                    rope.chunk_at_byte(chunk_byte_idx - 1);
+                chunk = prev;
+                chunk_byte_idx = prev_byte_idx;
+                chunk_char_idx = prev_char_idx;
+            }
+            Err(GraphemeIncomplete::PreContext(idx)) => {
+                let context = rope.chunk_at_byte(idx - 1).0;
+                cursor.provide_context(context, idx - context.len());
+            }
+            Err(_) => unreachable!("GraphemeCursor only asks for chunks and context"),
+        }
+    }
+}
+
+/// True when char_idx doesn't split a grapheme cluster.
+pub fn is_boundary(rope: &Rope, char_idx: usize) -> bool {
+    char_idx == 0 || next_boundary(rope, prev_boundary(rope, char_idx)) == char_idx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundaries() {
+        // e + combining acute, a family emoji joined with ZWJs, CRLF, a flag.
+        let text = "e\u{301}x\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\r\n\u{1f1f3}\u{1f1f1}";
+        let rope = Rope::from_str(text);
+        let mut stops = vec![0];
+        while *stops.last().unwrap() < rope.len_chars() {
+            stops.push(next_boundary(&rope, *stops.last().unwrap()));
+        }
+        assert_eq!(stops, [0, 2, 3, 8, 10, 12]);
+        let mut back = vec![rope.len_chars()];
+        while *back.last().unwrap() > 0 {
+            back.push(prev_boundary(&rope, *back.last().unwrap()));
+        }
+        back.reverse();
+        assert_eq!(back, stops);
+        assert!(is_boundary(&rope, 3) && !is_boundary(&rope, 1) && !is_boundary(&rope, 9));
+
+        // Clusters that straddle rope chunks are still stepped over whole.
+        let long = "e\u{301}".repeat(4000);
+        let rope = Rope::from_str(&long);
+        assert!((0..rope.len_chars())
+            .step_by(2)
+            .all(|idx| next_boundary(&rope, idx) == idx + 2));
+    }
+}
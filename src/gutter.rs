@@ -0,0 +1,412 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use crate::theme::Face;
+use ropey::Rope;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::Path;
+
+/// Above this many base lines times buffer lines the differing middle of a buffer is
+/// marked changed as a whole rather than compared line by line.
+const DIFF_LIMIT: usize = 1_000_000;
+
+/// The sign columns of the gutter, left to right. A column only takes up space in
+/// buffers that have a sign in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SignColumn {
+    Diagnostic,
+    Diff,
+    Bookmark,
+}
+
+impl SignColumn {
+    pub const ALL: [SignColumn; 3] = [
+        SignColumn::Diagnostic,
+        SignColumn::Diff,
+        SignColumn::Bookmark,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SignKind {
+    Error,
+    Warning,
+    /// A line that is not in the base version.
+    Added,
+    /// A line that replaced one of the base version.
+    Changed,
+    /// Lines of the base version were removed before this line.
+    Deleted,
+    Bookmark,
+}
+
+impl SignKind {
+    pub fn column(self) -> SignColumn {
+        match self {
+            SignKind::Error | SignKind::Warning => SignColumn::Diagnostic,
+            SignKind::Added | SignKind::Changed | SignKind::Deleted => SignColumn::Diff,
+            SignKind::Bookmark => SignColumn::Bookmark,
+        }
+    }
+
+    /// The one-column mark shown in the gutter.
+    pub fn glyph(self) -> &'static str {
+        match self {
+            SignKind::Error => "E",
+            SignKind::Warning => "W",
+            SignKind::Added => "+",
+            SignKind::Changed => "~",
+            SignKind::Deleted => "_",
+            SignKind::Bookmark => "*",
+        }
+    }
+
+    pub fn face(self) -> Face {
+        match self {
+            SignKind::Error => Face::SignError,
+            SignKind::Warning => Face::SignWarning,
+            SignKind::Added => Face::DiffAdded,
+            SignKind::Changed => Face::DiffChanged,
+            SignKind::Deleted => Face::DiffDeleted,
+            SignKind::Bookmark => Face::Bookmark,
+        }
+    }
+
+    /// Which sign a column shows when a line has several.
+    fn priority(self) -> u8 {
+        match self {
+            SignKind::Error | SignKind::Changed | SignKind::Bookmark => 2,
+            SignKind::Warning | SignKind::Added => 1,
+            SignKind::Deleted => 0,
+        }
+    }
+}
+
+/// A sign anchored to the start of a line. pos is a char index, so the sign moves with
+/// the text around it as the buffer is edited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sign {
+    pub pos: usize,
+    pub kind: SignKind,
+}
+
+/// The signs of one buffer.
+#[derive(Debug, Clone, Default)]
+pub struct Signs {
+    signs: Vec<Sign>,
+}
+
+impl Signs {
+    pub fn is_empty(&self) -> bool {
+        self.signs.is_empty()
+    }
+
+    /// Puts a sign of kind on line of rope.
+    pub fn add(&mut self, rope: &Rope, line: usize, kind: SignKind) {
+        let pos = rope.line_to_char(line.min(rope.len_lines() - 1));
+        self.signs.push(Sign { pos, kind });
+    }
+
+    /// Takes every sign of kind off line. Returns true when there was one.
+    pub fn remove(&mut self, rope: &Rope, line: usize, kind: SignKind) -> bool {
+        let count = self.signs.len();
+        self.signs
+            .retain(|sign| sign.kind != kind || line_of(rope, sign.pos) != line);
+        self.signs.len() < count
+    }
+
+    /// Puts a sign of kind on line, or takes it off when it is there. Returns true
+    /// when the line has the sign afterwards.
+    pub fn toggle(&mut self, rope: &Rope, line: usize, kind: SignKind) -> bool {
+        if self.remove(rope, line, kind) {
+            return false;
+        }
+        self.add(rope, line, kind);
+        true
+    }
+
+    /// Replaces the signs in column with signs, given as lines and kinds. This is how
+    /// a subsystem publishes its signs.
+    pub fn set_column<I>(&mut self, rope: &Rope, column: SignColumn, signs: I)
+    where
+        I: IntoIterator<Item = (usize, SignKind)>,
+    {
+        self.clear(column);
+        for (line, kind) in signs {
+            self.add(rope, line, kind);
+        }
+    }
+
+    pub fn clear(&mut self, column: SignColumn) {
+        self.signs.retain(|sign| sign.kind.column() != column);
+    }
+
+    /// The columns that have at least one sign, left to right.
+    pub fn columns(&self) -> Vec<SignColumn> {
+        SignColumn::ALL
+            .into_iter()
+            .filter(|column| self.signs.iter().any(|sign| sign.kind.column() == *column))
+            .collect()
+    }
+
+    /// The lines with a sign of kind, in order.
+    pub fn lines_with(&self, rope: &Rope, kind: SignKind) -> Vec<usize> {
+        let mut lines: Vec<usize> = self
+            .signs
+            .iter()
+            .filter(|sign| sign.kind == kind)
+            .map(|sign| line_of(rope, sign.pos))
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
+    /// For each line in lines that has signs, the sign each column shows, in the
+    /// order of SignColumn::ALL.
+    pub fn in_lines(
+        &self,
+        rope: &Rope,
+        lines: Range<usize>,
+    ) -> BTreeMap<usize, [Option<SignKind>; 3]> {
+        let mut shown: BTreeMap<usize, [Option<SignKind>; 3]> = BTreeMap::new();
+        for sign in &self.signs {
+            let line = line_of(rope, sign.pos);
+            if !lines.contains(&line) {
+                continue;
+            }
+            let slot = &mut shown.entry(line).or_default()[sign.kind.column() as usize];
+            if slot.is_none_or(|kind| kind.priority() < sign.kind.priority()) {
+                *slot = Some(sign.kind);
+            }
+        }
+        shown
+    }
+
+    /// Moves the signs for text that was replaced as a whole by new: each sign keeps
+    /// the line number it had in old, clamped to the lines of new.
+    pub fn replaced(&mut self, old: &Rope, new: &Rope) {
+        for sign in &mut self.signs {
+            let line = line_of(old, sign.pos).min(new.len_lines() - 1);
+            sign.pos = new.line_to_char(line);
+        }
+    }
+
+    /// Moves the signs for an edit that replaced removed chars at start with inserted
+    /// chars. Signs after the edit move with the text, and those in removed text end
+    /// up where it was.
+    pub fn edited(&mut self, start: usize, removed: usize, inserted: usize) {
+        for sign in &mut self.signs {
+            if sign.pos >= start + removed {
+                sign.pos = sign.pos - removed + inserted;
+            } else if sign.pos > start {
+                sign.pos = start;
+            } else if sign.pos == start && removed == 0 {
+                sign.pos += inserted;
+            }
+        }
+    }
+}
+
+/// The line pos is on, for positions that may have gone past the end of the rope.
+fn line_of(rope: &Rope, pos: usize) -> usize {
+    rope.char_to_line(pos.min(rope.len_chars()))
+}
+
+/// The diff signs for text compared with base, line by line: added and changed lines
+/// are marked, and so is the line after lines that were deleted. Both are split into
+/// lines at eol, the line break of the buffer.
+pub fn diff_signs(base: &str, text: &str, eol: &str) -> Vec<(usize, SignKind)> {
+    let old = split_lines(base, eol);
+    let new = split_lines(text, eol);
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    // Walk the middle as runs of deleted and inserted lines between kept ones.
+    let mut signs = Vec::new();
+    let mut hunk = |line: usize, deleted: usize, inserted: usize| {
+        let changed = deleted.min(inserted);
+        signs.extend((line..line + changed).map(|line| (line, SignKind::Changed)));
+        signs.extend((line + changed..line + inserted).map(|line| (line, SignKind::Added)));
+        if inserted == 0 && deleted > 0 {
+            let at = line.min(new.len().saturating_sub(1));
+            signs.push((at, SignKind::Deleted));
+        }
+    };
+    if old_mid.len() * new_mid.len() > DIFF_LIMIT {
+        hunk(prefix, old_mid.len(), new_mid.len());
+        return signs;
+    }
+    let (mut i, mut j) = (0, 0);
+    let (mut deleted, mut inserted, mut start) = (0, 0, prefix);
+    let lcs = lcs_table(old_mid, new_mid);
+    let width = new_mid.len() + 1;
+    while i < old_mid.len() || j < new_mid.len() {
+        if i < old_mid.len() && j < new_mid.len() && old_mid[i] == new_mid[j] {
+            hunk(start, deleted, inserted);
+            (deleted, inserted) = (0, 0);
+            i += 1;
+            j += 1;
+            start = prefix + j;
+        } else if j < new_mid.len()
+            && (i == old_mid.len() || lcs[i * width + j + 1] >= lcs[(i + 1) * width + j])
+        {
+            inserted += 1;
+            j += 1;
+        } else {
+            deleted += 1;
+            i += 1;
+        }
+    }
+    hunk(start, deleted, inserted);
+    signs
+}
+
+/// The lines of text split at eol, without a last empty line after a final line
+/// break, the same as str::lines for "\n".
+fn split_lines<'a>(text: &'a str, eol: &str) -> Vec<&'a str> {
+    let text = text.strip_suffix(eol).unwrap_or(text);
+    match text.is_empty() {
+        true => Vec::new(),
+        false => text.split(eol).collect(),
+    }
+}
+
+/// lcs[i * (new.len() + 1) + j] is the length of the longest common subsequence of
+/// old[i..] and new[j..].
+fn lcs_table(old: &[&str], new: &[&str]) -> Vec<u32> {
+    let width = new.len() + 1;
+    let mut lcs = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = match old[i] == new[j] {
+                true => lcs[(i + 1) * width + j + 1] + 1,
+                false => lcs[(i + 1) * width + j].max(lcs[i * width + j + 1]),
+            };
+        }
+    }
+    lcs
+}
+
+/// The text of path as committed at HEAD, asked of git. None outside a repository,
+/// for untracked files, and when git isn't installed.
+pub fn git_head_text(path: &Path) -> Option<std::string::String> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name()?.to_str()?;
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .arg("show")
+        .arg(format!("HEAD:./{}", name))
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    match output.status.success() {
+        true => Some(std::string::String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signs_follow_edits() {
+        let mut rope = Rope::from_str("one\ntwo\nthree\n");
+        let mut signs = Signs::default();
+        signs.add(&rope, 1, SignKind::Error);
+        assert!(signs.toggle(&rope, 2, SignKind::Bookmark));
+        assert_eq!(
+            signs.columns(),
+            [SignColumn::Diagnostic, SignColumn::Bookmark]
+        );
+
+        // A new line above pushes both signs down.
+        rope.insert(0, "zero\n");
+        signs.edited(0, 0, 5);
+        assert_eq!(signs.lines_with(&rope, SignKind::Error), [2]);
+        assert_eq!(signs.lines_with(&rope, SignKind::Bookmark), [3]);
+
+        // Splitting the line at its start moves the sign with the text.
+        rope.insert(9, "\n");
+        signs.edited(9, 0, 1);
+        assert_eq!(signs.lines_with(&rope, SignKind::Error), [3]);
+
+        // Deleting the line the sign is on leaves it where the text was.
+        let start = rope.line_to_char(3);
+        rope.remove(start..start + 4);
+        signs.edited(start, 4, 0);
+        assert_eq!(signs.lines_with(&rope, SignKind::Error), [3]);
+        assert_eq!(signs.lines_with(&rope, SignKind::Bookmark), [3]);
+
+        let shown = signs.in_lines(&rope, 0..10);
+        assert_eq!(
+            shown.get(&3),
+            Some(&[Some(SignKind::Error), None, Some(SignKind::Bookmark)])
+        );
+        assert!(!signs.toggle(&rope, 3, SignKind::Bookmark));
+        signs.clear(SignColumn::Diagnostic);
+        assert!(signs.is_empty());
+
+        // Text replaced as a whole keeps bookmarks on their lines, within the new text.
+        let mut signs = Signs::default();
+        let old = Rope::from_str("a\nb\nc\nd\n");
+        signs.add(&old, 1, SignKind::Bookmark);
+        signs.add(&old, 4, SignKind::Bookmark);
+        let new = Rope::from_str("longer a\nlonger b\n");
+        signs.replaced(&old, &new);
+        assert_eq!(signs.lines_with(&new, SignKind::Bookmark), [1, 2]);
+    }
+
+    #[test]
+    fn test_diff_signs() {
+        let base = "a\nb\nc\nd\ne\n";
+        assert_eq!(diff_signs(base, base, "\n"), []);
+        assert_eq!(
+            diff_signs(base, "a\nB\nc\nd\nnew\ne\n", "\n"),
+            [(1, SignKind::Changed), (4, SignKind::Added)]
+        );
+        assert_eq!(
+            diff_signs(base, "a\nd\ne\n", "\n"),
+            [(1, SignKind::Deleted)]
+        );
+        assert_eq!(
+            diff_signs(base, "a\nb\nc\n", "\n"),
+            [(2, SignKind::Deleted)]
+        );
+        assert_eq!(
+            diff_signs("", "x\ny\n", "\n"),
+            [(0, SignKind::Added), (1, SignKind::Added)]
+        );
+        assert_eq!(
+            diff_signs(base, "x\na\nb\nC\nD\nE\nF\n", "\n"),
+            [
+                (0, SignKind::Added),
+                (3, SignKind::Changed),
+                (4, SignKind::Changed),
+                (5, SignKind::Changed),
+                (6, SignKind::Added),
+            ]
+        );
+
+        // A buffer that ends lines with CR alone is split at its own line breaks.
+        assert_eq!(
+            diff_signs("a\rb\rc\r", "a\rB\rc\r", "\r"),
+            [(1, SignKind::Changed)]
+        );
+        assert_eq!(diff_signs("a\r\nb\r\n", "a\r\nb\r\n", "\r\n"), []);
+    }
+}
@@ -0,0 +1,175 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+/// Bytes shown on one row of the hex view.
+pub const ROW_BYTES: usize = 16;
+
+/// Screen column of the first hex digit, after "00000010: ".
+const HEX_COL: usize = 10;
+
+/// A hexl-style view of a buffer's bytes. Each row shows its offset, sixteen bytes as
+/// eight groups of two in hex and the same bytes as ASCII:
+///
+/// 00000000: 8950 4e47 0d0a 1a0a 0000 000d 4948 4452  .PNG........IHDR
+///
+/// Typing hex digits overwrites the byte at the cursor one nibble at a time, so the
+/// bytes written back are exactly the ones shown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hexl {
+    bytes: Vec<u8>,
+    cursor: usize,
+    low_nibble: bool,
+}
+
+impl Hexl {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Hexl {
+            bytes,
+            cursor: 0,
+            low_nibble: false,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Offset of the byte under the cursor.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn rows(&self) -> usize {
+        self.bytes.len().div_ceil(ROW_BYTES).max(1)
+    }
+
+    /// The text of row, None past the last one.
+    pub fn row(&self, row: usize) -> Option<std::string::String> {
+        if row >= self.rows() {
+            return None;
+        }
+        let start = row * ROW_BYTES;
+        let chunk = self.bytes.chunks(ROW_BYTES).nth(row).unwrap_or_default();
+        let mut text = format!("{:08x}: ", start);
+        for idx in 0..ROW_BYTES {
+            match chunk.get(idx) {
+                Some(byte) => text.push_str(&format!("{:02x}", byte)),
+                None => text.push_str("  "),
+            }
+            if idx % 2 == 1 {
+                text.push(' ');
+            }
+        }
+        text.push(' ');
+        text.extend(chunk.iter().map(|byte| match byte {
+            0x20..=0x7e => *byte as char,
+            _ => '.',
+        }));
+        Some(text)
+    }
+
+    pub fn cursor_row(&self) -> usize {
+        self.cursor / ROW_BYTES
+    }
+
+    /// Screen column of the nibble under the cursor within its row.
+    pub fn cursor_col(&self) -> usize {
+        let idx = self.cursor % ROW_BYTES;
+        HEX_COL + (idx / 2) * 5 + (idx % 2) * 2 + usize::from(self.low_nibble)
+    }
+
+    /// The last offset the cursor can be on. An empty buffer still has offset 0.
+    fn last(&self) -> usize {
+        self.bytes.len().saturating_sub(1)
+    }
+
+    pub fn goto(&mut self, offset: usize) {
+        self.cursor = offset.min(self.last());
+        self.low_nibble = false;
+    }
+
+    pub fn forward_byte(&mut self) {
+        self.goto(self.cursor + 1);
+    }
+
+    pub fn backward_byte(&mut self) {
+        self.goto(self.cursor.saturating_sub(1));
+    }
+
+    pub fn move_rows(&mut self, count: isize) {
+        let offset = self
+            .cursor
+            .saturating_add_signed(count.saturating_mul(ROW_BYTES as isize));
+        // Moving down past the last row stays put rather than jumping to the end.
+        if offset <= self.last() || count < 0 {
+            self.goto(offset);
+        }
+    }
+
+    /// Overwrites the nibble at the cursor with the hex digit ch and moves on. Returns
+    /// false when ch is not a hex digit or there is no byte to edit.
+    pub fn input(&mut self, ch: char) -> bool {
+        let (Some(digit), Some(byte)) = (ch.to_digit(16), self.bytes.get_mut(self.cursor)) else {
+            return false;
+        };
+        let digit = digit as u8;
+        if self.low_nibble {
+            *byte = (*byte & 0xf0) | digit;
+            self.low_nibble = false;
+            if self.cursor < self.last() {
+                self.cursor += 1;
+            }
+        } else {
+            *byte = (*byte & 0x0f) | (digit << 4);
+            self.low_nibble = true;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexl_rows() {
+        let hexl = Hexl::new(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDRtail".to_vec());
+        assert_eq!(hexl.rows(), 2);
+        assert_eq!(
+            hexl.row(0).unwrap(),
+            "00000000: 8950 4e47 0d0a 1a0a 0000 000d 4948 4452  .PNG........IHDR"
+        );
+        assert_eq!(
+            hexl.row(1).unwrap(),
+            "00000010: 7461 696c                                tail"
+        );
+        assert_eq!(hexl.row(2), None);
+        assert_eq!(Hexl::new(Vec::new()).row(0).unwrap().len(), 51);
+    }
+
+    #[test]
+    fn test_hexl_edit() {
+        let mut hexl = Hexl::new(vec![0; 20]);
+        hexl.move_rows(1);
+        assert_eq!((hexl.cursor_row(), hexl.cursor_col()), (1, 10));
+        hexl.move_rows(1);
+        assert_eq!(hexl.cursor(), 16);
+        hexl.backward_byte();
+        assert!(hexl.input('A'));
+        assert_eq!(hexl.cursor_col(), 10 + 7 * 5 + 2 + 1);
+        assert!(hexl.input('f'));
+        assert!(!hexl.input('g'));
+        assert_eq!(hexl.bytes()[15], 0xaf);
+        assert_eq!(hexl.cursor(), 16);
+
+        hexl.goto(99);
+        hexl.input('1');
+        hexl.input('2');
+        assert_eq!(hexl.bytes()[19], 0x12);
+        assert_eq!(hexl.cursor(), 19);
+    }
+}
@@ -0,0 +1,1008 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use crate::theme::Face;
+use ropey::Rope;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// A run of a line to draw in face, as char offsets into the line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub start: usize,
+    pub end: usize,
+    pub face: Face,
+}
+
+/// What the lexer is in the middle of where a line ends, which is all it needs to
+/// know to lex the next line.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum State {
+    #[default]
+    Normal,
+    /// In a block comment, nested this deep.
+    Comment(usize),
+    /// In a string that ends at close.
+    String {
+        close: std::string::String,
+        escapes: bool,
+    },
+    /// In a Markdown code block opened by this fence.
+    Fence(std::string::String),
+}
+
+/// The languages with built-in rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    #[default]
+    Plain,
+    Rust,
+    Toml,
+    Markdown,
+    Shell,
+    Json,
+}
+
+impl Language {
+    /// The language of a file with extension ext.
+    pub fn for_extension(ext: Option<&str>) -> Language {
+        match ext {
+            Some("rs") => Language::Rust,
+            Some("toml") => Language::Toml,
+            Some("md" | "markdown") => Language::Markdown,
+            Some("sh" | "bash" | "zsh") => Language::Shell,
+            Some("json") => Language::Json,
+            _ => Language::Plain,
+        }
+    }
+
+    fn rules(self) -> Option<&'static Rules> {
+        match self {
+            Language::Rust => Some(&RUST),
+            Language::Toml => Some(&TOML),
+            Language::Shell => Some(&SHELL),
+            Language::Json => Some(&JSON),
+            Language::Plain | Language::Markdown => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Language::Plain => "Text",
+            Language::Rust => "Rust",
+            Language::Toml => "TOML",
+            Language::Markdown => "Markdown",
+            Language::Shell => "Shell",
+            Language::Json => "JSON",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A kind of string literal.
+struct Quote {
+    open: &'static str,
+    close: &'static str,
+    /// A backslash escapes the next char, so it can't close the string.
+    escapes: bool,
+    /// Unclosed at the end of a line, the string goes on on the next one.
+    multiline: bool,
+}
+
+/// How to lex one language. Languages other than Markdown are lexed by the same code
+/// driven by one of these.
+struct Rules {
+    line_comment: Option<&'static str>,
+    /// The line comment only starts a comment at the start of a word, like "#" in
+    /// shell where "$#" is a variable.
+    comment_at_word: bool,
+    block_comment: Option<(&'static str, &'static str)>,
+    nested_comments: bool,
+    /// Tried in order, so longer openers go first.
+    quotes: &'static [Quote],
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    constants: &'static [&'static str],
+    /// Chars other than letters, digits and "_" that are part of words.
+    word_chars: &'static str,
+    /// Capitalised words are types and all capitals constants.
+    capitals: bool,
+    /// Words followed by "(" are function calls, as are words followed by "!" and the
+    /// word after "fn".
+    calls: bool,
+    /// Rust raw strings like r#"..."#.
+    raw_strings: bool,
+    /// "'" starts a char literal or a lifetime.
+    lifetimes: bool,
+    /// Rust attributes like #[derive(Debug)].
+    attributes: bool,
+    /// Strings and words followed by this are keys.
+    key_sep: Option<char>,
+    /// A line starting with "[" is a TOML table header.
+    tables: bool,
+    /// "$name" and "${name}" are variables.
+    variables: bool,
+    /// "-" before a digit is part of the number.
+    signed_numbers: bool,
+}
+
+const RUST: Rules = Rules {
+    line_comment: Some("//"),
+    comment_at_word: false,
+    block_comment: Some(("/*", "*/")),
+    nested_comments: true,
+    quotes: &[Quote {
+        open: "\"",
+        close: "\"",
+        escapes: true,
+        multiline: true,
+    }],
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+        "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "type",
+        "unsafe", "use", "where", "while", "yield",
+    ],
+    types: &[
+        "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32",
+        "i64", "i128", "isize", "f32", "f64",
+    ],
+    constants: &["true", "false"],
+    word_chars: "",
+    capitals: true,
+    calls: true,
+    raw_strings: true,
+    lifetimes: true,
+    attributes: true,
+    key_sep: None,
+    tables: false,
+    variables: false,
+    signed_numbers: false,
+};
+
+const TOML: Rules = Rules {
+    line_comment: Some("#"),
+    comment_at_word: false,
+    block_comment: None,
+    nested_comments: false,
+    quotes: &[
+        Quote {
+            open: "\"\"\"",
+            close: "\"\"\"",
+            escapes: true,
+            multiline: true,
+        },
+        Quote {
+            open: "'''",
+            close: "'''",
+            escapes: false,
+            multiline: true,
+        },
+        Quote {
+            open: "\"",
+            close: "\"",
+            escapes: true,
+            multiline: false,
+        },
+        Quote {
+            open: "'",
+            close: "'",
+            escapes: false,
+            multiline: false,
+        },
+    ],
+    keywords: &[],
+    types: &[],
+    constants: &["true", "false", "inf", "nan"],
+    word_chars: "-",
+    capitals: false,
+    calls: false,
+    raw_strings: false,
+    lifetimes: false,
+    attributes: false,
+    key_sep: Some('='),
+    tables: true,
+    variables: false,
+    signed_numbers: true,
+};
+
+const SHELL: Rules = Rules {
+    line_comment: Some("#"),
+    comment_at_word: true,
+    block_comment: None,
+    nested_comments: false,
+    quotes: &[
+        Quote {
+            open: "\"",
+            close: "\"",
+            escapes: true,
+            multiline: true,
+        },
+        Quote {
+            open: "'",
+            close: "'",
+            escapes: false,
+            multiline: true,
+        },
+    ],
+    keywords: &[
+        "if", "then", "else", "elif", "fi", "case", "esac", "for", "while", "until", "do", "done",
+        "in", "function", "select", "return", "local", "export", "readonly", "declare", "unset",
+        "shift", "exit", "break", "continue", "time",
+    ],
+    types: &[],
+    constants: &[],
+    word_chars: "-",
+    capitals: false,
+    calls: false,
+    raw_strings: false,
+    lifetimes: false,
+    attributes: false,
+    key_sep: None,
+    tables: false,
+    variables: true,
+    signed_numbers: false,
+};
+
+const JSON: Rules = Rules {
+    line_comment: None,
+    comment_at_word: false,
+    block_comment: None,
+    nested_comments: false,
+    quotes: &[Quote {
+        open: "\"",
+        close: "\"",
+        escapes: true,
+        multiline: false,
+    }],
+    keywords: &[],
+    types: &[],
+    constants: &["true", "false", "null"],
+    word_chars: "",
+    capitals: false,
+    calls: false,
+    raw_strings: false,
+    lifetimes: false,
+    attributes: false,
+    key_sep: Some(':'),
+    tables: false,
+    variables: false,
+    signed_numbers: true,
+};
+
+/// Lexes one line, without its line break, starting in state. Returns the line's
+/// tokens and the state the next line starts in.
+pub fn lex_line(language: Language, state: &State, line: &str) -> (Vec<Token>, State) {
+    let chars: Vec<char> = line.chars().collect();
+    match language.rules() {
+        Some(rules) => Lexer {
+            rules,
+            chars: &chars,
+            tokens: Vec::new(),
+        }
+        .run(state),
+        None if language == Language::Markdown => markdown(state, &chars),
+        None => (Vec::new(), State::Normal),
+    }
+}
+
+struct Lexer<'a> {
+    rules: &'a Rules,
+    chars: &'a [char],
+    tokens: Vec<Token>,
+}
+
+impl Lexer<'_> {
+    fn run(mut self, state: &State) -> (Vec<Token>, State) {
+        let carried = match state {
+            State::Comment(depth) => self.comment(0, 0, *depth),
+            State::String { close, escapes } => self.string(0, 0, close, *escapes, true),
+            State::Normal | State::Fence(_) => Ok(0),
+        };
+        let mut idx = match carried {
+            Ok(idx) => idx,
+            Err(state) => return (self.tokens, state),
+        };
+        while idx < self.chars.len() {
+            match self.token(idx) {
+                Ok(end) => idx = end,
+                Err(state) => return (self.tokens, state),
+            }
+        }
+        (self.tokens, State::Normal)
+    }
+
+    /// True when text starts at idx.
+    fn at(&self, idx: usize, text: &str) -> bool {
+        text.chars()
+            .enumerate()
+            .all(|(n, ch)| self.chars.get(idx + n) == Some(&ch))
+    }
+
+    fn is_word(&self, ch: char) -> bool {
+        ch.is_alphanumeric() || ch == '_' || self.rules.word_chars.contains(ch)
+    }
+
+    fn word_start(&self, idx: usize) -> bool {
+        idx == 0 || !self.is_word(self.chars[idx - 1])
+    }
+
+    fn push(&mut self, start: usize, end: usize, face: Face) {
+        if start < end {
+            self.tokens.push(Token { start, end, face });
+        }
+    }
+
+    /// Lexes the token at idx. Returns where the next one starts, or the state the line
+    /// ends in when the token runs on to the next line.
+    fn token(&mut self, idx: usize) -> Result<usize, State> {
+        let rules = self.rules;
+        let len = self.chars.len();
+        let ch = self.chars[idx];
+        if ch.is_whitespace() {
+            return Ok(idx + 1);
+        }
+        if let Some(comment) = rules.line_comment {
+            if self.at(idx, comment)
+                && (!rules.comment_at_word || idx == 0 || self.chars[idx - 1].is_whitespace())
+            {
+                self.push(idx, len, Face::Comment);
+                return Ok(len);
+            }
+        }
+        if let Some((open, _)) = rules.block_comment {
+            if self.at(idx, open) {
+                return self.comment(idx, idx + open.chars().count(), 1);
+            }
+        }
+        if rules.tables && ch == '[' && self.chars[..idx].iter().all(|ch| ch.is_whitespace()) {
+            let end = match self.chars[idx..].iter().position(|ch| *ch == ']') {
+                Some(close) => {
+                    let close = idx + close;
+                    close + 1 + usize::from(self.chars.get(close + 1) == Some(&']'))
+                }
+                None => len,
+            };
+            self.push(idx, end, Face::Heading);
+            return Ok(end);
+        }
+        if rules.attributes && (self.at(idx, "#[") || self.at(idx, "#![")) {
+            let end = self.bracket_end(idx);
+            self.push(idx, end, Face::Attribute);
+            return Ok(end);
+        }
+        if rules.raw_strings && self.word_start(idx) {
+            if let Some((open, hashes)) = self.raw_string(idx) {
+                let close = format!("\"{}", "#".repeat(hashes));
+                return self.string(idx, idx + open, &close, false, true);
+            }
+        }
+        if rules.lifetimes && ch == '\'' {
+            return Ok(self.char_or_lifetime(idx));
+        }
+        for quote in rules.quotes {
+            if self.at(idx, quote.open) {
+                let from = idx + quote.open.chars().count();
+                return self.string(idx, from, quote.close, quote.escapes, quote.multiline);
+            }
+        }
+        if rules.variables && ch == '$' {
+            let end = self.variable_end(idx);
+            self.push(idx, end, Face::Variable);
+            return Ok(end);
+        }
+        let signed = rules.signed_numbers
+            && ch == '-'
+            && self.chars.get(idx + 1).is_some_and(char::is_ascii_digit);
+        if (ch.is_ascii_digit() || signed) && self.word_start(idx) {
+            let mut end = idx + 1;
+            while end < len
+                && (self.is_word(self.chars[end])
+                    || self.chars[end] == '.'
+                        && self.chars.get(end + 1).is_some_and(char::is_ascii_digit))
+            {
+                end += 1;
+            }
+            self.push(idx, end, Face::Number);
+            return Ok(end);
+        }
+        if self.is_word(ch) {
+            return Ok(self.word(idx));
+        }
+        Ok(idx + 1)
+    }
+
+    /// Lexes a block comment from from, depth deep, with the token starting at start.
+    fn comment(&mut self, start: usize, from: usize, mut depth: usize) -> Result<usize, State> {
+        let Some((open, close)) = self.rules.block_comment else {
+            return Ok(from);
+        };
+        let mut idx = from;
+        while idx < self.chars.len() {
+            if self.at(idx, close) {
+                idx += close.chars().count();
+                depth -= 1;
+                if depth == 0 {
+                    self.push(start, idx, Face::Comment);
+                    return Ok(idx);
+                }
+            } else if self.rules.nested_comments && self.at(idx, open) {
+                idx += open.chars().count();
+                depth += 1;
+            } else {
+                idx += 1;
+            }
+        }
+        self.push(start, idx, Face::Comment);
+        Err(State::Comment(depth))
+    }
+
+    /// Lexes a string from from up to close, with the token starting at start. A string
+    /// left open ends with the line unless it is multiline.
+    fn string(
+        &mut self,
+        start: usize,
+        from: usize,
+        close: &str,
+        escapes: bool,
+        multiline: bool,
+    ) -> Result<usize, State> {
+        let len = self.chars.len();
+        let mut idx = from;
+        while idx < len {
+            if escapes && self.chars[idx] == '\\' {
+                idx += 2;
+            } else if self.at(idx, close) {
+                let end = idx + close.chars().count();
+                let face = match self.before_key_sep(end) {
+                    true => Face::Key,
+                    false => Face::String,
+                };
+                self.push(start, end, face);
+                return Ok(end);
+            } else {
+                idx += 1;
+            }
+        }
+        self.push(start, len, Face::String);
+        match multiline {
+            true => Err(State::String {
+                close: close.to_string(),
+                escapes,
+            }),
+            false => Ok(len),
+        }
+    }
+
+    /// True when the next thing after idx is the key separator, or a "." of a dotted
+    /// TOML key.
+    fn before_key_sep(&self, idx: usize) -> bool {
+        let Some(sep) = self.rules.key_sep else {
+            return false;
+        };
+        let next = self.chars[idx.min(self.chars.len())..]
+            .iter()
+            .find(|ch| !ch.is_whitespace());
+        next == Some(&sep) || (self.rules.tables && next == Some(&'.'))
+    }
+
+    /// Where the bracket opened at or just after idx is closed, or the end of the line.
+    fn bracket_end(&self, idx: usize) -> usize {
+        let mut depth = 0;
+        for (pos, ch) in self.chars.iter().enumerate().skip(idx) {
+            match ch {
+                '[' => depth += 1,
+                ']' if depth == 1 => return pos + 1,
+                ']' => depth -= 1,
+                _ => {}
+            }
+        }
+        self.chars.len()
+    }
+
+    /// The length of the opener of a raw string like r#" or br" at idx, with the
+    /// number of "#".
+    fn raw_string(&self, idx: usize) -> Option<(usize, usize)> {
+        let mut pos = idx + usize::from(self.chars[idx] == 'b');
+        if self.chars.get(pos) != Some(&'r') {
+            return None;
+        }
+        pos += 1;
+        let hashes = self.chars[pos..]
+            .iter()
+            .take_while(|ch| **ch == '#')
+            .count();
+        pos += hashes;
+        (self.chars.get(pos) == Some(&'"')).then_some((pos + 1 - idx, hashes))
+    }
+
+    /// Lexes 'a', '\n' or a lifetime like 'a at idx.
+    fn char_or_lifetime(&mut self, idx: usize) -> usize {
+        let len = self.chars.len();
+        if self.chars.get(idx + 1) == Some(&'\\') {
+            let end = self.chars[idx + 2..]
+                .iter()
+                .position(|ch| *ch == '\'')
+                .map_or(len, |close| idx + 2 + close + 1);
+            self.push(idx, end, Face::String);
+            return end;
+        }
+        if self.chars.get(idx + 2) == Some(&'\'') {
+            self.push(idx, idx + 3, Face::String);
+            return idx + 3;
+        }
+        let mut end = idx + 1;
+        while end < len && self.is_word(self.chars[end]) {
+            end += 1;
+        }
+        self.push(idx, end, Face::Variable);
+        end
+    }
+
+    /// Where the shell variable at idx ends: "${...}", "$name" or "$?" and the like.
+    fn variable_end(&self, idx: usize) -> usize {
+        let len = self.chars.len();
+        match self.chars.get(idx + 1) {
+            Some('{') => self.chars[idx..]
+                .iter()
+                .position(|ch| *ch == '}')
+                .map_or(len, |close| idx + close + 1),
+            Some(ch) if ch.is_alphabetic() || *ch == '_' => {
+                let mut end = idx + 1;
+                while end < len && (self.chars[end].is_alphanumeric() || self.chars[end] == '_') {
+                    end += 1;
+                }
+                end
+            }
+            Some(ch) if "?#@*!$-0123456789".contains(*ch) => idx + 2,
+            _ => idx + 1,
+        }
+    }
+
+    /// Lexes the word at idx, giving it a face by what it is and what follows it.
+    fn word(&mut self, idx: usize) -> usize {
+        let rules = self.rules;
+        let len = self.chars.len();
+        let mut end = idx;
+        while end < len && self.is_word(self.chars[end]) {
+            end += 1;
+        }
+        let word: std::string::String = self.chars[idx..end].iter().collect();
+        let next = self.chars[end..].iter().find(|ch| !ch.is_whitespace());
+        let face = if rules.keywords.contains(&word.as_str()) {
+            Some(Face::Keyword)
+        } else if rules.constants.contains(&word.as_str()) {
+            Some(Face::Constant)
+        } else if rules.types.contains(&word.as_str()) {
+            Some(Face::Type)
+        } else if self.before_key_sep(end) {
+            Some(Face::Key)
+        } else if rules.calls
+            && (next == Some(&'(')
+                || self.chars.get(end) == Some(&'!') && self.chars.get(end + 1) != Some(&'=')
+                || self.after_fn(idx))
+        {
+            Some(Face::Function)
+        } else if rules.capitals && word.starts_with(char::is_uppercase) {
+            match word.len() > 1 && !word.contains(char::is_lowercase) {
+                true => Some(Face::Constant),
+                false => Some(Face::Type),
+            }
+        } else {
+            None
+        };
+        if let Some(face) = face {
+            self.push(idx, end, face);
+        }
+        end
+    }
+
+    /// True when the word at idx is the name in "fn name".
+    fn after_fn(&self, idx: usize) -> bool {
+        let mut end = idx;
+        while end > 0 && self.chars[end - 1].is_whitespace() {
+            end -= 1;
+        }
+        end < idx
+            && end >= 2
+            && self.chars[end - 2..end] == ['f', 'n']
+            && (end == 2 || !self.is_word(self.chars[end - 3]))
+    }
+}
+
+/// Lexes a line of Markdown. Only fenced code blocks carry over from line to line.
+fn markdown(state: &State, chars: &[char]) -> (Vec<Token>, State) {
+    let len = chars.len();
+    let whole = |face| match len {
+        0 => Vec::new(),
+        _ => vec![Token {
+            start: 0,
+            end: len,
+            face,
+        }],
+    };
+    let indent = chars.iter().take_while(|ch| **ch == ' ').count();
+    let rest: std::string::String = chars[indent..].iter().collect();
+    if let State::Fence(fence) = state {
+        return match indent < 4 && rest.starts_with(fence.as_str()) {
+            true => (whole(Face::Code), State::Normal),
+            false => (whole(Face::Code), state.clone()),
+        };
+    }
+    if indent < 4 {
+        if let Some(fence) = ["```", "~~~"]
+            .into_iter()
+            .find(|fence| rest.starts_with(fence))
+        {
+            return (whole(Face::Code), State::Fence(fence.to_string()));
+        }
+        let hashes = rest.chars().take_while(|ch| *ch == '#').count();
+        if (1..=6).contains(&hashes) && rest[hashes..].chars().next().is_none_or(|ch| ch == ' ') {
+            return (whole(Face::Heading), State::Normal);
+        }
+        if rest.starts_with('>') {
+            return (whole(Face::Comment), State::Normal);
+        }
+    }
+    let mut tokens = Vec::new();
+    let digits = rest.chars().take_while(char::is_ascii_digit).count();
+    let marker = match rest.chars().next() {
+        Some('-' | '*' | '+') if rest[1..].starts_with(' ') => 1,
+        Some(_)
+            if digits > 0
+                && (rest[digits..].starts_with(". ") || rest[digits..].starts_with(") ")) =>
+        {
+            digits + 1
+        }
+        _ => 0,
+    };
+    if marker > 0 {
+        tokens.push(Token {
+            start: indent,
+            end: indent + marker,
+            face: Face::Keyword,
+        });
+    }
+    inline(chars, indent + marker, &mut tokens);
+    (tokens, State::Normal)
+}
+
+/// Code spans, emphasis and links within a Markdown line.
+fn inline(chars: &[char], mut idx: usize, tokens: &mut Vec<Token>) {
+    let len = chars.len();
+    let find = |from: usize, delim: &[char]| {
+        (from..len.saturating_sub(delim.len() - 1)).find(|pos| chars[*pos..].starts_with(delim))
+    };
+    while idx < len {
+        let ch = chars[idx];
+        let run = chars[idx..].iter().take_while(|next| **next == ch).count();
+        let token = match ch {
+            '`' => find(idx + run, &chars[idx..idx + run]).map(|close| (close + run, Face::Code)),
+            '*' | '_'
+                if chars
+                    .get(idx + run)
+                    .is_some_and(|next| !next.is_whitespace())
+                    && (ch == '*' || idx == 0 || !chars[idx - 1].is_alphanumeric()) =>
+            {
+                find(idx + run + 1, &chars[idx..idx + run])
+                    .map(|close| (close + run, Face::Emphasis))
+            }
+            '[' => find(idx + 1, &[']', '('])
+                .and_then(|mid| find(mid + 2, &[')']))
+                .map(|close| (close + 1, Face::Link)),
+            '<' if chars[idx..].starts_with(&['<', 'h', 't', 't', 'p']) => {
+                find(idx + 1, &['>']).map(|close| (close + 1, Face::Link))
+            }
+            _ => None,
+        };
+        match token {
+            Some((end, face)) => {
+                tokens.push(Token {
+                    start: idx,
+                    end,
+                    face,
+                });
+                idx = end;
+            }
+            None => idx += run,
+        }
+    }
+}
+
+/// The lexed form of one line, kept until its text or the state before it change.
+#[derive(Debug, Clone)]
+struct Lexed {
+    start: State,
+    hash: u64,
+    tokens: Vec<Token>,
+    end: State,
+}
+
+/// Highlighting for one buffer. Lines are lexed in order as they are asked for and
+/// the results kept with the state each line starts and ends in. An edit only drops
+/// the lines it touched; lines below it are lexed again only when the state they
+/// start in changed, as when a block comment is opened above them.
+#[derive(Debug, Clone, Default)]
+pub struct Highlighter {
+    language: Language,
+    lines: Vec<Option<Lexed>>,
+    /// Lines before this one are known to match the text.
+    checked: usize,
+    /// How many times a line was lexed.
+    lexed: usize,
+}
+
+impl Highlighter {
+    pub fn new(language: Language) -> Self {
+        Highlighter {
+            language,
+            ..Default::default()
+        }
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// Forgets every line, for when the whole text was replaced.
+    pub fn reset(&mut self) {
+        self.lines.clear();
+        self.checked = 0;
+    }
+
+    /// Records an edit starting on line that took the text from before to after
+    /// lines, keeping what was lexed for the lines after it.
+    pub fn edited(&mut self, line: usize, before: usize, after: usize) {
+        self.checked = self.checked.min(line);
+        if line >= self.lines.len() {
+            return;
+        }
+        self.lines[line] = None;
+        let at = line + 1;
+        if after > before {
+            self.lines
+                .splice(at..at, std::iter::repeat_n(None, after - before));
+        } else {
+            let end = (at + before - after).min(self.lines.len());
+            self.lines.drain(at..end);
+        }
+    }
+
+    /// The tokens of line of rope, lexing it and the lines before it as needed.
+    pub fn tokens(&mut self, rope: &Rope, line: usize) -> Vec<Token> {
+        if self.language == Language::Plain || line >= rope.len_lines() {
+            return Vec::new();
+        }
+        self.lines.resize(rope.len_lines(), None);
+        for idx in self.checked..=line {
+            let start = match idx {
+                0 => State::Normal,
+                _ => self.lines[idx - 1]
+                    .as_ref()
+                    .map_or(State::Normal, |prev| prev.end.clone()),
+            };
+            let text = line_text(rope, idx);
+            let mut hasher = DefaultHasher::new();
+            text.hash(&mut hasher);
+            let hash = hasher.finish();
+            if matches!(&self.lines[idx], Some(lexed) if lexed.start == start && lexed.hash == hash)
+            {
+                continue;
+            }
+            let (tokens, end) = lex_line(self.language, &start, &text);
+            self.lexed += 1;
+            self.lines[idx] = Some(Lexed {
+                start,
+                hash,
+                tokens,
+                end,
+            });
+        }
+        self.checked = self.checked.max(line + 1);
+        self.lines[line]
+            .as_ref()
+            .map(|lexed| lexed.tokens.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// line of rope without its line break.
+fn line_text(rope: &Rope, line: usize) -> std::string::String {
+    let mut text = rope.line(line).to_string();
+    while text.ends_with(['\n', '\r']) {
+        text.pop();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tokens of line as (text, face) pairs.
+    fn lex(
+        language: Language,
+        state: &State,
+        line: &str,
+    ) -> (Vec<(std::string::String, Face)>, State) {
+        let (tokens, end) = lex_line(language, state, line);
+        let chars: Vec<char> = line.chars().collect();
+        let tokens = tokens
+            .iter()
+            .map(|token| (chars[token.start..token.end].iter().collect(), token.face))
+            .collect();
+        (tokens, end)
+    }
+
+    fn faces(language: Language, line: &str) -> Vec<(std::string::String, Face)> {
+        lex(language, &State::Normal, line).0
+    }
+
+    fn pairs(expected: &[(&str, Face)]) -> Vec<(std::string::String, Face)> {
+        expected
+            .iter()
+            .map(|(text, face)| (text.to_string(), *face))
+            .collect()
+    }
+
+    #[test]
+    fn test_rust() {
+        assert_eq!(
+            faces(
+                Language::Rust,
+                "#[derive(Debug)] pub fn main() -> Option<u8> { // hi"
+            ),
+            pairs(&[
+                ("#[derive(Debug)]", Face::Attribute),
+                ("pub", Face::Keyword),
+                ("fn", Face::Keyword),
+                ("main", Face::Function),
+                ("Option", Face::Type),
+                ("u8", Face::Type),
+                ("// hi", Face::Comment),
+            ])
+        );
+        assert_eq!(
+            faces(
+                Language::Rust,
+                "let c = 'x'; f::<'a>(r#\"a\"b\"#, MAX, 1.5e3, true);"
+            ),
+            pairs(&[
+                ("let", Face::Keyword),
+                ("'x'", Face::String),
+                ("'a", Face::Variable),
+                ("r#\"a\"b\"#", Face::String),
+                ("MAX", Face::Constant),
+                ("1.5e3", Face::Number),
+                ("true", Face::Constant),
+            ])
+        );
+        assert_eq!(
+            faces(Language::Rust, "println!(\"{}\\\"\", x);"),
+            pairs(&[("println", Face::Function), ("\"{}\\\"\"", Face::String)])
+        );
+
+        // Nested block comments and strings carry over to the next line.
+        let (tokens, state) = lex(Language::Rust, &State::Normal, "a /* b /* c */");
+        assert_eq!(tokens, pairs(&[("/* b /* c */", Face::Comment)]));
+        assert_eq!(state, State::Comment(1));
+        let (tokens, state) = lex(Language::Rust, &state, "d */ e");
+        assert_eq!(tokens, pairs(&[("d */", Face::Comment)]));
+        assert_eq!(state, State::Normal);
+        let (_, state) = lex(Language::Rust, &State::Normal, "let s = \"open");
+        let (tokens, state) = lex(Language::Rust, &state, "still\" + 1");
+        assert_eq!(
+            tokens,
+            pairs(&[("still\"", Face::String), ("1", Face::Number)])
+        );
+        assert_eq!(state, State::Normal);
+    }
+
+    #[test]
+    fn test_toml_json_shell() {
+        assert_eq!(
+            faces(Language::Toml, "[[bin]] # x"),
+            pairs(&[("[[bin]]", Face::Heading), ("# x", Face::Comment)])
+        );
+        assert_eq!(
+            faces(Language::Toml, "edition-x.\"a\" = 'it''s' # c"),
+            pairs(&[
+                ("edition-x", Face::Key),
+                ("\"a\"", Face::Key),
+                ("'it'", Face::String),
+                ("'s'", Face::String),
+                ("# c", Face::Comment),
+            ])
+        );
+        let (tokens, state) = lex(Language::Toml, &State::Normal, "a = \"\"\"x");
+        assert_eq!(
+            state,
+            State::String {
+                close: "\"\"\"".to_string(),
+                escapes: true,
+            }
+        );
+        assert_eq!(
+            faces(Language::Json, "{\"n\": -1.5, \"s\": \"a:b\", \"t\": null}"),
+            pairs(&[
+                ("\"n\"", Face::Key),
+                ("-1.5", Face::Number),
+                ("\"s\"", Face::Key),
+                ("\"a:b\"", Face::String),
+                ("\"t\"", Face::Key),
+                ("null", Face::Constant),
+            ])
+        );
+        assert_eq!(
+            faces(
+                Language::Shell,
+                "if [ $# -gt 0 ]; then echo \"${HOME}\" a#b # c"
+            ),
+            pairs(&[
+                ("if", Face::Keyword),
+                ("$#", Face::Variable),
+                ("0", Face::Number),
+                ("then", Face::Keyword),
+                ("\"${HOME}\"", Face::String),
+                ("# c", Face::Comment),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_markdown() {
+        assert_eq!(
+            faces(Language::Markdown, "## Title"),
+            pairs(&[("## Title", Face::Heading)])
+        );
+        assert_eq!(
+            faces(Language::Markdown, "- a `b` *c* [d](e) snake_case"),
+            pairs(&[
+                ("-", Face::Keyword),
+                ("`b`", Face::Code),
+                ("*c*", Face::Emphasis),
+                ("[d](e)", Face::Link),
+            ])
+        );
+        let (_, state) = lex(Language::Markdown, &State::Normal, "```rust");
+        let (tokens, state) = lex(Language::Markdown, &state, "# not a heading");
+        assert_eq!(tokens, pairs(&[("# not a heading", Face::Code)]));
+        let (_, state) = lex(Language::Markdown, &state, "```");
+        assert_eq!(state, State::Normal);
+    }
+
+    #[test]
+    fn test_incremental() {
+        let mut rope = Rope::from_str("fn a() {}\nlet b = 1;\nlet c = 2;\n");
+        let mut highlighter = Highlighter::new(Language::Rust);
+        highlighter.tokens(&rope, 3);
+        assert_eq!(highlighter.lexed, 4);
+
+        // Changing one line lexes just that line again.
+        rope.insert(rope.line_to_char(1) + 4, "bb");
+        highlighter.edited(1, 4, 4);
+        highlighter.tokens(&rope, 3);
+        assert_eq!(highlighter.lexed, 5);
+
+        // Inserted lines shift the rest, which is kept.
+        let (before, at) = (rope.len_lines(), rope.line_to_char(1));
+        rope.insert(at, "x\ny\n");
+        highlighter.edited(1, before, rope.len_lines());
+        highlighter.tokens(&rope, 5);
+        assert_eq!(highlighter.lexed, 8);
+
+        // Opening a block comment changes the state every later line starts in.
+        rope.insert(0, "/*");
+        highlighter.edited(0, rope.len_lines(), rope.len_lines());
+        let tokens = highlighter.tokens(&rope, 4);
+        assert_eq!(tokens[0].face, Face::Comment);
+        assert_eq!(highlighter.lexed, 13);
+
+        // Without an edit nothing is lexed again.
+        highlighter.tokens(&rope, 5);
+        assert_eq!(highlighter.lexed, 14);
+        highlighter.tokens(&rope, 5);
+        assert_eq!(highlighter.lexed, 14);
+    }
+}
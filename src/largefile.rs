@@ -0,0 +1,196 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use crate::ffi::Mmap;
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+
+/// Files at least this big are opened as a read-only large-file view instead of being
+/// loaded into a rope.
+pub const LARGE_FILE_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// How many bytes are scanned for line breaks at a time while indexing.
+const INDEX_CHUNK: usize = 64 * 1024;
+
+/// A memory mapped file with an index of line start offsets that only grows as far as
+/// the lines asked for. Jumping to line 10 of a 4 GB log touches the first pages of
+/// the file and nothing else. The index sits behind a RefCell so lines can be read
+/// through a shared reference while the screen is drawn.
+#[derive(Debug)]
+pub struct LargeFile {
+    /// Kept open to notice when the mapped file is truncated in place.
+    file: std::fs::File,
+    map: Mmap,
+    line_starts: RefCell<Vec<usize>>,
+    indexed_to: Cell<usize>,
+}
+
+impl LargeFile {
+    pub fn open(path: &Path) -> std::io::Result<LargeFile> {
+        let file = std::fs::File::open(path)?;
+        Ok(LargeFile {
+            map: Mmap::map(&file)?,
+            file,
+            line_starts: RefCell::new(vec![0]),
+            indexed_to: Cell::new(0),
+        })
+    }
+
+    /// Maps the file again when it became shorter than the map, as a log truncated
+    /// by logrotate's copytruncate does; reading the old map past the new end would
+    /// raise SIGBUS. The line index starts over. Returns true when the file shrank.
+    ///
+    /// This only narrows the window: a truncation after the check and before a later
+    /// read of bytes or line can still raise SIGBUS.
+    pub fn remap_if_shrunk(&mut self) -> std::io::Result<bool> {
+        let len = self.file.metadata()?.len();
+        if len >= self.map.len() as u64 {
+            return Ok(false);
+        }
+        // Nothing of the old map may be read again, also when mapping fails.
+        self.map = Mmap::empty();
+        self.line_starts = RefCell::new(vec![0]);
+        self.indexed_to = Cell::new(0);
+        self.map = Mmap::map(&self.file)?;
+        Ok(true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.as_slice().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        self.map.as_slice()
+    }
+
+    /// True once the whole file was scanned and line_count is exact.
+    pub fn fully_indexed(&self) -> bool {
+        self.indexed_to.get() == self.len()
+    }
+
+    /// Lines known so far. The same as the number of lines once fully_indexed.
+    pub fn line_count(&self) -> usize {
+        self.line_starts.borrow().len()
+    }
+
+    /// Scans the next chunk for line breaks. Returns false when there was nothing left.
+    fn index_chunk(&self) -> bool {
+        let (start, len) = (self.indexed_to.get(), self.len());
+        if start == len {
+            return false;
+        }
+        let end = (start + INDEX_CHUNK).min(len);
+        let mut line_starts = self.line_starts.borrow_mut();
+        for (idx, byte) in self.map.as_slice()[start..end].iter().enumerate() {
+            if *byte == b'\n' {
+                line_starts.push(start + idx + 1);
+            }
+        }
+        self.indexed_to.set(end);
+        true
+    }
+
+    /// The byte offset line starts at, indexing as far as needed. None past the end.
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        while line >= self.line_count() {
+            if !self.index_chunk() {
+                return None;
+            }
+        }
+        Some(self.line_starts.borrow()[line])
+    }
+
+    /// The bytes of line without its line break.
+    pub fn line(&self, line: usize) -> Option<&[u8]> {
+        let start = self.line_start(line)?;
+        let end = match self.line_start(line + 1) {
+            Some(next) => next - 1,
+            None => self.len(),
+        };
+        let bytes = &self.map.as_slice()[start..end];
+        Some(bytes.strip_suffix(b"\r").unwrap_or(bytes))
+    }
+
+    /// The line offset is on.
+    pub fn line_of_offset(&self, offset: usize) -> usize {
+        while self.indexed_to.get() <= offset.min(self.len()) && self.index_chunk() {}
+        match self.line_starts.borrow().binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        }
+    }
+
+    /// Indexes the rest of the file and returns the exact number of lines.
+    pub fn count_lines(&self) -> usize {
+        while self.index_chunk() {}
+        self.line_count()
+    }
+
+    /// The offset of the first match of needle at or after from.
+    pub fn find(&self, needle: &[u8], from: usize) -> Option<usize> {
+        let haystack = self.map.as_slice().get(from..)?;
+        if needle.is_empty() {
+            return Some(from);
+        }
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .map(|pos| from + pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn test_lazy_line_index() {
+        let dir = scratch_dir("large");
+        let path = dir.join("large.log");
+        let text: std::string::String = (0..50_000).map(|n| format!("line {}\r\n", n)).collect();
+        std::fs::write(&path, &text).unwrap();
+
+        let large = LargeFile::open(&path).unwrap();
+        assert_eq!(large.line(2), Some(&b"line 2"[..]));
+        assert!(!large.fully_indexed());
+        assert_eq!(large.line(49_999), Some(&b"line 49999"[..]));
+        assert_eq!(large.line(50_000), Some(&b""[..]));
+        assert_eq!(large.line(50_001), None);
+        assert_eq!(large.count_lines(), 50_001);
+        assert!(large.fully_indexed());
+
+        let offset = large.find(b"line 1234\r", 0).unwrap();
+        assert_eq!(large.line_of_offset(offset), 1234);
+        assert_eq!(large.find(b"line 1234\r", offset + 1), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_remap_after_truncate() {
+        let dir = scratch_dir("trunc");
+        let path = dir.join("trunc.log");
+        let text: std::string::String = (0..10_000).map(|n| format!("line {}\n", n)).collect();
+        std::fs::write(&path, &text).unwrap();
+
+        let mut large = LargeFile::open(&path).unwrap();
+        assert_eq!(large.count_lines(), 10_001);
+        assert!(!large.remap_if_shrunk().unwrap());
+        // Truncated in place, the way copytruncate does it.
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(7)
+            .unwrap();
+        assert!(large.remap_if_shrunk().unwrap());
+        assert_eq!(large.len(), 7);
+        assert_eq!(large.line(0), Some(&b"line 0"[..]));
+        assert_eq!(large.count_lines(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -3,3 +3,22 @@ pub mod screen;
 pub mod document;
 pub mod event;
 pub mod program;
+pub mod minibuffer;
+pub mod cli;
+pub mod encoding;
+pub mod autosave;
+pub mod backup;
+pub mod largefile;
+pub mod hexl;
+pub mod dired;
+pub mod visual;
+pub mod grapheme;
+pub mod syntax;
+pub mod theme;
+pub mod highlight;
+pub mod modeline;
+pub mod messages;
+pub mod gutter;
+pub mod whitespace;
+#[cfg(test)]
+mod test_support;
@@ -3,22 +3,39 @@
 
 extern crate libc;
 use std::env;
+use std::process::ExitCode;
 
 use mr_text::{
+    cli::{self, Action},
     program,
     document::{self, Doc, Document, NewDocument},
     ffi,
     screen::{self, Builder, DrawScreen, Screen},
 };
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let mut mr_text = program::MrText::<NewDocument>::new();
-
-    if args.is_empty() {
-        mr_text.run();
-    } else {
-        mr_text.open_doc(&args[0]);
-        mr_text.run();
+fn main() -> ExitCode {
+    let cli = match cli::parse_args(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("mr_text: {}\n\n{}", err, cli::USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match cli.action {
+        Action::Help => {
+            print!("{}", cli::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Action::Version => {
+            println!("mr_text {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        Action::Edit => {}
     }
+
+    // Piped input is read before the terminal is set up.
+    let stdin = program::read_stdin(&cli);
+    let mut mr_text = program::MrText::new();
+    mr_text.open_args(&cli, stdin);
+    mr_text.run()
 }
@@ -0,0 +1,159 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use crate::{ffi, visual};
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The name of the read-only buffer that shows the message log.
+pub const MESSAGES_BUFFER: &str = "*Messages*";
+
+/// How many messages the log keeps before dropping the oldest ones.
+pub const MESSAGE_LOG_MAX: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// One message shown in the echo area.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub time: SystemTime,
+    pub severity: Severity,
+    pub text: std::string::String,
+}
+
+/// "HH:MM:SS [severity] text" in local time. Further lines of a message are indented
+/// under the first.
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = match self.time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(_) => 0,
+        };
+        let time = ffi::local_time(secs).unwrap_or_default();
+        write!(
+            f,
+            "{:02}:{:02}:{:02} [{}] ",
+            time.hour, time.min, time.sec, self.severity
+        )?;
+        for (idx, line) in self.text.lines().enumerate() {
+            if idx > 0 {
+                write!(f, "\n    ")?;
+            }
+            write!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every message the echo area showed, oldest first, up to MESSAGE_LOG_MAX of them.
+#[derive(Debug, Clone, Default)]
+pub struct Messages {
+    log: VecDeque<Message>,
+    /// How many messages were ever logged, including those dropped since.
+    count: usize,
+}
+
+impl Messages {
+    pub fn push(&mut self, severity: Severity, text: &str) {
+        if self.log.len() == MESSAGE_LOG_MAX {
+            self.log.pop_front();
+        }
+        self.log.push_back(Message {
+            time: SystemTime::now(),
+            severity,
+            text: text.to_string(),
+        });
+        self.count += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Message> {
+        self.log.iter()
+    }
+
+    pub fn last(&self) -> Option<&Message> {
+        self.log.back()
+    }
+
+    /// Goes up by one with every message logged, so a view of the log can tell it
+    /// is out of date.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The contents of the *Messages* buffer, one message per line.
+    pub fn text(&self) -> std::string::String {
+        let mut text = std::string::String::new();
+        for message in &self.log {
+            text.push_str(&message.to_string());
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// What the echo area shows of text: its first line cut to width columns between
+/// grapheme clusters, with "$" in the last column when some of it is left out.
+pub fn echo_line(text: &str, width: usize) -> std::string::String {
+    let first = text.lines().next().unwrap_or_default();
+    let cut = visual::truncate(first, 0, width, 1, &[]);
+    match text.lines().nth(1) {
+        Some(_) if visual::display_col(&cut, cut.chars().count(), 1) < width => {
+            // Show that the message goes on past the first line.
+            let mut cut = visual::truncate(first, 0, width.saturating_sub(1), 1, &[]);
+            cut.push('$');
+            cut
+        }
+        _ => cut,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log() {
+        let mut messages = Messages::default();
+        messages.push(Severity::Info, "Wrote /tmp/a");
+        messages.push(Severity::Error, "Save failed: denied\nsecond line");
+        let text = messages.text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].len(), "00:00:00 [info] Wrote /tmp/a".len());
+        assert!(lines[0].ends_with(" [info] Wrote /tmp/a"));
+        assert!(lines[1].ends_with(" [error] Save failed: denied"));
+        assert_eq!(lines[2], "    second line");
+
+        for idx in 0..MESSAGE_LOG_MAX {
+            messages.push(Severity::Info, &idx.to_string());
+        }
+        assert_eq!(messages.iter().count(), MESSAGE_LOG_MAX);
+        assert_eq!(messages.count(), MESSAGE_LOG_MAX + 2);
+        assert_eq!(messages.iter().next().unwrap().text, "0");
+    }
+
+    #[test]
+    fn test_echo_line() {
+        assert_eq!(echo_line("short", 10), "short");
+        assert_eq!(echo_line("0123456789abc", 10), "012345678$");
+        // Cutting inside a multi-byte character or a cluster must not split it.
+        let text = "é".repeat(70);
+        assert_eq!(echo_line(&text, 64).chars().count(), 64);
+        assert_eq!(echo_line("ab\u{301}cd", 3), "ab\u{301}$");
+        assert_eq!(echo_line("日本語", 4), "日$");
+        assert_eq!(echo_line("first\nsecond", 20), "first$");
+    }
+}
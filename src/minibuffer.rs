@@ -0,0 +1,341 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+/// What the minibuffer is currently reading. The event loop looks at this when the
+/// user presses Enter to decide what to do with the input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Prompt {
+    FindFile,
+    Command,
+    Eol,
+    FinalNewline,
+    Encoding,
+    RecoverFile,
+    ConfirmSave,
+    ConfirmRevert,
+    GotoLine,
+    Search,
+    TabWidth,
+    Dired,
+    DiredRename,
+    DiredCopy,
+    DiredMkdir,
+    DiredDelete,
+    LoadTheme,
+    ModeLineFormat,
+    LineNumbers,
+}
+
+impl Prompt {
+    /// The fixed set of answers for prompts that complete from a list.
+    pub fn choices(&self) -> &'static [&'static str] {
+        match *self {
+            Prompt::FindFile
+            | Prompt::GotoLine
+            | Prompt::Search
+            | Prompt::TabWidth
+            | Prompt::Dired
+            | Prompt::DiredRename
+            | Prompt::DiredCopy
+            | Prompt::DiredMkdir
+            | Prompt::LoadTheme
+            | Prompt::ModeLineFormat => &[],
+            Prompt::Command => crate::program::COMMANDS,
+            Prompt::Eol => &["lf", "crlf", "cr"],
+            Prompt::FinalNewline => &["keep", "ensure", "strip"],
+            Prompt::LineNumbers => &["absolute", "relative", "hybrid", "off"],
+            Prompt::RecoverFile
+            | Prompt::ConfirmSave
+            | Prompt::ConfirmRevert
+            | Prompt::DiredDelete => &["yes", "no"],
+            Prompt::Encoding => &[
+                "utf-8",
+                "utf-8-raw",
+                "utf-16le",
+                "utf-16be",
+                "latin-1",
+                "windows-1252",
+            ],
+        }
+    }
+
+    /// Prompts that read a file name and complete it against the file system.
+    pub fn completes_paths(&self) -> bool {
+        matches!(
+            *self,
+            Prompt::FindFile
+                | Prompt::Dired
+                | Prompt::DiredRename
+                | Prompt::DiredCopy
+                | Prompt::DiredMkdir
+                | Prompt::LoadTheme
+        )
+    }
+}
+
+impl std::fmt::Display for Prompt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Prompt::FindFile => write!(f, "Find file: "),
+            Prompt::Command => write!(f, "M-x "),
+            Prompt::Eol => write!(f, "Line endings (lf, crlf, cr): "),
+            Prompt::FinalNewline => write!(f, "Final newline (keep, ensure, strip): "),
+            Prompt::Encoding => write!(f, "Encoding for saving: "),
+            Prompt::RecoverFile => write!(f, "Recover from the auto-save file? (yes or no) "),
+            Prompt::ConfirmSave => write!(f, "File changed on disk; save anyway? (yes or no) "),
+            Prompt::ConfirmRevert => write!(f, "Discard edits and revert? (yes or no) "),
+            Prompt::GotoLine => write!(f, "Goto line: "),
+            Prompt::Search => write!(f, "Search: "),
+            Prompt::TabWidth => write!(f, "Tab width: "),
+            Prompt::Dired => write!(f, "Dired (directory): "),
+            Prompt::DiredRename => write!(f, "Rename to: "),
+            Prompt::DiredCopy => write!(f, "Copy to: "),
+            Prompt::DiredMkdir => write!(f, "Create directory: "),
+            Prompt::DiredDelete => write!(f, "Delete marked files? (yes or no) "),
+            Prompt::LoadTheme => write!(f, "Load theme: "),
+            Prompt::ModeLineFormat => write!(f, "Mode line format: "),
+            Prompt::LineNumbers => write!(f, "Line numbers (absolute, relative, hybrid, off): "),
+        }
+    }
+}
+
+/// One line of input read in the echo area row.
+#[derive(Debug, Clone)]
+pub struct Minibuffer {
+    prompt: Prompt,
+    input: std::string::String,
+}
+
+impl Minibuffer {
+    pub fn new(prompt: Prompt, initial: &str) -> Self {
+        Minibuffer {
+            prompt,
+            input: initial.to_string(),
+        }
+    }
+
+    pub fn prompt(&self) -> Prompt {
+        self.prompt
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    pub fn push(&mut self, ch: char) {
+        self.input.push(ch)
+    }
+
+    pub fn pop(&mut self) {
+        self.input.pop();
+    }
+
+    /// Completes the input in place. Returns the candidates when the input could not be
+    /// extended any further and more than one entry still matches.
+    pub fn complete(&mut self) -> Vec<std::string::String> {
+        let completion = if self.prompt.completes_paths() {
+            complete_path(&self.input)
+        } else {
+            complete_from(&self.input, self.prompt.choices())
+        };
+        let stuck = completion.completed == self.input;
+        self.input = completion.completed;
+        if stuck && completion.candidates.len() > 1 {
+            completion.candidates
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Completion {
+    pub completed: std::string::String,
+    pub candidates: Vec<std::string::String>,
+}
+
+/// Replaces a leading "~" with $HOME. "~user" forms are left alone.
+pub fn expand_tilde(input: &str) -> PathBuf {
+    let home = match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home),
+        None => return PathBuf::from(input),
+    };
+    if input == "~" {
+        home
+    } else if let Some(rest) = input.strip_prefix("~/") {
+        home.join(rest)
+    } else {
+        PathBuf::from(input)
+    }
+}
+
+/// Inverse of expand_tilde, used for the initial find-file input.
+pub fn abbreviate_home(path: &Path) -> std::string::String {
+    if let Some(home) = std::env::var_os("HOME") {
+        if let Ok(rest) = path.strip_prefix(&home) {
+            return Path::new("~").join(rest).to_string_lossy().to_string();
+        }
+    }
+    path.to_string_lossy().to_string()
+}
+
+/// Completes the last component of a path typed into the minibuffer against the
+/// entries of its directory. The input is extended to the longest common prefix of all
+/// matches, and a lone directory match gets a trailing "/". Dotfiles are only offered
+/// when the typed prefix starts with ".".
+pub fn complete_path(input: &str) -> Completion {
+    let (dir_part, prefix) = match input.rfind('/') {
+        Some(pos) => input.split_at(pos + 1),
+        None => ("", input),
+    };
+    let dir = if dir_part.is_empty() {
+        PathBuf::from(".")
+    } else {
+        expand_tilde(dir_part)
+    };
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => {
+            return Completion {
+                completed: input.to_string(),
+                candidates: Vec::new(),
+            }
+        }
+    };
+
+    let mut candidates: Vec<std::string::String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            // file_type does not follow symlinks, metadata does.
+            let is_dir = std::fs::metadata(entry.path()).is_ok_and(|meta| meta.is_dir());
+            Some(if is_dir { format!("{}/", name) } else { name })
+        })
+        .collect();
+    candidates.sort();
+
+    if candidates.is_empty() {
+        return Completion {
+            completed: input.to_string(),
+            candidates,
+        };
+    }
+    Completion {
+        completed: format!("{}{}", dir_part, common_prefix(&candidates)),
+        candidates,
+    }
+}
+
+/// Completes input against a fixed list of names, such as command names.
+pub fn complete_from(input: &str, choices: &[&str]) -> Completion {
+    let candidates: Vec<std::string::String> = choices
+        .iter()
+        .filter(|choice| choice.starts_with(input))
+        .map(|choice| choice.to_string())
+        .collect();
+    let completed = if candidates.is_empty() {
+        input.to_string()
+    } else {
+        common_prefix(&candidates).to_string()
+    };
+    Completion {
+        completed,
+        candidates,
+    }
+}
+
+fn common_prefix(candidates: &[std::string::String]) -> &str {
+    let mut common = candidates.first().map_or("", |first| first.as_str());
+    for candidate in candidates.iter().skip(1) {
+        let len = common
+            .char_indices()
+            .zip(candidate.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((idx, a), _)| idx + a.len_utf8());
+        common = &common[..len];
+    }
+    common
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn test_complete_path() {
+        let dir = scratch_dir("complete");
+        std::fs::create_dir(dir.join("src")).unwrap();
+        std::fs::write(dir.join("screen.rs"), "").unwrap();
+        std::fs::write(dir.join("script.sh"), "").unwrap();
+        std::fs::write(dir.join(".hidden"), "").unwrap();
+        let base = format!("{}/", dir.display());
+
+        let unique = complete_path(&format!("{}sr", base));
+        assert_eq!(unique.completed, format!("{}src/", base));
+
+        let common = complete_path(&format!("{}sc", base));
+        assert_eq!(common.completed, format!("{}scr", base));
+        assert_eq!(common.candidates, vec!["screen.rs", "script.sh"]);
+
+        let all = complete_path(&base);
+        assert_eq!(all.candidates, vec!["screen.rs", "script.sh", "src/"]);
+
+        let hidden = complete_path(&format!("{}.h", base));
+        assert_eq!(hidden.completed, format!("{}.hidden", base));
+
+        let missing = complete_path(&format!("{}nope", base));
+        assert_eq!(missing.completed, format!("{}nope", base));
+        assert!(missing.candidates.is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_minibuffer_complete() {
+        let dir = scratch_dir("minibuffer");
+        std::fs::write(dir.join("alpha.txt"), "").unwrap();
+        std::fs::write(dir.join("alpine.txt"), "").unwrap();
+        let mut mb = Minibuffer::new(Prompt::FindFile, &format!("{}/a", dir.display()));
+
+        assert!(mb.complete().is_empty());
+        assert_eq!(mb.input(), format!("{}/alp", dir.display()));
+        assert_eq!(mb.complete(), vec!["alpha.txt", "alpine.txt"]);
+        mb.push('h');
+        mb.complete();
+        assert_eq!(mb.input(), format!("{}/alpha.txt", dir.display()));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_complete_from() {
+        let choices = ["set-buffer-eol", "set-final-newline", "save-buffer"];
+        let completion = complete_from("se", &choices);
+        assert_eq!(completion.completed, "set-");
+        assert_eq!(completion.candidates.len(), 2);
+        assert_eq!(complete_from("sa", &choices).completed, "save-buffer");
+        assert_eq!(complete_from("x", &choices).completed, "x");
+
+        let mut mb = Minibuffer::new(Prompt::Eol, "c");
+        assert!(mb.complete().is_empty());
+        assert_eq!(mb.input(), "cr");
+        assert_eq!(mb.complete(), vec!["crlf", "cr"]);
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let home = PathBuf::from(std::env::var_os("HOME").unwrap());
+        assert_eq!(expand_tilde("~"), home);
+        assert_eq!(expand_tilde("~/notes.txt"), home.join("notes.txt"));
+        assert_eq!(expand_tilde("~other/x"), PathBuf::from("~other/x"));
+        assert_eq!(expand_tilde("/tmp/~"), PathBuf::from("/tmp/~"));
+        assert_eq!(abbreviate_home(&home.join("a")), "~/a");
+    }
+}
@@ -0,0 +1,173 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+/// What the mode line shows unless told otherwise: flags, buffer name, major mode,
+/// line and column, how far through the buffer point is, encoding, line endings and
+/// the git branch.
+pub const DEFAULT_FORMAT: &str = "%* %b  (%m)  L%l C%c  %p  %z %E  %g";
+
+/// Everything about a buffer the mode line can show.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Status {
+    pub buffer_name: std::string::String,
+    pub modified: bool,
+    pub read_only: bool,
+    pub mode: std::string::String,
+    /// 1-based.
+    pub line: usize,
+    /// 1-based.
+    pub col: usize,
+    pub point: usize,
+    pub len: usize,
+    pub encoding: std::string::String,
+    pub eol: std::string::String,
+    pub branch: Option<std::string::String>,
+}
+
+impl Status {
+    /// The read-only and modified flags as Emacs shows them: "--" for a writable
+    /// buffer, "**" once it is modified, "%%" for a read-only one and "%*" for a
+    /// read-only buffer that was modified anyway.
+    pub fn flags(&self) -> &'static str {
+        match (self.read_only, self.modified) {
+            (false, false) => "--",
+            (false, true) => "**",
+            (true, false) => "%%",
+            (true, true) => "%*",
+        }
+    }
+
+    /// "Top" and "Bot" at either end of the buffer, "All" when it is empty, else how far
+    /// through it point is.
+    pub fn percent(&self) -> std::string::String {
+        match (self.point, self.len) {
+            (_, 0) => "All".to_string(),
+            (0, _) => "Top".to_string(),
+            (point, len) if point >= len => "Bot".to_string(),
+            (point, len) => format!("{}%", point * 100 / len),
+        }
+    }
+}
+
+/// Expands the placeholders of format with what status holds. Unknown placeholders
+/// are kept as written, and %g is empty outside a git repository.
+pub fn format(format: &str, status: &Status) -> std::string::String {
+    let mut text = std::string::String::new();
+    let mut chars = format.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            text.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('b') => text.push_str(&status.buffer_name),
+            Some('*') => text.push_str(status.flags()),
+            Some('m') => text.push_str(&status.mode),
+            Some('l') => text.push_str(&status.line.to_string()),
+            Some('c') => text.push_str(&status.col.to_string()),
+            Some('p') => text.push_str(&status.percent()),
+            Some('z') => text.push_str(&status.encoding),
+            Some('E') => text.push_str(&status.eol),
+            Some('g') => text.push_str(status.branch.as_deref().unwrap_or_default()),
+            Some('%') => text.push('%'),
+            Some(other) => {
+                text.push('%');
+                text.push(other);
+            }
+            None => text.push('%'),
+        }
+    }
+    text
+}
+
+/// The branch checked out in the git repository dir is in, or the start of the
+/// commit hash when HEAD is detached. Reads .git/HEAD directly; a .git file, as in
+/// worktrees and submodules, points to the real git directory.
+pub fn git_branch(dir: &Path) -> Option<std::string::String> {
+    let dot_git = dir
+        .ancestors()
+        .map(|dir| dir.join(".git"))
+        .find(|dot_git| dot_git.exists())?;
+    let git_dir = match std::fs::read_to_string(&dot_git) {
+        Ok(text) => {
+            let target = text.trim().strip_prefix("gitdir:")?.trim();
+            dot_git.parent()?.join(target)
+        }
+        Err(_) => dot_git,
+    };
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref:") {
+        Some(reference) => {
+            let reference = reference.trim();
+            Some(
+                reference
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(reference)
+                    .to_string(),
+            )
+        }
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn test_format() {
+        let mut status = Status {
+            buffer_name: "main.rs".to_string(),
+            modified: true,
+            mode: "Rust".to_string(),
+            line: 12,
+            col: 5,
+            point: 250,
+            len: 1000,
+            encoding: "utf-8".to_string(),
+            eol: "LF".to_string(),
+            branch: Some("main".to_string()),
+            ..Status::default()
+        };
+        assert_eq!(
+            format(DEFAULT_FORMAT, &status),
+            "** main.rs  (Rust)  L12 C5  25%  utf-8 LF  main"
+        );
+        status.read_only = true;
+        status.modified = false;
+        status.point = 1000;
+        status.branch = None;
+        assert_eq!(format("%* %p %g|100%% %q%", &status), "%% Bot |100% %q%");
+        status.modified = true;
+        assert_eq!(format("%*", &status), "%*");
+        status.read_only = false;
+        status.modified = false;
+        assert_eq!(format("%*", &status), "--");
+    }
+
+    #[test]
+    fn test_git_branch() {
+        let dir = scratch_dir("git");
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::create_dir_all(dir.join("src/deep")).unwrap();
+        std::fs::write(dir.join(".git/HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+        assert_eq!(
+            git_branch(&dir.join("src/deep")),
+            Some("feature/x".to_string())
+        );
+        std::fs::write(dir.join(".git/HEAD"), "0123456789abcdef\n").unwrap();
+        assert_eq!(git_branch(&dir), Some("0123456".to_string()));
+
+        // A worktree's .git file names the real git directory.
+        let worktree = dir.join("src/deep");
+        std::fs::create_dir_all(dir.join("wt")).unwrap();
+        std::fs::write(dir.join("wt/HEAD"), "ref: refs/heads/other\n").unwrap();
+        std::fs::write(worktree.join(".git"), "gitdir: ../../wt\n").unwrap();
+        assert_eq!(git_branch(&worktree), Some("other".to_string()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
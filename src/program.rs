@@ -4,32 +4,213 @@
 extern crate libc;
 
 use std::io::{Error, ErrorKind, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 use crate::{
-    document::{Doc, Document},
+    autosave::{self, AutoSave},
+    backup::BackupConfig,
+    cli::{Cli, FileArg, Source},
+    document::{self, Doc, Document, Eol, FinalNewline},
+    encoding::Encoding,
     event::{Key, ReadKey},
     ffi,
-    screen::{Builder, DrawScreen, EscSeq, Screen},
+    messages::MESSAGES_BUFFER,
+    minibuffer::{self, Minibuffer, Prompt},
+    modeline,
+    screen::{Builder, DrawScreen, EscSeq, LineNumbers, Screen},
+    visual::LineWrap,
 };
 
-pub struct MrText<'a, U: Doc>
-where
-    U: Doc,
-{
+/// Names accepted by M-x, in the order they are offered for completion.
+pub const COMMANDS: &[&str] = &[
+    "auto-revert-mode",
+    "delete-trailing-whitespace",
+    "diff-signs-mode",
+    "dired",
+    "edit-whole-file",
+    "find-file",
+    "goto-line",
+    "hexl-mode",
+    "indent-tabs-mode",
+    "load-theme",
+    "next-bookmark",
+    "recover-file",
+    "revert-buffer",
+    "save-buffer",
+    "search-forward",
+    "set-buffer-encoding",
+    "set-buffer-eol",
+    "set-final-newline",
+    "set-line-numbers",
+    "set-mode-line-format",
+    "set-tab-width",
+    "toggle-bookmark",
+    "toggle-read-only",
+    "toggle-truncate-lines",
+    "view-echo-area-messages",
+    "whitespace-cleanup-mode",
+    "whitespace-mode",
+];
+
+/// How often buffers in auto-revert mode look at their files while no key is pressed.
+const AUTO_REVERT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long typing has to pause before diff signs catch up with the edits.
+const DIFF_SIGNS_DELAY: Duration = Duration::from_millis(300);
+
+/// How often the git branches in the mode line are read again while no key is pressed.
+const GIT_BRANCH_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct MrText<'a> {
     screen: Screen<'a>,
-    docs: Vec<U>,
+    docs: Vec<Document>,
+    current: usize,
+    prefix: Option<Key>,
+    minibuffer: Option<Minibuffer>,
+    auto_save: AutoSave,
+    backup: BackupConfig,
+    /// False while the terminal reports it lost focus.
+    focused: bool,
+    /// The buffer q goes back to from *Messages*.
+    previous: usize,
+    /// Messages::count when the *Messages* buffer was last filled.
+    messages_shown: usize,
+    /// Whether files are opened with whitespace-cleanup-mode on.
+    whitespace_cleanup: bool,
+    /// When the git branches of the buffers were last read.
+    branches_read: Instant,
 }
 
-impl<'a, U> MrText<'_, U>
-where
-    U: Doc,
-{
-    pub fn open_doc(&self, file_name: &str) {
-        let mut mr_text = MrText::new();
-        if let Ok(doc) = Document::open_doc(file_name) {
-            mr_text.docs.push(doc);
-        } else {
-            mr_text.screen.echo_area_msg("Failed to open file.");
+/// Reads piped input into a *stdin* buffer when "-" is on the command line. This runs
+/// before MrText::new puts the terminal in raw mode, so all of the input is in before
+/// the tty is opened for keys. None when there is no "-" or stdin is a terminal.
+pub fn read_stdin(cli: &Cli) -> Option<std::io::Result<Document>> {
+    let wanted = cli
+        .files
+        .iter()
+        .any(|file| matches!(file.source, Source::Stdin));
+    if !wanted || ffi::is_tty(&std::io::stdin()) {
+        return None;
+    }
+    Some(Document::from_reader("*stdin*", std::io::stdin()))
+}
+
+/// Applies --readonly and +LINE:COL of each argument to the buffer it opened, as
+/// returned by MrText::open_file_arg. Arguments that opened nothing are skipped, so
+/// their settings never land on a buffer opened before them.
+fn apply_file_args(docs: &mut [Document], opened: &[(Option<usize>, &FileArg)], readonly: bool) {
+    for (idx, file) in opened {
+        let Some(doc) = idx.and_then(|idx| docs.get_mut(idx)) else {
+            continue;
+        };
+        if readonly {
+            doc.set_read_only(true);
+        }
+        if let Some(line) = file.line {
+            doc.goto_line_col(line, file.col.unwrap_or(1));
+        }
+    }
+}
+
+impl Default for MrText<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MrText<'_> {
+    /// Visits file_name after expanding "~". Returns the buffer shown, None when the
+    /// file couldn't be opened.
+    pub fn open_doc(&mut self, file_name: &str) -> Option<usize> {
+        self.find_file(&minibuffer::expand_tilde(file_name))
+    }
+
+    /// Opens every file named on the command line and shows the first one. stdin is
+    /// what read_stdin returned for "-", read before the terminal was set up.
+    pub fn open_args(&mut self, cli: &Cli, mut stdin: Option<std::io::Result<Document>>) {
+        let first = self.docs.len();
+        let opened: Vec<(Option<usize>, &FileArg)> = cli
+            .files
+            .iter()
+            .map(|file| (self.open_file_arg(file, &mut stdin), file))
+            .collect();
+        apply_file_args(&mut self.docs, &opened, cli.readonly);
+        if first < self.docs.len() {
+            self.current = first;
+        }
+    }
+
+    /// Opens one command line argument. Returns the buffer it opened or switched to,
+    /// None when nothing was opened.
+    fn open_file_arg(
+        &mut self,
+        file: &FileArg,
+        stdin: &mut Option<std::io::Result<Document>>,
+    ) -> Option<usize> {
+        match &file.source {
+            Source::Path(path) => self.open_doc(path),
+            Source::Stdin if ffi::is_tty(&std::io::stdin()) => {
+                self.screen
+                    .echo_area_msg("Stdin is a terminal, nothing to read.");
+                None
+            }
+            // Only the first "-" gets the input; there is nothing left for the others.
+            Source::Stdin => match stdin.take()? {
+                Ok(doc) => {
+                    self.docs.push(doc);
+                    self.current = self.docs.len() - 1;
+                    Some(self.current)
+                }
+                Err(err) => {
+                    self.screen
+                        .echo_area_error(format!("Failed to read stdin: {}", err));
+                    None
+                }
+            },
+        }
+    }
+
+    /// Switches to the buffer visiting path, creating it first if no buffer does.
+    /// Returns that buffer, None when the file couldn't be opened.
+    pub fn find_file(&mut self, path: &Path) -> Option<usize> {
+        if let Some(idx) = self.docs.iter().position(|doc| doc.visits(path)) {
+            self.current = idx;
+            return Some(idx);
+        }
+        match Document::find_file(path) {
+            Ok(mut doc) => {
+                doc.set_backup_config(self.backup.clone());
+                doc.set_whitespace_cleanup(self.whitespace_cleanup);
+                doc.refresh_git_branch();
+                if !path.exists() {
+                    self.screen.echo_area_msg("(New file)");
+                } else if doc.looks_binary() {
+                    self.screen.echo_area_msg(format!(
+                        "{} looks binary; M-x hexl-mode to view it in hex",
+                        doc.buffer_name()
+                    ));
+                } else if doc.is_large() {
+                    self.screen.echo_area_msg(format!(
+                        "{} is large; showing it read-only. M-x edit-whole-file to edit",
+                        doc.buffer_name()
+                    ));
+                } else if autosave::has_newer_auto_save(path) {
+                    self.screen.echo_area_msg(format!(
+                        "{} has auto save data; consider M-x recover-file",
+                        doc.buffer_name()
+                    ));
+                }
+                self.docs.push(doc);
+                self.current = self.docs.len() - 1;
+                document::uniquify_buffer_names(&mut self.docs);
+                Some(self.current)
+            }
+            Err(err) => {
+                self.screen
+                    .echo_area_error(format!("Failed to open file: {}", err));
+                None
+            }
         }
     }
 
@@ -42,7 +223,13 @@ where
             .backup_terminal()
             .build();
 
-        Screen::raw_mode();
+        screen.raw_mode();
+        screen.focus_reporting(true);
+        if let Some(path) = std::env::var_os("MR_TEXT_THEME").filter(|path| !path.is_empty()) {
+            if let Err(err) = screen.load_theme(Path::new(&path)) {
+                screen.echo_area_error(format!("Failed to load theme: {}", err));
+            }
+        }
         screen.clear_screen();
         screen.draw_numbered_lm();
         screen.draw_ml_area();
@@ -50,40 +237,795 @@ where
         MrText {
             screen,
             docs: Vec::with_capacity(20),
+            current: 0,
+            prefix: None,
+            minibuffer: None,
+            auto_save: AutoSave::default(),
+            backup: BackupConfig::from_env(),
+            focused: true,
+            previous: 0,
+            messages_shown: 0,
+            whitespace_cleanup: std::env::var_os("MR_TEXT_WHITESPACE_CLEANUP").is_some(),
+            branches_read: Instant::now(),
+        }
+    }
+
+    /// The directory find-file starts completing from: the current buffer's directory,
+    /// or the working directory when there is no buffer yet.
+    fn default_directory(&self) -> std::string::String {
+        let dir = self
+            .docs
+            .get(self.current)
+            .and_then(|doc| match doc.dired() {
+                Some(dired) => Some(dired.dir().to_path_buf()),
+                None => doc.path()?.parent().map(Path::to_path_buf),
+            })
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .or_else(|| std::env::current_dir().ok());
+        match dir {
+            Some(dir) => format!(
+                "{}/",
+                minibuffer::abbreviate_home(&dir).trim_end_matches('/')
+            ),
+            None => std::string::String::new(),
+        }
+    }
+
+    fn minibuffer_key(&mut self, key: Key) {
+        let Some(minibuffer) = self.minibuffer.as_mut() else {
+            return;
+        };
+        match key {
+            Key::CtrlKey('g') | Key::Escape => {
+                self.minibuffer = None;
+                self.screen.echo_area_msg("Quit");
+            }
+            Key::Letter(ch) => minibuffer.push(ch),
+            Key::Backspace | Key::Delete => minibuffer.pop(),
+            Key::Tab(_) => {
+                let candidates = minibuffer.complete();
+                if !candidates.is_empty() {
+                    self.screen
+                        .echo_area_msg(format!("{{{}}}", candidates.join(" ")));
+                }
+            }
+            Key::Enter(_) => {
+                if let Some(minibuffer) = self.minibuffer.take() {
+                    self.submit(minibuffer.prompt(), minibuffer.input());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn prompt(&mut self, prompt: Prompt, initial: &str) {
+        self.minibuffer = Some(Minibuffer::new(prompt, initial));
+    }
+
+    /// Acts on input read by the minibuffer.
+    fn submit(&mut self, prompt: Prompt, input: &str) {
+        match prompt {
+            Prompt::FindFile => {
+                self.find_file(&minibuffer::expand_tilde(input));
+            }
+            Prompt::Command => self.execute_command(input),
+            Prompt::Eol => match (input.parse::<Eol>(), self.docs.get_mut(self.current)) {
+                (Ok(eol), Some(doc)) => {
+                    doc.convert_eol(eol);
+                    self.screen
+                        .echo_area_msg(format!("Line endings set to {}", eol));
+                }
+                (Err(err), _) => self.screen.echo_area_error(err),
+                _ => {}
+            },
+            Prompt::Encoding => {
+                match (input.parse::<Encoding>(), self.docs.get_mut(self.current)) {
+                    (Ok(encoding), Some(doc)) => doc.set_encoding(encoding),
+                    (Err(err), _) => self.screen.echo_area_error(err),
+                    _ => {}
+                }
+            }
+            Prompt::RecoverFile => match self.docs.get_mut(self.current) {
+                Some(doc) if input == "yes" => match doc.recover_auto_save() {
+                    Ok(()) => self
+                        .screen
+                        .echo_area_msg("Recovered; save the buffer to keep it"),
+                    Err(err) => self
+                        .screen
+                        .echo_area_error(format!("Recover failed: {}", err)),
+                },
+                _ => self.screen.echo_area_msg("Recover cancelled"),
+            },
+            Prompt::ConfirmSave if input == "yes" => self.write_buffer(),
+            Prompt::ConfirmSave => self.screen.echo_area_msg("Save cancelled"),
+            Prompt::GotoLine => match (
+                input.trim().parse::<usize>(),
+                self.docs.get_mut(self.current),
+            ) {
+                (Ok(line), Some(doc)) => doc.goto_line_col(line, 1),
+                (Err(_), _) => self
+                    .screen
+                    .echo_area_msg(format!("Not a line number: {}", input)),
+                _ => {}
+            },
+            Prompt::TabWidth => match (
+                input.trim().parse::<usize>(),
+                self.docs.get_mut(self.current),
+            ) {
+                (Ok(tab_width @ 1..), Some(doc)) => {
+                    doc.set_tab_width(tab_width);
+                    self.screen
+                        .echo_area_msg(format!("Tab width set to {}", tab_width));
+                }
+                (Ok(_) | Err(_), _) => self
+                    .screen
+                    .echo_area_msg(format!("Not a tab width: {}", input)),
+            },
+            Prompt::Search => {
+                if let Some(doc) = self.docs.get_mut(self.current) {
+                    if !input.is_empty() && !doc.search_forward(input) {
+                        self.screen
+                            .echo_area_error(format!("Search failed: {}", input));
+                    }
+                }
+            }
+            Prompt::Dired => {
+                self.find_file(&minibuffer::expand_tilde(input));
+            }
+            Prompt::ModeLineFormat => match input {
+                "" => self.screen.set_mode_line_format(modeline::DEFAULT_FORMAT),
+                format => self.screen.set_mode_line_format(format),
+            },
+            Prompt::LoadTheme => {
+                let path = minibuffer::expand_tilde(input);
+                match self.screen.load_theme(&path) {
+                    Ok(()) => self
+                        .screen
+                        .echo_area_msg(format!("Loaded theme {}", path.display())),
+                    Err(err) => self
+                        .screen
+                        .echo_area_error(format!("Failed to load theme: {}", err)),
+                }
+            }
+            Prompt::DiredRename | Prompt::DiredCopy | Prompt::DiredMkdir | Prompt::DiredDelete => {
+                self.dired_submit(prompt, input)
+            }
+            Prompt::ConfirmRevert if input == "yes" => self.revert_buffer(),
+            Prompt::ConfirmRevert => self.screen.echo_area_msg("Revert cancelled"),
+            Prompt::LineNumbers => match input.parse::<LineNumbers>() {
+                Ok(mode) => {
+                    self.screen.set_line_numbers(mode);
+                    self.screen
+                        .echo_area_msg(format!("Line numbers set to {}", mode));
+                }
+                Err(err) => self.screen.echo_area_error(err),
+            },
+            Prompt::FinalNewline => {
+                match (
+                    input.parse::<FinalNewline>(),
+                    self.docs.get_mut(self.current),
+                ) {
+                    (Ok(final_newline), Some(doc)) => doc.set_final_newline(final_newline),
+                    (Err(err), _) => self.screen.echo_area_error(err),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Runs a command by its M-x name.
+    fn execute_command(&mut self, name: &str) {
+        match name {
+            "find-file" => self.prompt(Prompt::FindFile, &self.default_directory()),
+            "recover-file" => match self.docs.get(self.current) {
+                Some(doc) if doc.auto_save_path().is_some_and(|path| path.exists()) => {
+                    self.prompt(Prompt::RecoverFile, "")
+                }
+                Some(doc) => self
+                    .screen
+                    .echo_area_msg(format!("No auto-save file for {}", doc.buffer_name())),
+                None => {}
+            },
+            "revert-buffer" => match self.docs.get(self.current) {
+                Some(doc) if doc.is_modified() => self.prompt(Prompt::ConfirmRevert, ""),
+                Some(_) => self.revert_buffer(),
+                None => {}
+            },
+            "auto-revert-mode" => {
+                if let Some(doc) = self.docs.get_mut(self.current) {
+                    let on = !doc.auto_revert();
+                    doc.set_auto_revert(on);
+                    self.screen.echo_area_msg(format!(
+                        "Auto-Revert mode {}",
+                        if on { "enabled" } else { "disabled" }
+                    ));
+                }
+            }
+            "diff-signs-mode" => {
+                if let Some(doc) = self.docs.get_mut(self.current) {
+                    if !doc.has_file() {
+                        return self.screen.echo_area_msg("Buffer is not visiting a file");
+                    }
+                    let on = !doc.diff_signs();
+                    doc.set_diff_signs(on);
+                    self.screen.echo_area_msg(format!(
+                        "Diff-Signs mode {}",
+                        if on { "enabled" } else { "disabled" }
+                    ));
+                }
+            }
+            "delete-trailing-whitespace" => {
+                if let Some(doc) = self.docs.get_mut(self.current) {
+                    if doc.is_read_only() {
+                        return self.screen.echo_area_msg("Buffer is read-only");
+                    }
+                    let count = doc.delete_trailing_whitespace();
+                    self.screen
+                        .echo_area_msg(format!("Deleted trailing whitespace on {} line(s)", count));
+                }
+            }
+            "dired" => self.prompt(Prompt::Dired, &self.default_directory()),
+            "indent-tabs-mode" => {
+                if let Some(doc) = self.docs.get_mut(self.current) {
+                    let on = !doc.indent_tabs();
+                    doc.set_indent_tabs(on);
+                    self.screen.echo_area_msg(format!(
+                        "Indent Tabs mode {}",
+                        if on { "enabled" } else { "disabled" }
+                    ));
+                }
+            }
+            "edit-whole-file" => {
+                if let Some(doc) = self.docs.get_mut(self.current) {
+                    match doc.edit_whole_file() {
+                        Ok(()) => self
+                            .screen
+                            .echo_area_msg("Loaded the whole file for editing"),
+                        Err(err) => self
+                            .screen
+                            .echo_area_error(format!("Loading failed: {}", err)),
+                    }
+                }
+            }
+            "goto-line" => self.prompt(Prompt::GotoLine, ""),
+            "load-theme" => self.prompt(Prompt::LoadTheme, &self.default_directory()),
+            "hexl-mode" => {
+                if let Some(doc) = self.docs.get_mut(self.current) {
+                    if doc.is_hexl() {
+                        doc.hexl_exit();
+                        self.screen.echo_area_msg("Hexl mode disabled");
+                    } else if let Err(err) = doc.hexl_mode() {
+                        self.screen
+                            .echo_area_error(format!("Hexl mode failed: {}", err));
+                    }
+                }
+            }
+            "search-forward" => self.prompt(Prompt::Search, ""),
+            "save-buffer" => self.save_buffer(),
+            "toggle-bookmark" => {
+                if let Some(doc) = self.docs.get_mut(self.current) {
+                    let msg = match doc.toggle_bookmark() {
+                        true => "Bookmark set",
+                        false => "Bookmark removed",
+                    };
+                    self.screen.echo_area_msg(msg);
+                }
+            }
+            "next-bookmark" => {
+                if let Some(doc) = self.docs.get_mut(self.current) {
+                    if !doc.next_bookmark() {
+                        self.screen.echo_area_msg("No bookmarks in this buffer");
+                    }
+                }
+            }
+            "toggle-read-only" => self.toggle_read_only(),
+            "whitespace-mode" => {
+                if let Some(doc) = self.docs.get_mut(self.current) {
+                    let on = !doc.whitespace_mode();
+                    doc.set_whitespace_mode(on);
+                    self.screen.echo_area_msg(format!(
+                        "Whitespace mode {}",
+                        if on { "enabled" } else { "disabled" }
+                    ));
+                }
+            }
+            "whitespace-cleanup-mode" => {
+                if let Some(doc) = self.docs.get_mut(self.current) {
+                    let on = !doc.whitespace_cleanup();
+                    doc.set_whitespace_cleanup(on);
+                    self.screen.echo_area_msg(format!(
+                        "Whitespace-Cleanup mode {}",
+                        if on { "enabled" } else { "disabled" }
+                    ));
+                }
+            }
+            "view-echo-area-messages" => {
+                if !self.in_messages_buffer() {
+                    self.previous = self.current;
+                }
+                self.show_messages();
+            }
+            "toggle-truncate-lines" => {
+                if let Some(doc) = self.docs.get_mut(self.current) {
+                    let (line_wrap, msg) = match doc.line_wrap() {
+                        LineWrap::Soft => (LineWrap::Truncate, "Truncate long lines enabled"),
+                        LineWrap::Truncate => (LineWrap::Soft, "Truncate long lines disabled"),
+                    };
+                    doc.set_line_wrap(line_wrap);
+                    self.screen.echo_area_msg(msg);
+                }
+            }
+            "set-buffer-encoding" => self.prompt(Prompt::Encoding, ""),
+            "set-buffer-eol" => self.prompt(Prompt::Eol, ""),
+            "set-final-newline" => self.prompt(Prompt::FinalNewline, ""),
+            "set-line-numbers" => self.prompt(Prompt::LineNumbers, ""),
+            "set-mode-line-format" => {
+                let format = self.screen.mode_line_format().to_string();
+                self.prompt(Prompt::ModeLineFormat, &format);
+            }
+            "set-tab-width" => self.prompt(Prompt::TabWidth, ""),
+            _ => self
+                .screen
+                .echo_area_msg(format!("No such command: {}", name)),
+        }
+    }
+
+    fn command_key(&mut self, key: Key) {
+        match (self.prefix.take(), key) {
+            (None, Key::CtrlKey('x') | Key::AltKey('g')) => self.prefix = Some(key),
+            (None, Key::AltKey('x')) => self.prompt(Prompt::Command, ""),
+            (None, Key::CtrlKey('s')) => self.execute_command("search-forward"),
+            (Some(Key::CtrlKey('x')), Key::CtrlKey('f')) => self.execute_command("find-file"),
+            (Some(Key::CtrlKey('x')), Key::CtrlKey('s')) => self.save_buffer(),
+            (Some(Key::CtrlKey('x')), Key::CtrlKey('q')) => self.toggle_read_only(),
+            (Some(Key::CtrlKey('x')), Key::Letter('d')) => self.execute_command("dired"),
+            (Some(Key::AltKey('g')), Key::Letter('g') | Key::AltKey('g')) => {
+                self.execute_command("goto-line")
+            }
+            (Some(Key::CtrlKey('x') | Key::AltKey('g')), _) => {
+                self.screen.echo_area_msg("Key unimplemented")
+            }
+            (_, Key::Letter('q')) if self.in_messages_buffer() => {
+                self.current = self.previous.min(self.docs.len() - 1);
+            }
+            (_, key) if self.dired_key(key) => {}
+            (_, key) => {
+                let Some(doc) = self.docs.get_mut(self.current) else {
+                    return;
+                };
+                let edits = matches!(
+                    key,
+                    Key::Letter(_) | Key::Enter(_) | Key::Tab(_) | Key::Backspace | Key::Delete
+                );
+                if edits && doc.is_read_only() {
+                    self.screen.echo_area_msg("Buffer is read-only");
+                    return;
+                }
+                if edits && doc.is_hexl() {
+                    if !matches!(key, Key::Letter(ch) if doc.hexl_input(ch)) {
+                        self.screen
+                            .echo_area_msg("Type hex digits to change the byte at point");
+                    }
+                    return;
+                }
+                match key {
+                    Key::Letter(ch) => doc.insert(ch.encode_utf8(&mut [0; 4])),
+                    Key::Enter(_) => doc.newline(),
+                    Key::Tab(_) => doc.insert_tab(),
+                    Key::Backspace | Key::Delete => doc.delete_backward_char(),
+                    Key::CtrlKey('f') | Key::Right => doc.forward_char(),
+                    Key::CtrlKey('b') | Key::Left => doc.backward_char(),
+                    Key::CtrlKey('n') | Key::Down => doc.move_lines(1),
+                    Key::CtrlKey('p') | Key::Up => doc.move_lines(-1),
+                    Key::CtrlKey('a') | Key::Home => doc.beginning_of_line(),
+                    Key::CtrlKey('e') | Key::End => doc.end_of_line(),
+                    Key::AltKey('m') => doc.back_to_indentation(),
+                    Key::AltKey('f') => doc.forward_word(),
+                    Key::AltKey('b') => doc.backward_word(),
+                    Key::AltKey('e') => doc.forward_sentence(),
+                    Key::AltKey('a') => doc.backward_sentence(),
+                    Key::AltKey('}') => doc.forward_paragraph(),
+                    Key::AltKey('{') => doc.backward_paragraph(),
+                    Key::AltKey('>') => doc.end_of_buffer(),
+                    Key::AltKey('<') => doc.beginning_of_buffer(),
+                    Key::CtrlAltKey('f') => {
+                        if let Err(err) = doc.forward_sexp() {
+                            self.screen.echo_area_error(err.to_string());
+                        }
+                    }
+                    Key::CtrlAltKey('b') => {
+                        if let Err(err) = doc.backward_sexp() {
+                            self.screen.echo_area_error(err.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// True when the current buffer is the *Messages* log.
+    fn in_messages_buffer(&self) -> bool {
+        self.docs
+            .get(self.current)
+            .is_some_and(|doc| doc.path().is_none() && doc.buffer_name() == MESSAGES_BUFFER)
+    }
+
+    /// Switches to a read-only *Messages* buffer holding the message log, with point
+    /// on the latest message. The buffer is refilled rather than duplicated.
+    fn show_messages(&mut self) {
+        let text = self.screen.messages().text();
+        let mut doc = match Document::from_reader(MESSAGES_BUFFER, text.as_bytes()) {
+            Ok(doc) => doc,
+            Err(err) => return self.screen.echo_area_error(err.to_string()),
+        };
+        doc.set_read_only(true);
+        doc.end_of_buffer();
+        doc.move_lines(-1);
+        self.messages_shown = self.screen.messages().count();
+        match self
+            .docs
+            .iter()
+            .position(|doc| doc.path().is_none() && doc.buffer_name() == MESSAGES_BUFFER)
+        {
+            Some(idx) => {
+                self.docs[idx] = doc;
+                self.current = idx;
+            }
+            None => {
+                self.docs.push(doc);
+                self.current = self.docs.len() - 1;
+            }
+        }
+    }
+
+    /// Saves the current buffer, asking first when the file was changed on disk since
+    /// it was read so the other change isn't clobbered silently.
+    fn save_buffer(&mut self) {
+        match self.docs.get(self.current) {
+            Some(doc) if doc.changed_on_disk() => self.prompt(Prompt::ConfirmSave, ""),
+            Some(_) => self.write_buffer(),
+            None => {}
+        }
+    }
+
+    fn write_buffer(&mut self) {
+        let Some(doc) = self.docs.get_mut(self.current) else {
+            return;
+        };
+        match doc.save_file() {
+            Ok(()) => {
+                if let Some(path) = doc.path() {
+                    self.screen
+                        .echo_area_msg(format!("Wrote {}", path.display()));
+                }
+            }
+            Err(err) => self.screen.echo_area_error(format!("Save failed: {}", err)),
+        }
+    }
+
+    /// Handles the single-letter commands of a dired buffer. Returns false for keys
+    /// that aren't dired commands, and in any other buffer.
+    fn dired_key(&mut self, key: Key) -> bool {
+        let Some(doc) = self.docs.get_mut(self.current) else {
+            return false;
+        };
+        let Some(dired) = doc.dired() else {
+            return false;
+        };
+        let line = doc.point_line();
+        let entry = dired.entry_at_line(line).cloned();
+        let target = entry
+            .as_ref()
+            .map(|entry| dired.path_of(entry).to_string_lossy().to_string())
+            .unwrap_or_default();
+        match key {
+            Key::Enter(_) | Key::Letter('f') | Key::Letter('e') => {
+                if entry.is_some() {
+                    self.find_file(Path::new(&target));
+                }
+            }
+            Key::Letter('n') => doc.move_lines(1),
+            Key::Letter('p') => doc.move_lines(-1),
+            Key::Letter(ch @ ('d' | 'u')) => {
+                doc.with_dired(|dired| Ok(dired.set_mark(line, ch == 'd')));
+                doc.move_lines(1);
+            }
+            Key::Letter('x') => {
+                if doc
+                    .dired()
+                    .is_some_and(|dired| dired.marked().next().is_some())
+                {
+                    self.prompt(Prompt::DiredDelete, "");
+                } else {
+                    self.screen
+                        .echo_area_msg("No files marked for deletion; mark them with d");
+                }
+            }
+            Key::Letter('R') => self.prompt(Prompt::DiredRename, &target),
+            Key::Letter('C') => self.prompt(Prompt::DiredCopy, &target),
+            Key::Letter('+') => self.prompt(Prompt::DiredMkdir, &self.default_directory()),
+            Key::Letter('g') => {
+                if let Some(Err(err)) = doc.with_dired(|dired| dired.refresh()) {
+                    self.screen
+                        .echo_area_error(format!("Refresh failed: {}", err));
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Runs the dired file operation prompt asked for on the line point is on.
+    fn dired_submit(&mut self, prompt: Prompt, input: &str) {
+        let Some(doc) = self.docs.get_mut(self.current) else {
+            return;
+        };
+        let line = doc.point_line();
+        let to = minibuffer::expand_tilde(input)
+            .to_string_lossy()
+            .to_string();
+        let result = doc.with_dired(|dired| match prompt {
+            Prompt::DiredRename => dired
+                .rename(line, &to)
+                .map(|path| format!("Renamed to {}", path.display())),
+            Prompt::DiredCopy => dired
+                .copy(line, &to)
+                .map(|path| format!("Copied to {}", path.display())),
+            Prompt::DiredMkdir => dired
+                .create_dir(&to)
+                .map(|path| format!("Created {}", path.display())),
+            _ if input == "yes" => dired
+                .delete_marked()
+                .map(|count| format!("Deleted {} file(s)", count)),
+            _ => Ok("Deletion cancelled".to_string()),
+        });
+        match result {
+            Some(Ok(msg)) => self.screen.echo_area_msg(msg),
+            Some(Err(err)) => self.screen.echo_area_error(err.to_string()),
+            None => {}
+        }
+    }
+
+    fn toggle_read_only(&mut self) {
+        let Some(doc) = self.docs.get_mut(self.current) else {
+            return;
+        };
+        let read_only = !doc.is_read_only();
+        doc.set_read_only(read_only);
+        if read_only {
+            self.screen.echo_area_msg("Read-only mode enabled");
+        } else if doc.is_write_protected() {
+            self.screen
+                .echo_area_msg("File is write-protected; saving it will fail");
+        } else {
+            self.screen.echo_area_msg("Read-only mode disabled");
+        }
+    }
+
+    fn revert_buffer(&mut self) {
+        let Some(doc) = self.docs.get_mut(self.current) else {
+            return;
+        };
+        match doc.revert() {
+            Ok(()) => self
+                .screen
+                .echo_area_msg(format!("Reverted {}", doc.buffer_name())),
+            Err(err) => self
+                .screen
+                .echo_area_error(format!("Revert failed: {}", err)),
+        }
+    }
+
+    /// Runs when the terminal window gets focus back, which is when files are most
+    /// likely to have been changed by another program.
+    fn focus_in(&mut self) {
+        self.focused = true;
+        self.auto_revert_buffers();
+        if let Some(doc) = self.docs.get(self.current) {
+            if doc.changed_on_disk() {
+                self.screen.echo_area_msg(format!(
+                    "{} changed on disk; M-x revert-buffer to reload it",
+                    doc.buffer_name()
+                ));
+            }
+        }
+    }
+
+    /// Brings every buffer in auto-revert mode up to date with its file. Returns true
+    /// when any of them changed.
+    fn auto_revert_buffers(&mut self) -> bool {
+        let mut reverted = false;
+        for doc in self.docs.iter_mut().filter(|doc| doc.auto_revert()) {
+            match doc.auto_revert_now() {
+                Ok(changed) => reverted |= changed,
+                Err(err) => {
+                    let msg = format!("Auto-revert of {} failed: {}", doc.buffer_name(), err);
+                    doc.set_auto_revert(false);
+                    self.screen.echo_area_error(msg);
+                }
+            }
+        }
+        reverted
+    }
+
+    /// How long the event loop waits for a key before running idle.
+    fn idle_timeout(&self) -> Duration {
+        let mut time_left = self.auto_save.time_left();
+        if self.docs.iter().any(|doc| doc.auto_revert()) {
+            time_left = time_left.min(AUTO_REVERT_INTERVAL);
+        }
+        if self.docs.iter().any(|doc| doc.diff_stale()) {
+            time_left = time_left.min(DIFF_SIGNS_DELAY);
+        }
+        time_left.min(GIT_BRANCH_INTERVAL.saturating_sub(self.branches_read.elapsed()))
+    }
+
+    fn redisplay(&mut self) {
+        self.remap_large_files();
+        self.screen.clr_echo_area_timer();
+        if self.in_messages_buffer() && self.messages_shown != self.screen.messages().count() {
+            self.show_messages();
+        }
+        self.screen
+            .set_mode_line_active(self.focused && self.minibuffer.is_none());
+        if let Some(doc) = self.docs.get(self.current) {
+            self.screen.draw_doc(doc);
+        } else {
+            self.screen.draw_ml_area();
+        }
+        if let Some(minibuffer) = &self.minibuffer {
+            self.screen.draw_minibuffer(minibuffer);
+        }
+    }
+
+    /// Maps large files that were truncated on disk again, before redisplay and key
+    /// dispatch read them. A truncation after this runs can still raise SIGBUS.
+    fn remap_large_files(&mut self) {
+        for doc in &mut self.docs {
+            if let Err(err) = doc.remap_if_shrunk() {
+                let msg = format!("Failed to map {} again: {}", doc.buffer_name(), err);
+                self.screen.echo_area_error(msg);
+            }
+        }
+    }
+
+    /// Runs when no key arrived before the next timer was due.
+    fn idle(&mut self) {
+        if self.auto_save.due() {
+            self.do_auto_save();
+            self.redisplay();
+        }
+        if self.auto_revert_buffers() {
+            self.redisplay();
+        }
+        if self.docs.iter().any(|doc| doc.diff_stale()) {
+            for doc in &mut self.docs {
+                doc.update_diff_signs();
+            }
+            self.redisplay();
+        }
+        if self.branches_read.elapsed() >= GIT_BRANCH_INTERVAL {
+            self.branches_read = Instant::now();
+            let mut changed = false;
+            for doc in &mut self.docs {
+                changed |= doc.refresh_git_branch();
+            }
+            if changed {
+                self.redisplay();
+            }
+        }
+    }
+
+    /// Writes every buffer with unsaved changes to its #file# auto-save file.
+    fn do_auto_save(&mut self) {
+        self.auto_save.reset();
+        let mut saved = 0;
+        for doc in self.docs.iter_mut().filter(|doc| doc.needs_auto_save()) {
+            match doc.auto_save() {
+                Ok(()) => saved += 1,
+                Err(err) => {
+                    let msg = format!("Auto-saving {} failed: {}", doc.buffer_name(), err);
+                    self.screen.echo_area_error(msg);
+                    return;
+                }
+            }
+        }
+        if saved > 0 {
+            self.screen.echo_area_msg("Auto-saving...done");
         }
     }
 
     pub fn event_loop(&mut self) {
+        let mut keys = match self.screen.tty() {
+            Ok(tty) => tty.read_key(),
+            Err(err) => panic!("Couldn't read the terminal. \nError: {}", err),
+        };
+        self.redisplay();
         loop {
-            match std::io::stdin().read_key().next() {
-                Some(Ok(Key::CtrlKey('q'))) => break,
-                Some(Ok(key @ Key::Letter(..))) => {
-                    let mut ostream = std::io::stdout();
-                    match write!(ostream, "{}{}", key, EscSeq::GetCursorPos) {
-                        Ok(_) => {}
-                        Err(err) if err.kind() == ErrorKind::Interrupted => panic!(),
-                        Err(_) => println!("{}", Error::last_os_error()),
+            if !keys.has_pending() {
+                match ffi::poll_read(keys.get_ref(), self.idle_timeout()) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        self.idle();
+                        continue;
                     }
-                    let _ = ostream.flush();
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) => self.screen.echo_area_error(err.to_string()),
                 }
+            }
+            let key = keys.next();
+            if matches!(key, Some(Ok(Key::FocusOut))) {
+                self.focused = false;
+                self.redisplay();
+                continue;
+            }
+            if self.auto_save.keystroke() {
+                self.do_auto_save();
+            }
+            self.remap_large_files();
+            match key {
+                Some(Ok(Key::CtrlKey('q')))
+                    if self.minibuffer.is_none() && self.prefix.is_none() =>
+                {
+                    break
+                }
+                Some(Ok(Key::FocusIn)) => self.focus_in(),
                 Some(Ok(output @ Key::CursorPos(pos))) => {
                     self.screen.draw_cursor_pos(output, pos);
+                    continue;
                 }
-                Some(Ok(Key::AltKey(..))) => continue,
-                Some(Ok(Key::CtrlKey(..))) => continue,
-                Some(Ok(..)) => continue,
-                Some(Err(err)) => println!("{}", err),
+                Some(Ok(key)) if self.minibuffer.is_some() => self.minibuffer_key(key),
+                Some(Ok(key)) => self.command_key(key),
+                Some(Err(err)) => self.screen.echo_area_error(err.to_string()),
                 None => break,
             }
+            self.redisplay();
         }
     }
 
-    pub fn run(&mut self) {
+    /// Runs the editor until the user quits. The exit code is a failure when the last
+    /// save of any buffer did not succeed.
+    pub fn run(&mut self) -> std::process::ExitCode {
         self.event_loop();
 
+        self.screen.focus_reporting(false);
         self.screen.clear_screen();
-        let mut drop_stream = std::io::stdin();
-        let _revert_on_drop =
-            ffi::RevertOnDrop::new(&mut drop_stream, self.screen.copy_original_term());
+        if let Ok(mut drop_stream) = self.screen.tty() {
+            let _revert_on_drop =
+                ffi::RevertOnDrop::new(&mut drop_stream, self.screen.copy_original_term());
+        }
+        if self.docs.iter().any(Document::save_failed) {
+            std::process::ExitCode::FAILURE
+        } else {
+            std::process::ExitCode::SUCCESS
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_file_args() {
+        // mr_text a.rs +5 - from a terminal: "-" opens nothing, so a.rs stays put.
+        let mut docs = vec![Document::from_reader("a.rs", "one\ntwo\n".as_bytes()).unwrap()];
+        let first = FileArg {
+            source: Source::Path("a.rs".to_string()),
+            line: None,
+            col: None,
+        };
+        let failed = FileArg {
+            source: Source::Stdin,
+            line: Some(2),
+            col: Some(3),
+        };
+        apply_file_args(&mut docs, &[(Some(0), &first), (None, &failed)], true);
+        assert_eq!((docs[0].point_line(), docs[0].point_col()), (0, 0));
+        assert!(docs[0].is_read_only());
+
+        apply_file_args(&mut docs, &[(Some(0), &failed)], false);
+        assert_eq!((docs[0].point_line(), docs[0].point_col()), (1, 2));
     }
 }
@@ -2,8 +2,16 @@
 #![allow(dead_code)]
 
 use crate::{
+    document::Document,
+    encoding,
     event::{Key, ReadKey},
     ffi,
+    messages::{self, Messages, Severity},
+    minibuffer::Minibuffer,
+    modeline::{self, Status},
+    theme::{ColorDepth, Face, Theme},
+    visual::{self, LineWrap},
+    whitespace::{self, Glyphs},
 };
 use ropey::Rope;
 use std::{
@@ -17,15 +25,28 @@ pub struct Screen<'a> {
     mode_line: ModeLine<'a>,
     left_margin: LeftMargin<'a>,
     point: Point,
+    theme: Theme,
+    /// The glyphs of whitespace mode.
+    whitespace: Glyphs,
     original_term: Option<libc::termios>,
+    tty: ffi::Tty,
     winsize_row: u16,
     winsize_col: u16,
 }
 
+impl<'a> Default for Screen<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'a> Screen<'a> {
     pub fn new() -> Screen<'a> {
-        let mut istream = std::io::stdin();
-        let winsize = match ffi::io_ctl(&mut istream) {
+        let mut tty = match ffi::Tty::open() {
+            Ok(tty) => tty,
+            Err(err) => panic!("Couldn't open the terminal. \nError: {}", err),
+        };
+        let winsize = match ffi::io_ctl(&mut tty) {
             Ok(winsize) => winsize,
             Err(err) => panic!("Couldn't get screen size. \nError: {}", err),
         };
@@ -34,7 +55,17 @@ impl<'a> Screen<'a> {
             mode_line: ModeLine::default(),
             left_margin: LeftMargin::default(),
             point: Point::default(),
+            theme: {
+                let mut theme = Theme::default();
+                theme.set_depth(ColorDepth::detect());
+                theme
+            },
+            whitespace: std::env::var("MR_TEXT_WHITESPACE_GLYPHS")
+                .ok()
+                .and_then(|glyphs| glyphs.parse().ok())
+                .unwrap_or_default(),
             original_term: None,
+            tty,
             winsize_row: winsize.ws_row,
             winsize_col: winsize.ws_col,
         }
@@ -50,7 +81,7 @@ impl<'a> Screen<'a> {
 
     pub fn clr_echo_area_timer(&mut self) {
         if self.mode_line.echo_area.msg_timer.elapsed().as_secs()
-            == self.mode_line.echo_area.disp_len
+            >= self.mode_line.echo_area.disp_len
         {
             self.mode_line.echo_area.message.clear();
         }
@@ -60,7 +91,77 @@ impl<'a> Screen<'a> {
     where
         M: AsRef<str>,
     {
-        self.mode_line.echo_area.store_message(msg.as_ref());
+        self.mode_line
+            .echo_area
+            .store_message(Severity::Info, msg.as_ref());
+    }
+
+    /// Like echo_area_msg, but logged as an error in *Messages*.
+    pub fn echo_area_error<M>(&mut self, msg: M)
+    where
+        M: AsRef<str>,
+    {
+        self.mode_line
+            .echo_area
+            .store_message(Severity::Error, msg.as_ref());
+    }
+
+    /// Everything the echo area has shown.
+    pub fn messages(&self) -> &Messages {
+        &self.mode_line.echo_area.log
+    }
+
+    /// Replaces the faces of the theme with those of the theme file at path.
+    pub fn load_theme(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        self.theme.load(path)
+    }
+
+    pub fn mode_line_format(&self) -> &str {
+        &self.mode_line.format
+    }
+
+    pub fn set_mode_line_format(&mut self, format: &str) {
+        self.mode_line.format = format.to_string();
+    }
+
+    pub fn line_numbers(&self) -> LineNumbers {
+        self.left_margin.mode
+    }
+
+    pub fn set_line_numbers(&mut self, mode: LineNumbers) {
+        self.left_margin.mode = mode;
+    }
+
+    pub fn set_mode_line_active(&mut self, active: bool) {
+        self.mode_line.active = active;
+    }
+
+    /// The mode line row: the status cut to the screen width, the rest filled with
+    /// the separator, in the active or inactive style.
+    fn mode_line_text(&self) -> std::string::String {
+        let width = self.winsize_col as usize;
+        let mut text = match self.mode_line.status.is_empty() {
+            true => std::string::String::new(),
+            false => format!(
+                "{} {} ",
+                self.mode_line.seperator.repeat(2),
+                visual::truncate(&self.mode_line.status, 0, width, 1, &[])
+            ),
+        };
+        let mut shown = visual::display_col(&text, text.chars().count(), 1);
+        while shown < width {
+            text.push_str(self.mode_line.seperator);
+            shown += 1;
+        }
+        let text: std::string::String = visual::cells(&text, 1)
+            .take_while(|cell| cell.col + cell.width <= width)
+            .map(|cell| cell.text)
+            .collect();
+        let face = match self.mode_line.active {
+            true => Face::ModeLine,
+            false => Face::ModeLineInactive,
+        };
+        self.theme.paint(face, &text)
     }
 
     pub fn copy_original_term(&self) -> libc::termios {
@@ -75,14 +176,27 @@ impl<'a> Screen<'a> {
         self.mode_line.echo_area.msg_timer.elapsed().as_secs() == self.mode_line.echo_area.disp_len
     }
 
-    pub fn raw_mode() {
-        let mut istream = stdin();
-        match ffi::configure_raw(&mut istream) {
+    pub fn raw_mode(&mut self) {
+        match ffi::configure_raw(&mut self.tty) {
             Ok(()) => {}
             Err(err) => panic!("{}", err),
         }
     }
 
+    /// Asks the terminal to report focus changes as FocusIn and FocusOut keys.
+    pub fn focus_reporting(&mut self, on: bool) {
+        let mut ostream = stdout();
+        let seq = EscSeq::FocusReporting(on);
+        if let Err(err) = write!(ostream, "{}", seq).and_then(|_| ostream.flush()) {
+            self.mode_line.echo_area.store_error(err);
+        }
+    }
+
+    /// A second handle on the terminal for reading keys and restoring it on exit.
+    pub fn tty(&self) -> std::io::Result<ffi::Tty> {
+        self.tty.try_clone()
+    }
+
     fn move_cursor<W>(ostream: &mut W, row: u16, col: u16) -> std::io::Result<()>
     where
         W: Write + ?Sized,
@@ -158,6 +272,8 @@ pub trait DrawScreen {
     fn draw_ml(&mut self);
     fn draw_numbered_lm(&mut self);
     fn draw_cursor_pos(&mut self, pos: Key, pos: (u16, u16));
+    fn draw_doc(&mut self, doc: &Document);
+    fn draw_minibuffer(&mut self, minibuffer: &Minibuffer);
     fn clear_screen(&mut self);
 }
 
@@ -188,6 +304,230 @@ impl DrawScreen for Screen<'_> {
         let _ = self.point.go_home(&mut ostream);
     }
 
+    fn draw_doc(&mut self, doc: &Document) {
+        let mut ostream = stdout();
+        let rows = self.text_window.bottom_ln as usize;
+        let sign_columns = doc.signs().columns();
+        let point_line = doc.point_line();
+        // Lines past what a large-file view indexed so far can still be on screen, but
+        // no further down than a screenful below point.
+        let lines = match doc.is_large() {
+            true => doc.known_lines().max(point_line + rows),
+            false => doc.known_lines(),
+        };
+        self.left_margin.fit(lines, rows, sign_columns.len());
+        let first_col = self.left_margin.thickness;
+        let width = self.winsize_col.saturating_sub(first_col) as usize;
+        let point_text = doc.line_text(point_line).unwrap_or_default();
+        let line_wrap = doc.line_wrap();
+        let tab_width = doc.tab_width();
+
+        // Where point is within its line: the wrapped row and the column on it.
+        let (point_row, point_col) = match line_wrap {
+            LineWrap::Soft => {
+                let wrapped = visual::wrap(&point_text, width, tab_width);
+                let (row, col) = visual::cursor(&point_text, &wrapped, doc.point_col(), tab_width);
+                self.text_window
+                    .scroll_to_row(point_line, row, rows, |line| match doc.line_text(line) {
+                        Some(text) => visual::wrap(&text, width, tab_width).len(),
+                        None => 1,
+                    });
+                (row, col)
+            }
+            LineWrap::Truncate => {
+                self.text_window.scroll_to(point_line, rows);
+                let col = visual::display_col(&point_text, doc.point_col(), tab_width);
+                self.text_window.hscroll =
+                    visual::hscroll_for(self.text_window.hscroll, col, width);
+                (0, col - self.text_window.hscroll)
+            }
+        };
+
+        // The bracket next to point and its match, by line: reversed when they match,
+        // on red when they don't.
+        let rope = doc.rope();
+        let parens: Vec<(usize, visual::Span)> = match doc.paren_match() {
+            Some(paren) => {
+                let sgr = match paren.other {
+                    Some(_) => self.theme.sgr(Face::ParenMatch),
+                    None => self.theme.sgr(Face::ParenMismatch),
+                };
+                [Some(paren.at), paren.other]
+                    .into_iter()
+                    .flatten()
+                    .map(|idx| {
+                        let line = rope.char_to_line(idx);
+                        let start = idx - rope.line_to_char(line);
+                        let span = visual::Span {
+                            start,
+                            end: start + 1,
+                            sgr: sgr.clone(),
+                            glyph: None,
+                        };
+                        (line, span)
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        let whitespace = doc.whitespace_mode();
+        let mut frame = std::string::String::from(HIDE_CURSOR);
+        let mut margin = Vec::with_capacity(rows);
+        let (mut line_idx, mut skip) = (self.text_window.cur_line, self.text_window.top_row);
+        while margin.len() < rows {
+            let Some(line) = doc.line_text(line_idx).map(|line| displayable(&line)) else {
+                margin.push(MarginRow::Empty);
+                frame.push_str(&format!(
+                    "\x1b[{};{}H{}",
+                    margin.len(),
+                    first_col,
+                    CLR_LN_CURSR_END
+                ));
+                continue;
+            };
+            // Later spans win, so the paren highlight shows over the syntax colours.
+            let spans: Vec<visual::Span> = doc
+                .highlights(line_idx)
+                .into_iter()
+                .filter(|token| !self.theme.sgr(token.face).is_empty())
+                .map(|token| visual::Span {
+                    start: token.start,
+                    end: token.end,
+                    sgr: self.theme.sgr(token.face),
+                    glyph: None,
+                })
+                .chain(match whitespace {
+                    true => whitespace::spans(
+                        &line,
+                        &self.whitespace,
+                        &self.theme.sgr(Face::Whitespace),
+                        &self.theme.sgr(Face::TrailingWhitespace),
+                    ),
+                    false => Vec::new(),
+                })
+                .chain(
+                    parens
+                        .iter()
+                        .filter(|(line, _)| *line == line_idx)
+                        .map(|(_, span)| span.clone()),
+                )
+                .collect();
+            let end_col = visual::display_col(&line, line.chars().count(), tab_width);
+            let (mut shown, room) = match line_wrap {
+                LineWrap::Soft => {
+                    let rows = visual::wrap(&line, width, tab_width);
+                    let last_col = rows
+                        .last()
+                        .map_or(0, |row| visual::display_col(&line, row.start, tab_width));
+                    let shown: Vec<std::string::String> = rows
+                        .into_iter()
+                        .map(|row| visual::row_text(&line, row, tab_width, &spans))
+                        .collect();
+                    (shown, end_col - last_col < width)
+                }
+                LineWrap::Truncate => {
+                    let hscroll = self.text_window.hscroll;
+                    let shown = vec![visual::truncate(&line, hscroll, width, tab_width, &spans)];
+                    (shown, (hscroll..hscroll + width).contains(&end_col))
+                }
+            };
+            // The end of line marker goes after the text, where there is room for it.
+            if whitespace && room && line_idx + 1 < doc.rope().len_lines() {
+                if let Some(last) = shown.last_mut() {
+                    let eol = self.whitespace.eol.to_string();
+                    last.push_str(&self.theme.paint(Face::Whitespace, &eol));
+                }
+            }
+            for (idx, text) in shown.iter().enumerate().skip(skip) {
+                if margin.len() == rows {
+                    break;
+                }
+                margin.push(match idx {
+                    0 => MarginRow::Line(line_idx),
+                    _ => MarginRow::Continuation,
+                });
+                if line_idx == point_line && idx == point_row {
+                    self.point.row = margin.len() as u16;
+                }
+                frame.push_str(&format!(
+                    "\x1b[{};{}H{}",
+                    margin.len(),
+                    first_col,
+                    CLR_LN_CURSR_END
+                ));
+                frame.push_str(text);
+            }
+            line_idx += 1;
+            skip = 0;
+        }
+        match write!(ostream, "{}", frame) {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::Interrupted => self.draw_doc(doc),
+            Err(err) => self.mode_line.echo_area.store_error(err),
+        }
+        let shown_signs = doc
+            .signs()
+            .in_lines(doc.rope(), self.text_window.cur_line..line_idx);
+        self.left_margin.signs = margin
+            .iter()
+            .map(|row| match row {
+                MarginRow::Line(line) if shown_signs.contains_key(line) => sign_columns
+                    .iter()
+                    .map(|column| match shown_signs[line][*column as usize] {
+                        Some(kind) => self.theme.paint(kind.face(), kind.glyph()),
+                        None => " ".to_string(),
+                    })
+                    .collect(),
+                _ => " ".repeat(sign_columns.len()),
+            })
+            .collect();
+        self.left_margin.rows = margin;
+        self.left_margin.point_line = point_line;
+
+        let status = Status {
+            buffer_name: doc.buffer_name().to_string(),
+            modified: doc.is_modified(),
+            read_only: doc.is_read_only(),
+            mode: doc.mode_name(),
+            line: point_line + 1,
+            col: visual::display_col(&point_text, doc.point_col(), tab_width) + 1,
+            point: doc.point(),
+            len: doc.rope().len_chars(),
+            encoding: format!(
+                "{}{}",
+                doc.encoding(),
+                if doc.has_bom() { " BOM" } else { "" }
+            ),
+            eol: doc.eol().to_string(),
+            branch: doc.git_branch().map(str::to_string),
+        };
+        self.mode_line.status = modeline::format(&self.mode_line.format, &status);
+        self.point.col = first_col + point_col.min(width) as u16;
+        self.draw_numbered_lm();
+        self.draw_ml_area();
+    }
+
+    fn draw_minibuffer(&mut self, minibuffer: &Minibuffer) {
+        let mut ostream = stdout();
+        match write!(
+            ostream,
+            "{}\x1b[{};{}H{}{}{}{}",
+            HIDE_CURSOR,
+            self.winsize_row,
+            0,
+            CLR_LN,
+            minibuffer.prompt(),
+            minibuffer.input(),
+            SHOW_CURSOR
+        ) {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::Interrupted => self.draw_minibuffer(minibuffer),
+            Err(err) => self.mode_line.echo_area.store_error(err),
+        }
+        let _ = ostream.flush();
+    }
+
     fn clear_screen(&mut self) {
         let mut ostream = stdout();
         match write!(ostream, "{}", CLR_SCRN,) {
@@ -206,33 +546,39 @@ impl DrawScreen for Screen<'_> {
 
     fn draw_numbered_lm(&mut self) {
         let mut ostream = stdout();
-        for (num, row) in (1..=self.point.row).rev().enumerate() {
-            match write!(
-                ostream,
-                "{}\x1b[{};{}H{}\x1b[{};{}H{}",
-                HIDE_CURSOR, row, self.left_margin.thickness, CLR_LN_UPTO_CURSR, row, 1, num
-            ) {
-                Ok(_) => {}
-                Err(err) if err.kind() == ErrorKind::Interrupted => self.clear_screen(),
-                Err(err) => self.mode_line.echo_area.store_error(err),
-            }
+        let point_line = self.left_margin.point_line;
+        let mut frame = std::string::String::from(HIDE_CURSOR);
+        if self.left_margin.thickness == 1 {
+            return;
         }
-        for (num, row) in (self.point.row..self.winsize_row - 1).enumerate() {
-            match write!(
-                ostream,
-                "{}\x1b[{};{}H{}\x1b[{};{}H{}",
-                HIDE_CURSOR, row, self.left_margin.thickness, CLR_LN_UPTO_CURSR, row, 1, num
-            ) {
-                Ok(_) => {}
-                Err(err) if err.kind() == ErrorKind::Interrupted => self.clear_screen(),
-                Err(err) => self.mode_line.echo_area.store_error(err),
-            }
+        for (idx, margin_row) in self.left_margin.rows.iter().enumerate() {
+            let face = match *margin_row {
+                MarginRow::Line(line) if line == point_line => Face::LineNumberCurrent,
+                _ => Face::LineNumber,
+            };
+            let label = self.left_margin.label(*margin_row);
+            let text = match label.is_empty() {
+                true => std::string::String::new(),
+                false => self.theme.paint(face, &label),
+            };
+            let signs = self
+                .left_margin
+                .signs
+                .get(idx)
+                .map(std::string::String::as_str)
+                .unwrap_or_default();
+            frame.push_str(&format!(
+                "\x1b[{};{}H{}\x1b[{};{}H{}{}",
+                idx + 1,
+                self.left_margin.thickness - 1,
+                CLR_LN_UPTO_CURSR,
+                idx + 1,
+                1,
+                signs,
+                text
+            ));
         }
-        match write!(
-            ostream,
-            "\x1b[{};{}H{}{}",
-            self.point.row, 1, CLR_LN_UPTO_CURSR, self.left_margin.indicator
-        ) {
+        match write!(ostream, "{}", frame) {
             Ok(_) => {}
             Err(err) if err.kind() == ErrorKind::Interrupted => self.clear_screen(),
             Err(err) => self.mode_line.echo_area.store_error(err),
@@ -244,8 +590,10 @@ impl DrawScreen for Screen<'_> {
         let mut ostream = stdout();
         match write!(
             ostream,
-            "{}{}{}{}",
-            HIDE_CURSOR, CLR_SCRN, self.left_margin.seperator_line, &self.mode_line.start_greeting
+            "{}{}{}",
+            HIDE_CURSOR,
+            CLR_SCRN,
+            self.mode_line_text()
         ) {
             Ok(_) => {}
             Err(err) if err.kind() == ErrorKind::Interrupted => self.draw_screen(),
@@ -263,7 +611,7 @@ impl DrawScreen for Screen<'_> {
             self.mode_line.sep_line_pos,
             0,
             CLR_SCRN_CURSR_END,
-            &self.mode_line.seperator_line,
+            self.mode_line_text(),
         ) {
             Ok(_) => {}
             Err(err) if err.kind() == ErrorKind::Interrupted => self.draw_screen(),
@@ -276,8 +624,8 @@ impl DrawScreen for Screen<'_> {
         let mut ostream = stdout();
         match write!(
             ostream,
-            "{}\x1b[{};{}H{}R: {}, C: {}",
-            HIDE_CURSOR, self.winsize_row, 0, CLR_LN, self.point.row, self.point.col
+            "{}\x1b[{};{}H{}",
+            HIDE_CURSOR, self.winsize_row, 0, CLR_LN
         ) {
             Ok(_) => {}
             Err(err) if err.kind() == ErrorKind::Interrupted => self.draw_ml(),
@@ -288,7 +636,12 @@ impl DrawScreen for Screen<'_> {
             match write!(
                 ostream,
                 "\x1b[{};{}H{}",
-                self.winsize_row, self.mode_line.msg_pos, self.mode_line.echo_area.message
+                self.winsize_row,
+                self.mode_line.msg_pos,
+                messages::echo_line(
+                    &self.mode_line.echo_area.message,
+                    (self.winsize_col + 1).saturating_sub(self.mode_line.msg_pos) as usize
+                )
             ) {
                 Ok(_) => {}
                 Err(err) if err.kind() == ErrorKind::Interrupted => self.draw_ml(),
@@ -315,8 +668,10 @@ impl Builder for Screen<'_> {
             mode_line: self.mode_line,
             left_margin: self.left_margin,
             point: self.point,
+            theme: self.theme,
+            whitespace: self.whitespace,
             original_term: self.original_term,
-            arena: self.arena,
+            tty: self.tty,
             winsize_row: self.winsize_row,
             winsize_col: self.winsize_col,
         }
@@ -328,6 +683,8 @@ impl Builder for Screen<'_> {
             bottom_ln: self.winsize_row - self.mode_line.thickness,
             // TODO: rope slice for displaying current
             cur_line: 0,
+            top_row: 0,
+            hscroll: 0,
         };
         self
     }
@@ -344,62 +701,30 @@ impl Builder for Screen<'_> {
             thickness: 3,
             seperator_line: self.mode_line.seperator_line,
             sep_line_pos: self.winsize_row - 1,
-            msg_pos: self.winsize_col / 6,
-            start_greeting: "Hi David!".to_string(),
+            msg_pos: 1,
+            format: std::env::var("MR_TEXT_MODE_LINE")
+                .ok()
+                .filter(|format| !format.is_empty())
+                .unwrap_or_else(|| modeline::DEFAULT_FORMAT.to_string()),
+            active: true,
+            status: std::string::String::new(),
             echo_area: EchoArea::default(),
         };
         self
     }
 
     fn left_margin(mut self) -> Self {
-        let winsize_row = 60;
-        let mut new_line = std::string::String::new();
-        let rng: (i32, i32) = ((winsize_row as i32) * -1, winsize_row as i32);
-        let mut disp_number = (rng.0..rng.1).into_iter();
-        let mut term_row = (1..=rng.1).into_iter();
-
-        loop {
-            let mut num_row: (i32, i32) = match (disp_number.next(), term_row.next()) {
-                (Some(num), Some(row)) => (num, row),
-                (Some(_), None) => {
-                    term_row = (1..=rng.1).into_iter();
-                    (0, 0)
-                }
-                _ => break,
-            };
-            if num_row.0.is_negative() {
-                num_row.0 = num_row.0 * -1;
-            }
-            if num_row.0 != 0 {
-                new_line.push_str(&format!(
-                    "{}\x1b[{};{}H{}\x1b[{};{}H{}",
-                    HIDE_CURSOR,
-                    num_row.1,
-                    self.left_margin.thickness,
-                    CLR_LN_UPTO_CURSR,
-                    num_row.1,
-                    1,
-                    num_row.0
-                ));
-            } else {
-                new_line.push_str(&format!(
-                    "{}\x1b[{};{}H{}\x1b[{};{}H {}",
-                    HIDE_CURSOR,
-                    num_row.1,
-                    self.left_margin.thickness,
-                    CLR_LN_UPTO_CURSR,
-                    num_row.1,
-                    1,
-                    self.left_margin.indicator
-                ));
-            }
+        let mut left_margin = LeftMargin::new();
+        if let Some(mode) = std::env::var("MR_TEXT_LINE_NUMBERS")
+            .ok()
+            .and_then(|mode| mode.parse().ok())
+        {
+            left_margin.mode = mode;
         }
-
-        self.left_margin = LeftMargin {
-            indicator: "=>",
-            thickness: 4,
-            seperator_line: new_line,
-        };
+        left_margin.fit(1, 1, 0);
+        // Until a buffer is drawn, show the empty line point is on.
+        left_margin.rows = vec![MarginRow::Line(0)];
+        self.left_margin = left_margin;
         self
     }
 
@@ -412,8 +737,7 @@ impl Builder for Screen<'_> {
     }
 
     fn backup_terminal(mut self) -> Self {
-        let mut istream = std::io::stdin();
-        self.original_term = match ffi::tc_getattr(&mut istream) {
+        self.original_term = match ffi::tc_getattr(&mut self.tty) {
             Ok(backup) => Some(backup),
             Err(err) => panic!("Error: {}", err),
         };
@@ -455,6 +779,54 @@ pub struct TextWindow {
     bottom_ln: u16,
     rope: ropey::Rope,
     cur_line: usize,
+    /// Wrapped rows of cur_line scrolled off the top, when it doesn't fit on screen.
+    top_row: usize,
+    /// Columns scrolled off the left edge of truncated lines.
+    hscroll: usize,
+}
+
+impl TextWindow {
+    /// Scrolls the window the least amount needed to keep line visible. cur_line is the
+    /// first buffer line shown.
+    pub fn scroll_to(&mut self, line: usize, rows: usize) {
+        self.top_row = 0;
+        if line < self.cur_line {
+            self.cur_line = line;
+        } else if rows > 0 && line >= self.cur_line + rows {
+            self.cur_line = line + 1 - rows;
+        }
+    }
+
+    /// Same as scroll_to for wrapped lines: keeps wrapped row of line visible, where
+    /// line_rows gives the number of screen rows a buffer line wraps to.
+    pub fn scroll_to_row<F>(&mut self, line: usize, row: usize, rows: usize, line_rows: F)
+    where
+        F: Fn(usize) -> usize,
+    {
+        if rows == 0 {
+            return;
+        }
+        if (line, row) < (self.cur_line, self.top_row) {
+            self.cur_line = line;
+            self.top_row = row;
+            return;
+        }
+        // Every line takes at least one row, so nothing further up can still be shown.
+        if line >= self.cur_line + rows {
+            self.cur_line = line + 1 - rows;
+            self.top_row = 0;
+        }
+        let mut used =
+            (self.cur_line..line).map(&line_rows).sum::<usize>() + row + 1 - self.top_row;
+        while used > rows {
+            self.top_row += 1;
+            if self.top_row >= line_rows(self.cur_line) {
+                self.cur_line += 1;
+                self.top_row = 0;
+            }
+            used -= 1;
+        }
+    }
 }
 
 /// Use the screen builder method to construct a ModeLine. The initial field value of
@@ -466,7 +838,12 @@ pub struct ModeLine<'a> {
     seperator_line: std::string::String,
     sep_line_pos: u16,
     msg_pos: u16,
-    start_greeting: std::string::String,
+    /// What the mode line shows, with the placeholders of modeline::format.
+    format: std::string::String,
+    /// Drawn in the inactive style while the minibuffer is in use or the terminal
+    /// doesn't have focus.
+    active: bool,
+    status: std::string::String,
     echo_area: EchoArea,
 }
 
@@ -505,7 +882,8 @@ impl<'a> ModeLine<'a> {
 /// the message will be displayed in the mode line, measured in seconds.
 #[derive(Debug, Clone)]
 struct EchoArea {
-    errors: Rope,
+    /// Every message shown here, for the *Messages* buffer.
+    log: Messages,
     message: std::string::String,
     msg_timer: std::time::Instant,
     disp_len: u64,
@@ -520,7 +898,7 @@ impl std::fmt::Display for EchoArea {
 impl Default for EchoArea {
     fn default() -> Self {
         EchoArea {
-            errors: Rope::new(),
+            log: Messages::default(),
             message: std::string::String::with_capacity(64),
             msg_timer: std::time::Instant::now(),
             disp_len: 3,
@@ -536,37 +914,87 @@ impl EchoArea {
     where
         T: Display,
     {
-        let error = format!("Error: {}", err);
-        self.errors.insert(self.errors.len_chars(), &error);
-        // Insert the \n seperately so the error doesn't shift the screen when displayed in echo area.
-        self.errors.insert(self.errors.len_chars(), "\n");
-        self.message.clear();
-        if error.len() > 64 {
-            self.message = "You've got an error.".to_string();
-        } else {
-            self.message = error;
-        }
-        self.store_message("Mr-Text found an error.");
+        self.store_message(Severity::Error, &format!("Error: {}", err));
     }
 
-    fn store_message(&mut self, msg: &str) {
+    /// Shows msg until the display time runs out and logs it. draw_ml cuts it to the
+    /// width of the screen.
+    fn store_message(&mut self, severity: Severity, msg: &str) {
         self.msg_timer = std::time::Instant::now();
-        if msg.len() < 64 {
-            self.message.push_str(msg);
-        } else {
-            self.message.push_str(&msg[..64]);
-        }
+        self.log.push(severity, msg);
+        self.message.clear();
+        self.message.push_str(msg);
     }
 }
 
-/// Use the Screen builder method to construct a new LeftMargin. The thickness field is measured in
-/// terminal rows. Each row is the thickness of one terminal character. The initial field value of LeftMargin
-/// thickness is 2, meaning two rows or two characters. The initial seperator is "~".
+/// Use the Screen builder method to construct a new LeftMargin, the gutter. The thickness field is
+/// measured in terminal columns: a column for each kind of gutter sign the buffer has, the line
+/// numbers, one blank column, and the text starts in the column after. fit sets it each time a
+/// buffer is drawn.
 #[derive(Debug)]
 pub struct LeftMargin<'a> {
     thickness: u16,
+    mode: LineNumbers,
+    /// Shown next to point's line in relative mode.
     indicator: &'a str,
-    seperator_line: std::string::String,
+    /// Shown next to the rows a wrapped line continues on.
+    continuation: &'a str,
+    /// What each text row showed the last time a buffer was drawn.
+    rows: Vec<MarginRow>,
+    /// The painted sign columns of each row in rows.
+    signs: Vec<std::string::String>,
+    sign_columns: usize,
+    point_line: usize,
+}
+
+/// What the left margin shows next to one row of the text window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarginRow {
+    /// The first row of a buffer line.
+    Line(usize),
+    /// A further row of a wrapped line.
+    Continuation,
+    /// Past the end of the buffer.
+    Empty,
+}
+
+/// How lines are numbered in the left margin.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineNumbers {
+    /// No margin; text starts in the first column.
+    Off,
+    /// Every line shows its own number.
+    Absolute,
+    /// Lines show how far they are from point's line, which shows the indicator.
+    #[default]
+    Relative,
+    /// Like Relative, but point's line shows its own number.
+    Hybrid,
+}
+
+impl std::fmt::Display for LineNumbers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineNumbers::Off => write!(f, "off"),
+            LineNumbers::Absolute => write!(f, "absolute"),
+            LineNumbers::Relative => write!(f, "relative"),
+            LineNumbers::Hybrid => write!(f, "hybrid"),
+        }
+    }
+}
+
+impl std::str::FromStr for LineNumbers {
+    type Err = std::string::String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "none" => Ok(LineNumbers::Off),
+            "absolute" => Ok(LineNumbers::Absolute),
+            "relative" => Ok(LineNumbers::Relative),
+            "hybrid" => Ok(LineNumbers::Hybrid),
+            _ => Err(format!("Unknown line number mode: {}", s)),
+        }
+    }
 }
 
 impl<'a> std::default::Default for LeftMargin<'a> {
@@ -579,8 +1007,13 @@ impl<'a> LeftMargin<'a> {
     pub fn new() -> Self {
         LeftMargin {
             thickness: 0,
-            indicator: " =>",
-            seperator_line: String::new(),
+            mode: LineNumbers::default(),
+            indicator: "=>",
+            continuation: "\\",
+            rows: Vec::new(),
+            signs: Vec::new(),
+            sign_columns: 0,
+            point_line: 0,
         }
     }
     pub fn new_sep(&mut self, indicator: &'a str) {
@@ -590,6 +1023,61 @@ impl<'a> LeftMargin<'a> {
     pub fn new_thickness(&mut self, thickness: u16) {
         self.thickness = thickness
     }
+
+    /// Sets the thickness for a buffer of lines lines shown in rows text rows, with
+    /// sign_columns columns of gutter signs: wide enough for the largest number the mode
+    /// can show, and for the indicator.
+    fn fit(&mut self, lines: usize, rows: usize, sign_columns: usize) {
+        self.sign_columns = sign_columns;
+        let largest = match self.mode {
+            LineNumbers::Off if sign_columns == 0 => {
+                self.thickness = 1;
+                return;
+            }
+            LineNumbers::Off => {
+                self.thickness = (sign_columns + 2) as u16;
+                return;
+            }
+            LineNumbers::Absolute | LineNumbers::Hybrid => lines,
+            LineNumbers::Relative => lines.min(rows),
+        };
+        let digits = largest.max(1).ilog10() as usize + 1;
+        let indicator = visual::display_col(self.indicator, self.indicator.chars().count(), 1);
+        let width = digits.max(indicator);
+        self.thickness = (sign_columns + width + 2) as u16;
+    }
+
+    /// The text for one row, right-aligned in the columns before the blank one.
+    fn label(&self, row: MarginRow) -> std::string::String {
+        let width = (self.thickness as usize).saturating_sub(self.sign_columns + 2);
+        let text = match row {
+            _ if self.mode == LineNumbers::Off => return std::string::String::new(),
+            MarginRow::Empty => return std::string::String::new(),
+            MarginRow::Continuation => self.continuation.to_string(),
+            MarginRow::Line(line) => match self.mode {
+                LineNumbers::Off | LineNumbers::Absolute => (line + 1).to_string(),
+                LineNumbers::Hybrid if line == self.point_line => (line + 1).to_string(),
+                LineNumbers::Relative if line == self.point_line => self.indicator.to_string(),
+                LineNumbers::Relative | LineNumbers::Hybrid => {
+                    line.abs_diff(self.point_line).to_string()
+                }
+            },
+        };
+        format!("{:>width$}", text, width = width)
+    }
+}
+
+/// The text of a line with raw bytes and control characters replaced, since control
+/// characters would move the terminal cursor. visual::width counts them as the one
+/// column the replacement takes, and tabs are left for visual to expand.
+fn displayable(line: &str) -> std::string::String {
+    line.chars()
+        .map(|ch| match encoding::raw_byte(ch) {
+            Some(_) => char::REPLACEMENT_CHARACTER,
+            None if ch.is_control() && ch != '\t' => char::REPLACEMENT_CHARACTER,
+            None => ch,
+        })
+        .collect()
 }
 
 /// # Safety
@@ -629,6 +1117,8 @@ pub enum EscSeq {
     MvRight,    //  "\x1b[1C";
     MvUp,       //  "\x1b[1A";
     MvDown,     //  "\x1b[1B";
+
+    FocusReporting(bool), //  "\x1b[?1004h" or "\x1b[?1004l";
 }
 
 impl std::fmt::Display for EscSeq {
@@ -649,6 +1139,8 @@ impl std::fmt::Display for EscSeq {
             EscSeq::MvRight => write!(f, "\x1b[1C"),
             EscSeq::MvUp => write!(f, "\x1b[1A"),
             EscSeq::MvDown => write!(f, "\x1b[1B"),
+            EscSeq::FocusReporting(true) => write!(f, "\x1b[?1004h"),
+            EscSeq::FocusReporting(false) => write!(f, "\x1b[?1004l"),
         }
     }
 }
@@ -673,3 +1165,46 @@ const MV_DOWN: &str = "\x1b[1B";
 
 const ESC_SEQ_LEN: usize = 5;
 const SEMICOLON: u8 = 59;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_left_margin() {
+        let mut margin = LeftMargin::new();
+        margin.point_line = 9;
+        margin.fit(1234, 40, 0);
+        assert_eq!(margin.thickness, 4);
+        assert_eq!(margin.label(MarginRow::Line(9)), "=>");
+        assert_eq!(margin.label(MarginRow::Line(12)), " 3");
+        assert_eq!(margin.label(MarginRow::Continuation), " \\");
+        assert_eq!(margin.label(MarginRow::Empty), "");
+
+        margin.mode = LineNumbers::Absolute;
+        margin.fit(1234, 40, 0);
+        assert_eq!(margin.thickness, 6);
+        assert_eq!(margin.label(MarginRow::Line(9)), "  10");
+        assert_eq!(margin.label(MarginRow::Line(12)), "  13");
+
+        margin.mode = LineNumbers::Hybrid;
+        margin.fit(7, 40, 0);
+        assert_eq!(margin.thickness, 4);
+        assert_eq!(margin.label(MarginRow::Line(9)), "10");
+        assert_eq!(margin.label(MarginRow::Line(7)), " 2");
+
+        margin.mode = "off".parse().unwrap();
+        margin.fit(1234, 40, 0);
+        assert_eq!(margin.thickness, 1);
+        assert_eq!(margin.label(MarginRow::Line(9)), "");
+
+        // Sign columns go before the numbers.
+        margin.fit(1234, 40, 2);
+        assert_eq!(margin.thickness, 4);
+        margin.mode = LineNumbers::Absolute;
+        margin.fit(1234, 40, 2);
+        assert_eq!(margin.thickness, 8);
+        assert_eq!(margin.label(MarginRow::Line(9)), "  10");
+        assert!("sideways".parse::<LineNumbers>().is_err());
+    }
+}
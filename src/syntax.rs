@@ -0,0 +1,290 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+/// What a character is to motion commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Whitespace,
+    /// Part of a word: M-f and M-b stop where a run of these ends.
+    Word,
+    /// Part of a symbol but not of a word, like "_" in code, so M-f stops at each
+    /// part of snake_case names.
+    Symbol,
+    Punct,
+    /// An opening bracket, with the character that closes it.
+    Open(char),
+    /// A closing bracket, with the character that opens it.
+    Close(char),
+    /// Starts a string that runs to the next unescaped copy of the same character.
+    StringQuote,
+    /// Starts a comment that runs to the end of the line.
+    Comment,
+}
+
+/// Classes of characters for one kind of buffer. Letters and digits are words and
+/// whitespace is whitespace everywhere; entries only hold what differs from that.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTable {
+    entries: HashMap<char, Class>,
+    /// What starts a line comment. A two character starter like "//" is only known to
+    /// the Scanner, since its first character alone is punctuation.
+    comment: Option<&'static str>,
+}
+
+impl Default for SyntaxTable {
+    fn default() -> Self {
+        Self::text()
+    }
+}
+
+impl SyntaxTable {
+    fn with(entries: &[(char, Class)], comment: Option<&'static str>) -> SyntaxTable {
+        let mut table = SyntaxTable {
+            entries: [
+                ('(', Class::Open(')')),
+                (')', Class::Close('(')),
+                ('[', Class::Open(']')),
+                (']', Class::Close('[')),
+                ('{', Class::Open('}')),
+                ('}', Class::Close('{')),
+            ]
+            .into_iter()
+            .chain(entries.iter().copied())
+            .collect(),
+            comment,
+        };
+        if let Some(ch) = comment.filter(|comment| comment.chars().count() == 1) {
+            table
+                .entries
+                .insert(ch.chars().next().unwrap(), Class::Comment);
+        }
+        table
+    }
+
+    /// Prose: apostrophes are part of words, so "don't" is one word.
+    pub fn text() -> SyntaxTable {
+        Self::with(&[('\'', Class::Word), ('\u{2019}', Class::Word)], None)
+    }
+
+    /// Markdown: prose with `code spans` read as strings.
+    pub fn markdown() -> SyntaxTable {
+        Self::with(
+            &[
+                ('\'', Class::Word),
+                ('\u{2019}', Class::Word),
+                ('`', Class::StringQuote),
+            ],
+            None,
+        )
+    }
+
+    /// C-like source code: "_" joins symbols but separates words, "//" comments.
+    pub fn code() -> SyntaxTable {
+        Self::with(
+            &[('_', Class::Symbol), ('"', Class::StringQuote)],
+            Some("//"),
+        )
+    }
+
+    /// Rust reads like code; "'" is left as punctuation since it starts lifetimes as
+    /// often as char literals.
+    pub fn rust() -> SyntaxTable {
+        Self::code()
+    }
+
+    /// Shell and other languages with "#" comments and both kinds of quotes.
+    pub fn shell() -> SyntaxTable {
+        Self::with(
+            &[
+                ('_', Class::Symbol),
+                ('"', Class::StringQuote),
+                ('\'', Class::StringQuote),
+            ],
+            Some("#"),
+        )
+    }
+
+    /// TOML: like shell, with "-" in bare keys.
+    pub fn toml() -> SyntaxTable {
+        let mut table = Self::shell();
+        table.entries.insert('-', Class::Symbol);
+        table
+    }
+
+    pub fn json() -> SyntaxTable {
+        Self::with(&[('"', Class::StringQuote)], None)
+    }
+
+    /// The table for a file with extension ext.
+    pub fn for_extension(ext: Option<&str>) -> SyntaxTable {
+        match ext {
+            Some("rs") => Self::rust(),
+            Some("toml") => Self::toml(),
+            Some("json") => Self::json(),
+            Some("sh" | "bash" | "zsh" | "py") => Self::shell(),
+            Some("md" | "markdown") => Self::markdown(),
+            Some("c" | "h" | "cpp" | "js" | "ts" | "go" | "java") => Self::code(),
+            _ => Self::text(),
+        }
+    }
+
+    pub fn class(&self, ch: char) -> Class {
+        match self.entries.get(&ch) {
+            Some(class) => *class,
+            None if ch.is_alphanumeric() => Class::Word,
+            None if ch.is_whitespace() => Class::Whitespace,
+            None => Class::Punct,
+        }
+    }
+
+    pub fn is_word(&self, ch: char) -> bool {
+        self.class(ch) == Class::Word
+    }
+
+    pub fn comment(&self) -> Option<&'static str> {
+        self.comment
+    }
+}
+
+/// Where a character sits as far as brackets are concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    Code,
+    /// Inside the string whose opening quote is at this offset, quotes included.
+    String(usize),
+    /// Inside a line comment, its starter included.
+    Comment,
+}
+
+/// Walks text telling for each character whether it is code, part of a string or
+/// part of a comment, so brackets in strings and comments can be told apart from
+/// real ones. Offsets count from where the scan started.
+pub struct Scanner<'a, I: Iterator<Item = char>> {
+    table: &'a SyntaxTable,
+    chars: std::iter::Peekable<I>,
+    pos: usize,
+    state: Context,
+    quote: char,
+    escaped: bool,
+}
+
+impl<'a, I: Iterator<Item = char>> Scanner<'a, I> {
+    /// Scans chars, the first of which is at offset pos and outside any string.
+    pub fn new(table: &'a SyntaxTable, chars: I, pos: usize) -> Self {
+        Scanner {
+            table,
+            chars: chars.peekable(),
+            pos,
+            state: Context::Code,
+            quote: '"',
+            escaped: false,
+        }
+    }
+
+    fn comment_starts(&mut self, ch: char) -> bool {
+        let Some(comment) = self.table.comment else {
+            return false;
+        };
+        let mut starter = comment.chars();
+        starter.next() == Some(ch)
+            && match starter.next() {
+                None => true,
+                Some(second) => self.chars.peek() == Some(&second),
+            }
+    }
+}
+
+impl<I: Iterator<Item = char>> Iterator for Scanner<'_, I> {
+    type Item = (usize, char, Context);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ch = self.chars.next()?;
+        let pos = self.pos;
+        self.pos += 1;
+        let comment = self.state == Context::Code && self.comment_starts(ch);
+        let context = match self.state {
+            Context::Comment if matches!(ch, '\n' | '\r') => {
+                self.state = Context::Code;
+                Context::Code
+            }
+            Context::Comment => Context::Comment,
+            Context::String(start) => {
+                if self.escaped {
+                    self.escaped = false;
+                } else if ch == '\\' {
+                    self.escaped = true;
+                } else if ch == self.quote {
+                    self.state = Context::Code;
+                }
+                Context::String(start)
+            }
+            Context::Code if self.table.class(ch) == Class::StringQuote => {
+                self.state = Context::String(pos);
+                self.quote = ch;
+                self.state
+            }
+            Context::Code if comment => {
+                self.state = Context::Comment;
+                Context::Comment
+            }
+            Context::Code => Context::Code,
+        };
+        Some((pos, ch, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classes() {
+        let text = SyntaxTable::text();
+        assert_eq!(text.class('x'), Class::Word);
+        assert_eq!(text.class('7'), Class::Word);
+        assert_eq!(text.class('\''), Class::Word);
+        assert_eq!(text.class('_'), Class::Punct);
+        assert_eq!(text.class('\t'), Class::Whitespace);
+        assert_eq!(text.class('['), Class::Open(']'));
+        assert_eq!(text.class('}'), Class::Close('{'));
+        let code = SyntaxTable::for_extension(Some("rs"));
+        assert_eq!(code.class('_'), Class::Symbol);
+        assert_eq!(code.class('\''), Class::Punct);
+        assert_eq!(code.class('"'), Class::StringQuote);
+        assert_eq!(code.class('/'), Class::Punct);
+        assert_eq!(SyntaxTable::toml().class('#'), Class::Comment);
+        assert_eq!(SyntaxTable::for_extension(None), text);
+    }
+
+    #[test]
+    fn test_scanner() {
+        let rust = SyntaxTable::rust();
+        let text = "f(\"a\\\")\", b) // (x\ny";
+        let contexts: std::string::String = Scanner::new(&rust, text.chars(), 0)
+            .map(|(_, _, context)| match context {
+                Context::Code => 'c',
+                Context::String(_) => 's',
+                Context::Comment => '/',
+            })
+            .collect();
+        assert_eq!(contexts, "ccssssssccccc/////cc");
+        let strings: Vec<Context> = Scanner::new(&rust, text.chars(), 10)
+            .map(|(_, _, context)| context)
+            .filter(|context| matches!(context, Context::String(_)))
+            .collect();
+        assert_eq!(strings, [Context::String(12); 6]);
+
+        // A lone "/" is no comment in Rust, "#" is one in shell scripts.
+        let contexts: Vec<Context> = Scanner::new(&rust, "a / b".chars(), 0)
+            .map(|(_, _, context)| context)
+            .collect();
+        assert!(contexts.iter().all(|context| *context == Context::Code));
+        let shell = SyntaxTable::shell();
+        let last = Scanner::new(&shell, "echo '#' # x".chars(), 0).last();
+        assert_eq!(last, Some((11, 'x', Context::Comment)));
+        let quoted = Scanner::new(&shell, "echo '#' # x".chars(), 0).nth(6);
+        assert_eq!(quoted, Some((6, '#', Context::String(5))));
+    }
+}
@@ -0,0 +1,13 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+/// An empty directory for one test, named after it and the process so parallel tests
+/// and test runs don't share files. Anything left from an earlier run is removed.
+pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mr_text-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,598 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// What a piece of the screen is, as far as colouring goes. The highlighter tags text
+/// with faces and the theme decides how each face looks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Face {
+    Keyword,
+    Type,
+    Function,
+    String,
+    Comment,
+    Number,
+    Constant,
+    Attribute,
+    Variable,
+    /// Keys of TOML tables and JSON objects.
+    Key,
+    Heading,
+    Emphasis,
+    /// Markdown code spans and blocks.
+    Code,
+    Link,
+    Region,
+    ModeLine,
+    ModeLineInactive,
+    LineNumber,
+    LineNumberCurrent,
+    /// A bracket next to point and the one matching it.
+    ParenMatch,
+    /// A bracket next to point that has no match.
+    ParenMismatch,
+    /// Gutter signs for lines with errors and warnings.
+    SignError,
+    SignWarning,
+    /// Gutter signs for lines that differ from the file on disk or in git.
+    DiffAdded,
+    DiffChanged,
+    DiffDeleted,
+    Bookmark,
+    /// The glyphs of whitespace mode.
+    Whitespace,
+    TrailingWhitespace,
+}
+
+impl Face {
+    pub const ALL: [Face; 29] = [
+        Face::Keyword,
+        Face::Type,
+        Face::Function,
+        Face::String,
+        Face::Comment,
+        Face::Number,
+        Face::Constant,
+        Face::Attribute,
+        Face::Variable,
+        Face::Key,
+        Face::Heading,
+        Face::Emphasis,
+        Face::Code,
+        Face::Link,
+        Face::Region,
+        Face::ModeLine,
+        Face::ModeLineInactive,
+        Face::LineNumber,
+        Face::LineNumberCurrent,
+        Face::ParenMatch,
+        Face::ParenMismatch,
+        Face::SignError,
+        Face::SignWarning,
+        Face::DiffAdded,
+        Face::DiffChanged,
+        Face::DiffDeleted,
+        Face::Bookmark,
+        Face::Whitespace,
+        Face::TrailingWhitespace,
+    ];
+
+    /// The name of the face in theme files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Face::Keyword => "keyword",
+            Face::Type => "type",
+            Face::Function => "function",
+            Face::String => "string",
+            Face::Comment => "comment",
+            Face::Number => "number",
+            Face::Constant => "constant",
+            Face::Attribute => "attribute",
+            Face::Variable => "variable",
+            Face::Key => "key",
+            Face::Heading => "heading",
+            Face::Emphasis => "emphasis",
+            Face::Code => "code",
+            Face::Link => "link",
+            Face::Region => "region",
+            Face::ModeLine => "mode-line",
+            Face::ModeLineInactive => "mode-line-inactive",
+            Face::LineNumber => "line-number",
+            Face::LineNumberCurrent => "line-number-current",
+            Face::ParenMatch => "paren-match",
+            Face::ParenMismatch => "paren-mismatch",
+            Face::SignError => "sign-error",
+            Face::SignWarning => "sign-warning",
+            Face::DiffAdded => "diff-added",
+            Face::DiffChanged => "diff-changed",
+            Face::DiffDeleted => "diff-deleted",
+            Face::Bookmark => "bookmark",
+            Face::Whitespace => "whitespace",
+            Face::TrailingWhitespace => "trailing-whitespace",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Face> {
+        Face::ALL.into_iter().find(|face| face.name() == name)
+    }
+}
+
+/// The 16 basic colours as xterm shows them, for matching other colours to them.
+const ANSI_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+const ANSI_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// The channel values of the 6x6x6 colour cube in the 256 colour palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// One of the 16 basic colours, which the terminal user may have redefined.
+    Ansi(u8),
+    /// An entry of the 256 colour palette.
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// The colour as red, green and blue, taking the basic colours as xterm has them.
+    pub fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            Color::Ansi(idx) => ANSI_RGB[usize::from(idx & 0xf)],
+            Color::Indexed(idx @ 0..=15) => ANSI_RGB[usize::from(idx)],
+            Color::Indexed(idx @ 16..=231) => {
+                let idx = usize::from(idx - 16);
+                (
+                    CUBE_LEVELS[idx / 36],
+                    CUBE_LEVELS[idx / 6 % 6],
+                    CUBE_LEVELS[idx % 6],
+                )
+            }
+            Color::Indexed(idx) => {
+                let level = 8 + 10 * (idx - 232);
+                (level, level, level)
+            }
+            Color::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+
+    /// The closest colour the terminal can show.
+    pub fn fit(self, depth: ColorDepth) -> Color {
+        match (self, depth) {
+            (_, ColorDepth::TrueColor) | (Color::Ansi(_), _) => self,
+            (Color::Indexed(idx @ 0..=15), _) => Color::Ansi(idx),
+            (Color::Indexed(_), ColorDepth::Indexed256) => self,
+            (Color::Rgb(..), ColorDepth::Indexed256) => {
+                let nearest = (16..=255)
+                    .min_by_key(|idx| distance(Color::Indexed(*idx).rgb(), self.rgb()))
+                    .unwrap_or(16);
+                Color::Indexed(nearest)
+            }
+            (_, ColorDepth::Ansi16) => {
+                let nearest = (0..16)
+                    .min_by_key(|idx| distance(ANSI_RGB[usize::from(*idx)], self.rgb()))
+                    .unwrap_or(0);
+                Color::Ansi(nearest)
+            }
+        }
+    }
+
+    /// The SGR parameters setting this colour, as foreground or background.
+    fn sgr(self, background: bool) -> std::string::String {
+        let base = if background { 40 } else { 30 };
+        match self {
+            Color::Ansi(idx @ 0..=7) => (base + u32::from(idx)).to_string(),
+            Color::Ansi(idx) => (base + 60 + u32::from(idx & 0x7)).to_string(),
+            Color::Indexed(idx) => format!("{};5;{}", base + 8, idx),
+            Color::Rgb(r, g, b) => format!("{};2;{};{};{}", base + 8, r, g, b),
+        }
+    }
+}
+
+impl std::str::FromStr for Color {
+    type Err = std::string::String;
+
+    /// "#rrggbb", a palette index from 0 to 255, or a basic colour name like "red" or
+    /// "bright-blue".
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("Unknown color '{}'", text);
+        if let Some(hex) = text.strip_prefix('#') {
+            // Checked byte by byte so slicing below can't split a multi-byte char.
+            if hex.len() != 6 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return Err(bad());
+            }
+            let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).map_err(|_| bad());
+            return Ok(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+        }
+        if let Ok(idx) = text.parse::<u8>() {
+            return Ok(match idx {
+                0..=15 => Color::Ansi(idx),
+                _ => Color::Indexed(idx),
+            });
+        }
+        let (bright, name) = match text.strip_prefix("bright-") {
+            Some(name) => (8, name),
+            None => (0, text),
+        };
+        match name {
+            "gray" | "grey" if bright == 0 => Ok(Color::Ansi(8)),
+            _ => ANSI_NAMES
+                .iter()
+                .position(|known| *known == name)
+                .map(|idx| Color::Ansi(idx as u8 + bright))
+                .ok_or_else(bad),
+        }
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let channel = |x: u8, y: u8| u32::from(x.abs_diff(y)).pow(2);
+    channel(a.0, b.0) + channel(a.1, b.1) + channel(a.2, b.2)
+}
+
+/// How many colours the terminal shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorDepth {
+    #[default]
+    Ansi16,
+    Indexed256,
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// Reads the terminal's abilities from COLORTERM and TERM.
+    pub fn detect() -> ColorDepth {
+        Self::from_env(
+            std::env::var("COLORTERM").ok().as_deref(),
+            std::env::var("TERM").ok().as_deref(),
+        )
+    }
+
+    /// COLORTERM=truecolor or 24bit means any colour; a TERM like xterm-256color
+    /// means the 256 colour palette.
+    pub fn from_env(colorterm: Option<&str>, term: Option<&str>) -> ColorDepth {
+        match (colorterm, term) {
+            (Some("truecolor" | "24bit"), _) => ColorDepth::TrueColor,
+            (_, Some(term)) if term.contains("256color") => ColorDepth::Indexed256,
+            _ => ColorDepth::Ansi16,
+        }
+    }
+}
+
+/// How a face is drawn. None colours are left as the terminal has them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    pub fn fg(color: Color) -> Style {
+        Style {
+            fg: Some(color),
+            ..Style::default()
+        }
+    }
+
+    /// The SGR parameters for this style with colours fitted to depth, like
+    /// "1;38;5;208". Empty for the plain style.
+    pub fn sgr(&self, depth: ColorDepth) -> std::string::String {
+        let attributes = [
+            (self.bold, "1"),
+            (self.italic, "3"),
+            (self.underline, "4"),
+            (self.reverse, "7"),
+        ];
+        let mut params: Vec<std::string::String> = attributes
+            .into_iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, param)| param.to_string())
+            .collect();
+        if let Some(fg) = self.fg {
+            params.push(fg.fit(depth).sgr(false));
+        }
+        if let Some(bg) = self.bg {
+            params.push(bg.fit(depth).sgr(true));
+        }
+        params.join(";")
+    }
+}
+
+/// How each face is drawn, and how many colours there are to draw with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    faces: HashMap<Face, Style>,
+    depth: ColorDepth,
+}
+
+impl Default for Theme {
+    /// Colours from the basic 16, which every terminal has.
+    fn default() -> Self {
+        let attr = |bold, italic, underline, reverse| Style {
+            bold,
+            italic,
+            underline,
+            reverse,
+            ..Style::default()
+        };
+        let faces = [
+            (Face::Keyword, Style::fg(Color::Ansi(5))),
+            (Face::Type, Style::fg(Color::Ansi(2))),
+            (Face::Function, Style::fg(Color::Ansi(4))),
+            (Face::String, Style::fg(Color::Ansi(3))),
+            (Face::Comment, Style::fg(Color::Ansi(8))),
+            (Face::Number, Style::fg(Color::Ansi(6))),
+            (Face::Constant, Style::fg(Color::Ansi(6))),
+            (Face::Attribute, Style::fg(Color::Ansi(8))),
+            (Face::Variable, Style::fg(Color::Ansi(1))),
+            (Face::Key, Style::fg(Color::Ansi(4))),
+            (
+                Face::Heading,
+                Style {
+                    bold: true,
+                    ..Style::fg(Color::Ansi(4))
+                },
+            ),
+            (Face::Emphasis, attr(false, true, false, false)),
+            (Face::Code, Style::fg(Color::Ansi(2))),
+            (
+                Face::Link,
+                Style {
+                    underline: true,
+                    ..Style::fg(Color::Ansi(4))
+                },
+            ),
+            (Face::Region, attr(false, false, false, true)),
+            (Face::ModeLine, attr(false, false, false, true)),
+            (Face::ModeLineInactive, Style::fg(Color::Ansi(8))),
+            (Face::LineNumber, Style::fg(Color::Ansi(8))),
+            (Face::LineNumberCurrent, attr(true, false, false, false)),
+            (Face::ParenMatch, attr(false, false, false, true)),
+            (
+                Face::ParenMismatch,
+                Style {
+                    bg: Some(Color::Ansi(1)),
+                    ..Style::default()
+                },
+            ),
+            (
+                Face::SignError,
+                Style {
+                    bold: true,
+                    ..Style::fg(Color::Ansi(1))
+                },
+            ),
+            (Face::SignWarning, Style::fg(Color::Ansi(3))),
+            (Face::DiffAdded, Style::fg(Color::Ansi(2))),
+            (Face::DiffChanged, Style::fg(Color::Ansi(4))),
+            (Face::DiffDeleted, Style::fg(Color::Ansi(1))),
+            (Face::Bookmark, Style::fg(Color::Ansi(6))),
+            (Face::Whitespace, Style::fg(Color::Ansi(8))),
+            (
+                Face::TrailingWhitespace,
+                Style {
+                    bg: Some(Color::Ansi(1)),
+                    ..Style::default()
+                },
+            ),
+        ];
+        Theme {
+            faces: faces.into_iter().collect(),
+            depth: ColorDepth::default(),
+        }
+    }
+}
+
+impl Theme {
+    pub fn style(&self, face: Face) -> Style {
+        self.faces.get(&face).copied().unwrap_or_default()
+    }
+
+    pub fn set_style(&mut self, face: Face, style: Style) {
+        self.faces.insert(face, style);
+    }
+
+    pub fn depth(&self) -> ColorDepth {
+        self.depth
+    }
+
+    pub fn set_depth(&mut self, depth: ColorDepth) {
+        self.depth = depth;
+    }
+
+    /// The SGR parameters for face, empty when the theme leaves it plain.
+    pub fn sgr(&self, face: Face) -> std::string::String {
+        self.style(face).sgr(self.depth)
+    }
+
+    /// text drawn in face, back to the plain style after it.
+    pub fn paint(&self, face: Face, text: &str) -> std::string::String {
+        match self.sgr(face) {
+            sgr if sgr.is_empty() => text.to_string(),
+            sgr => format!("\x1b[{}m{}\x1b[0m", sgr, text),
+        }
+    }
+
+    /// Reads a theme file on top of the default theme. The file is a list of sections
+    /// named after faces, each setting fg, bg, bold, italic, underline and reverse:
+    ///
+    /// ```text
+    /// # Comments start with "#" or ";".
+    /// [keyword]
+    /// fg = "#c678dd"
+    /// bold = true
+    /// ```
+    ///
+    /// A face with a section gets only what the section sets.
+    pub fn parse(text: &str) -> Result<Theme, std::string::String> {
+        let mut theme = Theme::default();
+        let mut face = None;
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let fail = |msg: std::string::String| format!("line {}: {}", idx + 1, msg);
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|rest| rest.strip_suffix(']'))
+            {
+                let found = Face::from_name(name.trim())
+                    .ok_or_else(|| fail(format!("Unknown face '{}'", name.trim())))?;
+                theme.set_style(found, Style::default());
+                face = Some(found);
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(fail(format!("Expected 'key = value', got '{}'", line)));
+            };
+            let Some(face) = face else {
+                return Err(fail("Setting outside of a [face] section".to_string()));
+            };
+            let (key, value) = (key.trim(), value.trim().trim_matches(['"', '\'']));
+            let mut style = theme.style(face);
+            let flag = || match value {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(fail(format!("Expected true or false, got '{}'", value))),
+            };
+            match key {
+                "fg" => style.fg = Some(value.parse().map_err(fail)?),
+                "bg" => style.bg = Some(value.parse().map_err(fail)?),
+                "bold" => style.bold = flag()?,
+                "italic" => style.italic = flag()?,
+                "underline" => style.underline = flag()?,
+                "reverse" => style.reverse = flag()?,
+                _ => return Err(fail(format!("Unknown setting '{}'", key))),
+            }
+            theme.set_style(face, style);
+        }
+        Ok(theme)
+    }
+
+    /// Reads the theme file at path. The colour depth stays as it was.
+    pub fn load(&mut self, path: &Path) -> std::io::Result<()> {
+        let text = std::fs::read_to_string(path)?;
+        let theme = Theme::parse(&text).map_err(std::io::Error::other)?;
+        self.faces = theme.faces;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors() {
+        assert_eq!("#ff8000".parse(), Ok(Color::Rgb(255, 128, 0)));
+        assert_eq!("bright-blue".parse(), Ok(Color::Ansi(12)));
+        assert_eq!("grey".parse(), Ok(Color::Ansi(8)));
+        assert_eq!("208".parse(), Ok(Color::Indexed(208)));
+        assert!("#ff80".parse::<Color>().is_err());
+        assert!("#aébcd".parse::<Color>().is_err());
+        assert!("#+1ff00".parse::<Color>().is_err());
+        assert!("purple".parse::<Color>().is_err());
+
+        let orange = Color::Rgb(255, 135, 0);
+        assert_eq!(orange.fit(ColorDepth::TrueColor), orange);
+        assert_eq!(orange.fit(ColorDepth::Indexed256), Color::Indexed(208));
+        assert_eq!(orange.fit(ColorDepth::Ansi16), Color::Ansi(3));
+        assert_eq!(Color::Indexed(196).fit(ColorDepth::Ansi16), Color::Ansi(9));
+        assert_eq!(Color::Indexed(244).rgb(), (128, 128, 128));
+    }
+
+    #[test]
+    fn test_style_sgr() {
+        let style = Style {
+            fg: Some(Color::Rgb(255, 135, 0)),
+            bg: Some(Color::Ansi(12)),
+            bold: true,
+            reverse: true,
+            ..Style::default()
+        };
+        assert_eq!(style.sgr(ColorDepth::TrueColor), "1;7;38;2;255;135;0;104");
+        assert_eq!(style.sgr(ColorDepth::Indexed256), "1;7;38;5;208;104");
+        assert_eq!(style.sgr(ColorDepth::Ansi16), "1;7;33;104");
+        assert_eq!(Style::default().sgr(ColorDepth::TrueColor), "");
+
+        assert_eq!(
+            ColorDepth::from_env(Some("truecolor"), Some("xterm")),
+            ColorDepth::TrueColor
+        );
+        assert_eq!(
+            ColorDepth::from_env(None, Some("xterm-256color")),
+            ColorDepth::Indexed256
+        );
+        assert_eq!(
+            ColorDepth::from_env(None, Some("linux")),
+            ColorDepth::Ansi16
+        );
+    }
+
+    #[test]
+    fn test_parse_theme() {
+        let text = "\
+# A theme.
+[keyword]
+fg = \"#c678dd\"
+bold = true
+
+; Only what a section sets is kept.
+[comment]
+italic = true
+";
+        let theme = Theme::parse(text).unwrap();
+        assert_eq!(
+            theme.style(Face::Keyword),
+            Style {
+                bold: true,
+                ..Style::fg(Color::Rgb(0xc6, 0x78, 0xdd))
+            }
+        );
+        assert_eq!(theme.sgr(Face::Comment), "3");
+        assert_eq!(theme.style(Face::Type), Theme::default().style(Face::Type));
+
+        assert_eq!(
+            Theme::parse("[nope]"),
+            Err("line 1: Unknown face 'nope'".to_string())
+        );
+        assert_eq!(
+            Theme::parse("fg = red"),
+            Err("line 1: Setting outside of a [face] section".to_string())
+        );
+        assert_eq!(
+            Theme::parse("[keyword]\nfg = \"#aébcd\"\n"),
+            Err("line 2: Unknown color '#aébcd'".to_string())
+        );
+        assert_eq!(
+            Theme::parse("[type]\nbold = yes"),
+            Err("line 2: Expected true or false, got 'yes'".to_string())
+        );
+    }
+}
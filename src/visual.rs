@@ -0,0 +1,437 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Columns between tab stops unless a buffer sets its own tab-width.
+pub const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// How a buffer shows lines wider than the text window. Neither mode touches the
+/// text: a wrapped line is still one line of the rope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineWrap {
+    /// Long lines continue on the next screen rows, broken after the last space that
+    /// fits.
+    #[default]
+    Soft,
+    /// Long lines are cut at the window edge and the view scrolls sideways to follow
+    /// point. "$" marks text hidden to either side.
+    Truncate,
+}
+
+impl std::fmt::Display for LineWrap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineWrap::Soft => write!(f, "Wrap"),
+            LineWrap::Truncate => write!(f, "Truncate"),
+        }
+    }
+}
+
+/// The part of a buffer line shown on one screen row, as char offsets into the line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VisualRow {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// One grapheme cluster of a line: where it starts, in chars and in screen columns,
+/// how many chars it has and how many columns it takes up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell<'a> {
+    pub start: usize,
+    pub col: usize,
+    pub chars: usize,
+    pub width: usize,
+    pub text: &'a str,
+}
+
+impl<'a> Cell<'a> {
+    /// What is written to the terminal for the cell: a tab becomes the spaces up to
+    /// the next tab stop.
+    pub fn shown(&self) -> std::borrow::Cow<'a, str> {
+        match self.text {
+            "\t" => std::borrow::Cow::Owned(" ".repeat(self.width)),
+            text => std::borrow::Cow::Borrowed(text),
+        }
+    }
+}
+
+/// The column of the first tab stop after col.
+pub fn next_tab_stop(col: usize, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    (col / tab_width + 1) * tab_width
+}
+
+/// Terminal columns a grapheme cluster takes up: two for East Asian wide and
+/// fullwidth characters and most emoji, none for zero width ones. Control characters
+/// are shown as one replacement character.
+pub fn width(grapheme: &str) -> usize {
+    match grapheme.chars().next() {
+        Some(ch) if ch.is_control() => 1,
+        _ => UnicodeWidthStr::width(grapheme),
+    }
+}
+
+/// The grapheme clusters of line, in order. A tab reaches to the next tab stop, so
+/// its width depends on where it starts.
+pub fn cells(line: &str, tab_width: usize) -> impl Iterator<Item = Cell<'_>> {
+    let (mut start, mut col) = (0, 0);
+    line.graphemes(true).map(move |text| {
+        let chars = text.chars().count();
+        let width = match text {
+            "\t" => next_tab_stop(col, tab_width) - col,
+            text => width(text),
+        };
+        let cell = Cell {
+            start,
+            col,
+            chars,
+            width,
+            text,
+        };
+        start += chars;
+        col += width;
+        cell
+    })
+}
+
+/// The screen column char offset chars of line is shown at. An offset inside a
+/// cluster is shown where the cluster starts.
+pub fn display_col(line: &str, chars: usize, tab_width: usize) -> usize {
+    cells(line, tab_width)
+        .take_while(|cell| cell.start + cell.chars <= chars)
+        .map(|cell| cell.width)
+        .sum()
+}
+
+/// The char offset of the cluster shown at screen column col of line, the inverse of
+/// display_col. A column past the end of the line gives the end of the line, and the
+/// second column of a wide character or any column a tab covers gives its start.
+pub fn char_at_col(line: &str, col: usize, tab_width: usize) -> usize {
+    let mut end = 0;
+    for cell in cells(line, tab_width) {
+        if cell.col + cell.width > col {
+            return cell.start;
+        }
+        end = cell.start + cell.chars;
+    }
+    end
+}
+
+/// Splits line into rows no wider than width. A row ends after the last whitespace
+/// that fits; a word longer than the whole row is broken where the row ends, but
+/// never inside a grapheme cluster. A line that fills its last row exactly gets an
+/// empty row after it, so point at the end of the line still has a place on screen.
+pub fn wrap(line: &str, width: usize, tab_width: usize) -> Vec<VisualRow> {
+    let width = width.max(1);
+    let cells: Vec<Cell> = cells(line, tab_width).collect();
+    let len = line.chars().count();
+    let char_of = |idx: usize| cells.get(idx).map_or(len, |cell| cell.start);
+    let mut rows = Vec::new();
+    let (mut first, mut col, mut last_break) = (0, 0, 0);
+    for (idx, cell) in cells.iter().enumerate() {
+        if col + cell.width > width && idx > first {
+            let end = if last_break > first { last_break } else { idx };
+            rows.push(VisualRow {
+                start: char_of(first),
+                end: char_of(end),
+            });
+            first = end;
+            col = cells[first..idx].iter().map(|cell| cell.width).sum();
+        }
+        col += cell.width;
+        if cell.text.chars().all(char::is_whitespace) {
+            last_break = idx + 1;
+        }
+    }
+    rows.push(VisualRow {
+        start: char_of(first),
+        end: len,
+    });
+    if col >= width {
+        rows.push(VisualRow {
+            start: len,
+            end: len,
+        });
+    }
+    rows
+}
+
+/// The row of rows that char offset col of line is on, and its screen column there.
+pub fn cursor(line: &str, rows: &[VisualRow], col: usize, tab_width: usize) -> (usize, usize) {
+    let row = rows
+        .iter()
+        .rposition(|row| row.start <= col)
+        .unwrap_or_default();
+    let start = rows.get(row).map_or(0, |row| row.start);
+    (
+        row,
+        display_col(line, col, tab_width) - display_col(line, start, tab_width),
+    )
+}
+
+/// A run of chars of a line drawn in another style, as char offsets into the line.
+/// sgr holds the SGR parameters to set, like "7" for reverse video. A glyph is shown
+/// in place of each cell of the span, padded with spaces to the cell's width.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub sgr: std::string::String,
+    pub glyph: Option<char>,
+}
+
+/// Writes cells with the escape sequences that switch between span styles. When spans
+/// overlap the last one wins.
+struct Painter<'a> {
+    spans: &'a [Span],
+    active: Option<usize>,
+}
+
+impl<'a> Painter<'a> {
+    fn new(spans: &'a [Span]) -> Self {
+        Painter {
+            spans,
+            active: None,
+        }
+    }
+
+    fn push(&mut self, text: &mut std::string::String, cell: &Cell) {
+        let span = self
+            .spans
+            .iter()
+            .rposition(|span| span.start <= cell.start && cell.start < span.end);
+        if span != self.active {
+            self.finish(text);
+            if let Some(idx) = span.filter(|idx| !self.spans[*idx].sgr.is_empty()) {
+                text.push_str(&format!("\x1b[{}m", self.spans[idx].sgr));
+            }
+            self.active = span;
+        }
+        match span.and_then(|idx| self.spans[idx].glyph) {
+            Some(glyph) => {
+                text.push(glyph);
+                text.push_str(&" ".repeat(cell.width.saturating_sub(1)));
+            }
+            None => text.push_str(&cell.shown()),
+        }
+    }
+
+    /// Back to the plain style.
+    fn finish(&mut self, text: &mut std::string::String) {
+        if self.active.take().is_some() {
+            text.push_str("\x1b[0m");
+        }
+    }
+}
+
+/// The text of one wrapped row as written to the terminal.
+pub fn row_text(
+    line: &str,
+    row: VisualRow,
+    tab_width: usize,
+    spans: &[Span],
+) -> std::string::String {
+    let mut text = std::string::String::new();
+    let mut painter = Painter::new(spans);
+    cells(line, tab_width)
+        .skip_while(|cell| cell.start < row.start)
+        .take_while(|cell| cell.start < row.end)
+        .for_each(|cell| painter.push(&mut text, &cell));
+    painter.finish(&mut text);
+    text
+}
+
+/// The columns hscroll..hscroll + width of line. When the view is scrolled the first
+/// column shows "$", and so does the last when the line goes on past the window. A
+/// wide character cut in half by the window edge is left out.
+pub fn truncate(
+    line: &str,
+    hscroll: usize,
+    width: usize,
+    tab_width: usize,
+    spans: &[Span],
+) -> std::string::String {
+    let mut text = std::string::String::new();
+    let mut painter = Painter::new(spans);
+    if width == 0 {
+        return text;
+    }
+    let mut col = 0;
+    let mut shown = 0;
+    let mut cells = cells(line, tab_width).peekable();
+    if hscroll > 0 {
+        // Skip up to and including the column the left "$" covers.
+        while let Some(cell) = cells.peek() {
+            if col > hscroll {
+                break;
+            }
+            col += cell.width;
+            cells.next();
+        }
+        if col == 0 {
+            return text;
+        }
+        text.push('$');
+        shown = 1;
+        // A wide char or tab cut by the marker leaves blank columns behind it.
+        for _ in hscroll + 1..col {
+            text.push(' ');
+            shown += 1;
+        }
+    }
+    while let Some(cell) = cells.next() {
+        let more = cells.peek().is_some();
+        if shown + cell.width > width || (more && shown + cell.width == width) {
+            painter.finish(&mut text);
+            text.push('$');
+            break;
+        }
+        painter.push(&mut text, &cell);
+        shown += cell.width;
+    }
+    painter.finish(&mut text);
+    text
+}
+
+/// The horizontal scroll that keeps column col in view, away from the "$" markers.
+/// The view stays put while col is visible and only scrolls when it has to, back to
+/// the left edge when that is enough or by half a window otherwise.
+pub fn hscroll_for(hscroll: usize, col: usize, width: usize) -> usize {
+    let left = hscroll + usize::from(hscroll > 0);
+    let right = (hscroll + width).saturating_sub(1);
+    if col >= left && col < right {
+        hscroll
+    } else if col + 1 < width {
+        0
+    } else {
+        col.saturating_sub(width / 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(line: &str, width: usize) -> Vec<std::string::String> {
+        wrap(line, width, 8)
+            .into_iter()
+            .map(|row| row_text(line, row, 8, &[]))
+            .collect()
+    }
+
+    #[test]
+    fn test_wrap_at_words() {
+        assert_eq!(texts("", 10), [""]);
+        assert_eq!(texts("short", 10), ["short"]);
+        assert_eq!(
+            texts("the quick brown fox jumps", 10),
+            ["the quick ", "brown fox ", "jumps"]
+        );
+        assert_eq!(texts("abcdefghijklmno", 6), ["abcdef", "ghijkl", "mno"]);
+        assert_eq!(texts("ab cdefghijkl", 6), ["ab ", "cdefgh", "ijkl"]);
+        // A full last row gets an empty one after it for the cursor.
+        assert_eq!(texts("abcdef", 6), ["abcdef", ""]);
+
+        let line = "the quick brown fox";
+        let rows = wrap(line, 10, 8);
+        assert_eq!(cursor(line, &rows, 0, 8), (0, 0));
+        assert_eq!(cursor(line, &rows, 12, 8), (1, 2));
+        assert_eq!(cursor(line, &rows, 19, 8), (1, 9));
+    }
+
+    #[test]
+    fn test_truncate() {
+        let line = "0123456789abcdefghij";
+        assert_eq!(truncate(line, 0, 30, 8, &[]), line);
+        assert_eq!(truncate(line, 0, 8, 8, &[]), "0123456$");
+        assert_eq!(truncate(line, 5, 8, 8, &[]), "$6789ab$");
+        assert_eq!(truncate(line, 14, 8, 8, &[]), "$fghij");
+        assert_eq!(truncate(line, 40, 8, 8, &[]), "$");
+        assert_eq!(truncate("01234567", 0, 8, 8, &[]), "01234567");
+
+        assert_eq!(hscroll_for(0, 3, 8), 0);
+        assert_eq!(hscroll_for(0, 7, 8), 3);
+        assert_eq!(hscroll_for(3, 9, 8), 3);
+        assert_eq!(hscroll_for(3, 3, 8), 0);
+        assert_eq!(hscroll_for(10, 4, 8), 0);
+        assert_eq!(hscroll_for(10, 9, 8), 5);
+    }
+
+    #[test]
+    fn test_wide_and_combined() {
+        // "日本" is two wide chars, "e\u{301}" one cluster of two chars, then a family
+        // emoji of five chars that is two columns wide.
+        let line = "日本e\u{301}x\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}!";
+        let widths: Vec<usize> = cells(line, 8).map(|cell| cell.width).collect();
+        assert_eq!(widths, [2, 2, 1, 1, 2, 1]);
+        assert_eq!(display_col(line, 2, 8), 4);
+        assert_eq!(display_col(line, 4, 8), 5);
+        assert_eq!(display_col(line, 3, 8), 4);
+        assert_eq!(display_col(line, 10, 8), 8);
+        assert_eq!(char_at_col(line, 1, 8), 0);
+        assert_eq!(char_at_col(line, 2, 8), 1);
+        assert_eq!(char_at_col(line, 5, 8), 4);
+        assert_eq!(char_at_col(line, 7, 8), 5);
+        assert_eq!(char_at_col(line, 40, 8), 11);
+        for chars in [0, 1, 2, 4, 5, 10, 11] {
+            assert_eq!(char_at_col(line, display_col(line, chars, 8), 8), chars);
+        }
+
+        assert_eq!(texts("日本語の文", 5), ["日本", "語の", "文"]);
+        let rows = wrap(line, 5, 8);
+        assert_eq!(cursor(line, &rows, 5, 8), (1, 1));
+        assert_eq!(truncate("日本語", 0, 5, 8, &[]), "日本$");
+        assert_eq!(truncate("日本語", 2, 5, 8, &[]), "$ 語");
+        assert_eq!(truncate("a日本語", 1, 5, 8, &[]), "$ 本$");
+    }
+
+    #[test]
+    fn test_spans() {
+        let spans = [
+            Span {
+                start: 1,
+                end: 3,
+                sgr: "7".to_string(),
+                glyph: None,
+            },
+            Span {
+                start: 2,
+                end: 4,
+                sgr: "1".to_string(),
+                glyph: None,
+            },
+        ];
+        let rows = wrap("abcdef", 4, 8);
+        assert_eq!(
+            row_text("abcdef", rows[0], 8, &spans),
+            "a\x1b[7mb\x1b[0m\x1b[1mcd\x1b[0m"
+        );
+        assert_eq!(row_text("abcdef", rows[1], 8, &spans), "ef");
+        assert_eq!(
+            truncate("abcdef", 0, 4, 8, &spans),
+            "a\x1b[7mb\x1b[0m\x1b[1mc\x1b[0m$"
+        );
+    }
+
+    #[test]
+    fn test_tabs() {
+        let line = "\tab\tc\t";
+        let widths: Vec<usize> = cells(line, 4).map(|cell| cell.width).collect();
+        assert_eq!(widths, [4, 1, 1, 2, 1, 3]);
+        assert_eq!(display_col(line, 1, 4), 4);
+        assert_eq!(display_col(line, 4, 4), 8);
+        assert_eq!(display_col(line, 4, 8), 16);
+        assert_eq!(char_at_col(line, 2, 4), 0);
+        assert_eq!(char_at_col(line, 7, 4), 3);
+        assert_eq!(char_at_col(line, 8, 4), 4);
+        assert_eq!(next_tab_stop(5, 4), 8);
+        assert_eq!(next_tab_stop(8, 4), 12);
+
+        assert_eq!(texts("a\tb", 8), ["a       ", "b"]);
+        assert_eq!(truncate(line, 0, 12, 4, &[]), "    ab  c   ");
+        assert_eq!(truncate(line, 2, 8, 4, &[]), "$ ab  c$");
+    }
+}
@@ -0,0 +1,144 @@
+#![allow(unused_imports, unused_variables)]
+#![allow(dead_code)]
+
+use crate::visual::{self, Span};
+
+/// What whitespace mode shows in place of whitespace. Every glyph takes one column; a
+/// tab shows its glyph followed by spaces up to the tab stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Glyphs {
+    pub tab: char,
+    /// Trailing spaces.
+    pub space: char,
+    pub nbsp: char,
+    /// After the last char of lines that end with a line break.
+    pub eol: char,
+}
+
+impl Default for Glyphs {
+    fn default() -> Self {
+        Glyphs {
+            tab: '»',
+            space: '·',
+            nbsp: '¤',
+            eol: '¬',
+        }
+    }
+}
+
+/// Glyphs as "tab=>,space=.,nbsp=_,eol=$", as in MR_TEXT_WHITESPACE_GLYPHS. Glyphs
+/// left out keep their defaults.
+impl std::str::FromStr for Glyphs {
+    type Err = std::string::String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut glyphs = Glyphs::default();
+        for setting in s.split(',').filter(|setting| !setting.trim().is_empty()) {
+            let Some((name, glyph)) = setting.split_once('=') else {
+                return Err(format!("Expected name=glyph: {}", setting));
+            };
+            let mut chars = glyph.chars();
+            let glyph = match (chars.next(), chars.next()) {
+                (Some(ch), None) if visual::width(glyph) == 1 => ch,
+                _ => return Err(format!("Not a one-column glyph: {}", glyph)),
+            };
+            match name.trim() {
+                "tab" => glyphs.tab = glyph,
+                "space" => glyphs.space = glyph,
+                "nbsp" => glyphs.nbsp = glyph,
+                "eol" => glyphs.eol = glyph,
+                name => return Err(format!("Unknown whitespace glyph: {}", name)),
+            }
+        }
+        Ok(glyphs)
+    }
+}
+
+/// Whitespace that trailing-whitespace cleanup removes: everything but line breaks.
+pub fn is_blank(ch: char) -> bool {
+    ch.is_whitespace() && !matches!(ch, '\n' | '\r')
+}
+
+fn is_nbsp(ch: char) -> bool {
+    matches!(ch, '\u{a0}' | '\u{2007}' | '\u{202f}')
+}
+
+/// The char offset where the trailing whitespace of line starts, line.chars().count()
+/// when there is none. line doesn't include its line break.
+pub fn trailing_start(line: &str) -> usize {
+    let len = line.chars().count();
+    len - line.chars().rev().take_while(|ch| is_blank(*ch)).count()
+}
+
+/// Spans that show the whitespace of line with glyphs: tabs and non-breaking spaces
+/// everywhere, any whitespace at the end of the line. Trailing whitespace is styled
+/// with trailing_sgr and the rest with sgr.
+pub fn spans(line: &str, glyphs: &Glyphs, sgr: &str, trailing_sgr: &str) -> Vec<Span> {
+    let trailing = trailing_start(line);
+    let mut spans: Vec<Span> = Vec::new();
+    for (idx, ch) in line.chars().enumerate() {
+        let glyph = match ch {
+            '\t' => glyphs.tab,
+            ch if is_nbsp(ch) => glyphs.nbsp,
+            _ if idx >= trailing => glyphs.space,
+            _ => continue,
+        };
+        let sgr = match idx >= trailing {
+            true => trailing_sgr,
+            false => sgr,
+        };
+        // Runs of the same glyph share a span so the style isn't set for every cell.
+        match spans.last_mut() {
+            Some(last) if last.end == idx && last.glyph == Some(glyph) && last.sgr == sgr => {
+                last.end += 1;
+            }
+            _ => spans.push(Span {
+                start: idx,
+                end: idx + 1,
+                sgr: sgr.to_string(),
+                glyph: Some(glyph),
+            }),
+        }
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glyphs() {
+        assert_eq!("".parse::<Glyphs>(), Ok(Glyphs::default()));
+        let glyphs: Glyphs = "tab=>, eol=$".parse().unwrap();
+        assert_eq!((glyphs.tab, glyphs.eol, glyphs.space), ('>', '$', '·'));
+        assert!("tab=>>".parse::<Glyphs>().is_err());
+        assert!("tab=日".parse::<Glyphs>().is_err());
+        assert!("bell=!".parse::<Glyphs>().is_err());
+        assert!("tab".parse::<Glyphs>().is_err());
+    }
+
+    #[test]
+    fn test_whitespace_spans() {
+        assert_eq!(trailing_start("a b \t "), 3);
+        assert_eq!(trailing_start("   "), 0);
+        assert_eq!(trailing_start("ab"), 2);
+
+        let glyphs = Glyphs::default();
+        let shown: Vec<(usize, usize, Option<char>)> = spans("\tx y\u{a0}z  ", &glyphs, "2", "41")
+            .iter()
+            .map(|span| (span.start, span.end, span.glyph))
+            .collect();
+        assert_eq!(
+            shown,
+            [(0, 1, Some('»')), (4, 5, Some('¤')), (6, 8, Some('·'))]
+        );
+        let spans = spans("\tx  ", &glyphs, "2", "41");
+        assert_eq!(spans[0].sgr, "2");
+        assert_eq!(spans[1].sgr, "41");
+
+        // Rendered, a tab keeps its width and shows the glyph in its first column.
+        let text = visual::row_text("\tx  ", visual::VisualRow { start: 0, end: 4 }, 4, &spans);
+        assert_eq!(text, "\x1b[2m»   \x1b[0mx\x1b[41m··\x1b[0m");
+    }
+}